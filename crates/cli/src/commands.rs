@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use stowage_proto::dial::DialString;
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -19,11 +20,91 @@ pub(crate) struct ServerCommand {
     #[clap(subcommand)]
     pub command: ServerCommands,
 
-    #[arg(default_value = "0.0.0.0:3000", long, short)]
-    pub addr: std::net::SocketAddr,
+    /// the dial string to listen on: `tcp!host!port`, `tls!host!port`, `unix!path`, or `-` to
+    /// serve a single connection over stdin and stdout
+    #[arg(default_value = "tcp!0.0.0.0!3000", long, short)]
+    pub addr: DialString,
 
+    /// the permissions of a unix socket, in octal
+    #[arg(default_value = "600", long, value_parser = parse_mode)]
+    pub socket_mode: u32,
+
+    /// require clients to prove they know the secret in this file before attaching
+    #[arg(long)]
+    pub secret_file: Option<PathBuf>,
+
+    /// what to make of the user a client attaches as, given the credentials of a unix socket or
+    /// a TLS client certificate
+    #[arg(default_value = "trust", long, value_enum)]
+    pub peer_credentials: PeerCredentials,
+
+    /// the certificate chain to serve a `tls!` address with, in PEM
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// the private key of the TLS certificate, in PEM
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// require TLS clients to present a certificate issued by a CA in this file, whose common
+    /// name is the user they are
+    #[arg(long, requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// the directory served by the disk backend
     #[arg(default_value = "data", long, short)]
     pub path: PathBuf,
+
+    #[arg(default_value = "disk", long, short, value_enum)]
+    pub backend: Backend,
+
+    /// check the permissions of attached users against the accounts they map to, instead of
+    /// granting everyone what the server process may do
+    #[arg(long, value_enum)]
+    pub users: Option<UsersSource>,
+
+    /// the users file read by `--users passwd`
+    #[arg(default_value = "/etc/passwd", long)]
+    pub passwd_file: PathBuf,
+
+    /// the groups file read by `--users passwd`
+    #[arg(default_value = "/etc/group", long)]
+    pub group_file: PathBuf,
+
+    /// the users file read by `--users adm`
+    #[arg(default_value = "/adm/users", long)]
+    pub adm_users_file: PathBuf,
+}
+
+/// Where the server keeps its files
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum Backend {
+    /// a directory of the host filesystem
+    Disk,
+    /// a tree kept in memory, lost when the server stops
+    Memory,
+}
+
+/// Where the disk backend looks up the users attaching to it
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum UsersSource {
+    /// the accounts of the host
+    Host,
+    /// files in the format of `/etc/passwd` and `/etc/group`
+    Passwd,
+    /// a file in the format of Plan 9's `/adm/users`
+    Adm,
+}
+
+/// How the kernel's word on who connected to a unix socket is used
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum PeerCredentials {
+    /// attach as the connecting user, whatever name the client gives
+    Trust,
+    /// refuse attaches as anyone but the connecting user
+    Verify,
+    /// believe the name the client gives
+    Ignore,
 }
 
 /// A command for running the API server
@@ -38,8 +119,35 @@ pub(crate) struct FileCommand {
     #[clap(subcommand)]
     pub command: FileCommands,
 
-    #[arg(default_value = "0.0.0.0:3000", long, short)]
-    pub addr: std::net::SocketAddr,
+    /// the dial string of the server: `tcp!host!port`, `tls!host!port` or `unix!path`
+    #[arg(default_value = "tcp!0.0.0.0!3000", long, short)]
+    pub addr: DialString,
+
+    /// speak 9P over the standard input and output of this shell command instead of dialing,
+    /// e.g. `ssh host stowage-cli server --addr - start`
+    #[arg(long, short, conflicts_with = "addr")]
+    pub import: Option<String>,
+
+    /// authenticate with the secret in this file, for servers started with one
+    #[arg(long)]
+    pub secret_file: Option<PathBuf>,
+
+    /// the user to attach as, by default the one running this command
+    #[arg(long, short)]
+    pub user: Option<String>,
+
+    /// trust `tls!` servers with a certificate issued by a CA in this file, such as the
+    /// server's own self-signed certificate
+    #[arg(long)]
+    pub tls_ca: Option<PathBuf>,
+
+    /// the certificate to present to TLS servers that ask for one, in PEM
+    #[arg(long, requires = "tls_key", requires = "tls_ca")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// the private key of the client certificate, in PEM
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -80,3 +188,10 @@ pub(crate) enum DebugCommands {
     /// Display the 9p messages contained in a binary file
     DumpMessages { path: PathBuf },
 }
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("{mode}: not an octal permission mode"))
+}
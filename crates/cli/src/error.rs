@@ -4,6 +4,8 @@ pub enum Error {
     StdIo(#[from] std::io::Error),
     #[error(transparent)]
     StowageProto(#[from] stowage_proto::error::Error),
+    #[error(transparent)]
+    Client(#[from] stowage_client::Error),
     #[error("Hello {0}")]
     Other(String),
 }
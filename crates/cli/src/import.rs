@@ -0,0 +1,119 @@
+use crate::error::{Error, Result};
+use std::{
+    io,
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+    time::Duration,
+};
+use stowage_client::Client;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    process::{Child, ChildStdin, ChildStdout, Command},
+};
+use tracing::{error, info};
+
+/// How long an imported command gets to exit after the connection is closed
+const REAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The standard input and output of a child as one connection
+///
+/// Shutting it down closes the child's standard input, which is how it learns that the session
+/// is over.
+struct ChildStdio {
+    stdout: ChildStdout,
+    stdin: Option<ChildStdin>,
+}
+
+impl ChildStdio {
+    fn stdin(&mut self) -> io::Result<&mut ChildStdin> {
+        self.stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl AsyncRead for ChildStdio {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ChildStdio {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(self.get_mut().stdin()?).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stdin {
+            Some(stdin) => Pin::new(stdin).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(stdin) = &mut this.stdin {
+            std::task::ready!(Pin::new(stdin).poll_flush(cx))?;
+        }
+        // a pipe is only closed once dropped
+        this.stdin = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Spawn `command` with `sh -c` and connect over its standard input and output
+///
+/// Its standard error is ours, so that ssh can still ask for passwords.
+pub(crate) async fn import(command: &str) -> Result<(Client, Child)> {
+    info!("importing through: {command}");
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // unwrap - both were piped above
+    let stdio = ChildStdio {
+        stdout: child.stdout.take().unwrap(),
+        stdin: child.stdin.take(),
+    };
+    match Client::new(stdio).await {
+        Ok(client) => Ok((client, child)),
+        Err(e) => {
+            // a command that failed says more than the broken connection
+            if let Ok(status) = tokio::time::timeout(REAP_TIMEOUT, child.wait()).await {
+                let status = status?;
+                if !status.success() {
+                    return Err(Error::Other(format!("{command}: exited with {status}")));
+                }
+            }
+            Err(e.into())
+        }
+    }
+}
+
+/// Wait for an imported command to exit once the connection is closed, killing it when it
+/// does not
+pub(crate) async fn reap(mut child: Child) -> Result<()> {
+    if let Ok(status) = tokio::time::timeout(REAP_TIMEOUT, child.wait()).await {
+        let status = status?;
+        if !status.success() {
+            error!("imported command exited with {status}");
+        }
+    } else {
+        error!("imported command did not exit, killing it");
+        child.kill().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use crate::requests::{self, Challenge};
+use crate::{mux::Mux, reply_error, Result};
+use stowage_proto::{Message, Tclunk};
+
+/// Open `afid` for `uname` and `aname` and answer its challenge with `secret`
+///
+/// The afid is clunked again when authenticating fails.
+pub(crate) async fn authenticate(
+    mux: &Mux,
+    afid: u32,
+    uname: &str,
+    aname: &str,
+    secret: &[u8],
+) -> Result<()> {
+    match mux.rpc(requests::tauth(afid, uname, aname)).await? {
+        Message::Rauth(_) => {}
+        reply => return Err(reply_error(reply)),
+    }
+
+    let answered = answer(mux, afid, uname, aname, secret).await;
+    if answered.is_err() {
+        mux.rpc(Message::Tclunk(Tclunk { fid: afid })).await?;
+    }
+    answered
+}
+
+async fn answer(mux: &Mux, afid: u32, uname: &str, aname: &str, secret: &[u8]) -> Result<()> {
+    let mut challenge = Challenge::new(afid, uname, aname, secret);
+    while let Some(request) = challenge.request() {
+        challenge.reply(mux.rpc(request).await?)?;
+    }
+
+    Ok(())
+}
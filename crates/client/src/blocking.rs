@@ -0,0 +1,794 @@
+//! A client for callers without a tokio runtime
+//!
+//! It speaks the same protocol as the async client over any `Read + Write` transport, one
+//! request at a time, and has the same operations. Opened files implement `std::io::Read`,
+//! `Write` and `Seek`.
+
+use crate::file::{components, split_parent};
+use crate::fs::FILE_PERM;
+use crate::remote::seek_offset;
+use crate::requests::{self, Challenge, Walk};
+use crate::{negotiated_msize, pool::IdPool, reply_error, Error, Metadata, Result, NOTAG};
+use bytes::{Bytes, BytesMut};
+use flagset::FlagSet;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use stowage_proto::{
+    consts::{P9_DEFAULT_MSIZE, P9_IOHDRSZ, P9_NOFID},
+    Dialect, FileMode, Message, MessageCodec, OpenMode, Qid, QidType, Stat, TaggedMessage, Tclunk,
+    Tcreate, Topen, Tread, Tremove, Tstat, Tversion, Twrite, Twstat,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// The tag of every request but Tversion, only one is outstanding at a time
+const TAG: u16 = 0;
+
+/// A blocking 9P2000 connection to a server
+///
+/// Requests are sent one after the other, callers sharing the client wait for each other.
+/// Cloning it is cheap, all clones share the connection.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    connection: Mutex<Connection>,
+    msize: u32,
+    fids: Mutex<IdPool>,
+}
+
+trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+/// The transport and what was read from it but not decoded yet
+struct Connection {
+    transport: Box<dyn Transport>,
+    codec: MessageCodec,
+    buffer: BytesMut,
+    /// a failed read or write left a partial message behind, nothing can follow it
+    broken: bool,
+}
+
+impl Connection {
+    /// Send `request` and wait for the reply with its tag
+    fn rpc(&mut self, request: TaggedMessage) -> Result<Message> {
+        if self.broken {
+            return Err(Error::ConnectionClosed);
+        }
+
+        let tag = request.tag;
+        let mut frame = BytesMut::new();
+        self.codec.encode(request, &mut frame)?;
+        let result = self.exchange(&frame, tag);
+        if matches!(result, Err(Error::Io(_) | Error::ConnectionClosed)) {
+            self.broken = true;
+        }
+        result
+    }
+
+    fn exchange(&mut self, frame: &[u8], tag: u16) -> Result<Message> {
+        self.transport.write_all(frame)?;
+        self.transport.flush()?;
+
+        let mut chunk = [0; P9_DEFAULT_MSIZE as usize];
+        loop {
+            while let Some(reply) = self.codec.decode(&mut self.buffer)? {
+                if reply.tag == tag {
+                    return Ok(reply.message);
+                }
+                tracing::debug!(tag = reply.tag, "reply to an unknown tag");
+            }
+
+            match self.transport.read(&mut chunk) {
+                Ok(0) => return Err(Error::ConnectionClosed),
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Connect to a server over TCP and negotiate the version
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - version negotiation fails
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Connect to a server listening on the unix socket at `path` and negotiate the version
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - version negotiation fails
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        Self::new(std::os::unix::net::UnixStream::connect(path)?)
+    }
+
+    /// Negotiate the version over an established `transport`
+    ///
+    /// # Errors
+    /// - the transport fails
+    /// - the server does not speak 9P2000
+    pub fn new<T>(transport: T) -> Result<Self>
+    where
+        T: Read + Write + Send + 'static,
+    {
+        let mut connection = Connection {
+            transport: Box::new(transport),
+            codec: MessageCodec::new(),
+            buffer: BytesMut::new(),
+            broken: false,
+        };
+
+        let tversion = Tversion {
+            msize: P9_DEFAULT_MSIZE,
+            version: Dialect::Base.as_str().to_string(),
+        };
+        let rversion =
+            match connection.rpc(TaggedMessage::new(NOTAG, Message::Tversion(tversion)))? {
+                Message::Rversion(rversion) => rversion,
+                reply => return Err(reply_error(reply)),
+            };
+        let msize = negotiated_msize(rversion)?;
+        connection.codec.set_msize(msize);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                connection: Mutex::new(connection),
+                msize,
+                fids: Mutex::new(IdPool::new(P9_NOFID)),
+            }),
+        })
+    }
+
+    /// The msize agreed on with the server
+    #[must_use]
+    pub fn msize(&self) -> u32 {
+        self.inner.msize
+    }
+
+    /// Attach to the file tree `aname` of the server as `uname`
+    ///
+    /// Servers that require authentication are attached to with `attach_with_secret`.
+    ///
+    /// # Errors
+    /// - the server requires authentication
+    /// - the server refuses the attach
+    pub fn attach(&self, uname: &str, aname: &str) -> Result<Dir> {
+        let afid = self.alloc_fid()?;
+        match requests::auth_required(self.rpc(requests::tauth(afid, uname, aname))) {
+            Ok(false) => self.release_fid(afid),
+            Ok(true) => {
+                self.clunk(afid)?;
+                return Err(Error::AuthRequired);
+            }
+            Err(e) => {
+                self.release_fid(afid);
+                return Err(e);
+            }
+        }
+
+        self.tattach(P9_NOFID, uname, aname)
+    }
+
+    /// Attach to the file tree `aname` of the server as `uname`, proving it with `secret`
+    ///
+    /// # Errors
+    /// - the server does not authenticate, or not this way
+    /// - `secret` is not the server's
+    /// - the server refuses the attach
+    pub fn attach_with_secret(&self, uname: &str, aname: &str, secret: &[u8]) -> Result<Dir> {
+        let afid = self.alloc_fid()?;
+        if let Err(e) = self.rpc(requests::tauth(afid, uname, aname)) {
+            self.release_fid(afid);
+            return Err(e);
+        }
+        let answered = self.answer(afid, uname, aname, secret);
+        let attached = answered.and_then(|()| self.tattach(afid, uname, aname));
+        self.clunk(afid)?;
+        attached
+    }
+
+    /// Answer the challenge read from `afid` with `secret`
+    fn answer(&self, afid: u32, uname: &str, aname: &str, secret: &[u8]) -> Result<()> {
+        let mut challenge = Challenge::new(afid, uname, aname, secret);
+        while let Some(request) = challenge.request() {
+            challenge.reply(self.rpc(request)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn tattach(&self, afid: u32, uname: &str, aname: &str) -> Result<Dir> {
+        let fid = self.alloc_fid()?;
+        match requests::rattach(self.rpc(requests::tattach(fid, afid, uname, aname))) {
+            Ok(qid) => Ok(Dir {
+                fid: Fid::new(self.clone(), fid, qid),
+            }),
+            Err(e) => {
+                self.release_fid(fid);
+                Err(e)
+            }
+        }
+    }
+
+    /// Send `message` and wait for its reply
+    ///
+    /// Rerror and Rlerror replies are turned into `Error::Server`.
+    ///
+    /// # Errors
+    /// - the connection fails
+    /// - the server answers with an error
+    pub fn rpc(&self, message: Message) -> Result<Message> {
+        match self.connection().rpc(TaggedMessage::new(TAG, message))? {
+            reply @ (Message::Rerror(_) | Message::Rlerror(_)) => Err(reply_error(reply)),
+            reply => Ok(reply),
+        }
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.inner.connection.lock().unwrap()
+    }
+
+    /// The largest count a single Tread or Twrite can carry
+    fn max_io(&self) -> u32 {
+        self.inner.msize - P9_IOHDRSZ
+    }
+
+    fn alloc_fid(&self) -> Result<u32> {
+        self.inner
+            .fids
+            .lock()
+            .unwrap()
+            .alloc()
+            .ok_or(Error::Exhausted("fids"))
+    }
+
+    /// Give back a fid the server does not know about
+    fn release_fid(&self, fid: u32) {
+        self.inner.fids.lock().unwrap().release(fid);
+    }
+
+    /// Clunk `fid`, which the server frees even when the clunk fails
+    fn clunk(&self, fid: u32) -> Result<()> {
+        let result = self.rpc(Message::Tclunk(Tclunk { fid }));
+        self.release_fid(fid);
+        result.map(|_| ())
+    }
+}
+
+/// A fid of the client that is clunked once dropped
+struct Fid {
+    client: Client,
+    id: u32,
+    qid: Qid,
+    /// whether the server still knows the fid, so dropping it must clunk
+    live: bool,
+}
+
+impl Fid {
+    fn new(client: Client, id: u32, qid: Qid) -> Self {
+        Self {
+            client,
+            id,
+            qid,
+            live: true,
+        }
+    }
+
+    /// Walk `names` to a new fid, which is only bound when every name was walked
+    ///
+    /// A Twalk carries at most MAXWELEM names, longer walks continue from the new fid.
+    fn walk(&self, names: &[String], path: &str) -> Result<Fid> {
+        let newfid = self.client.alloc_fid()?;
+        let mut walk = Walk::new(names, path, self.id, self.qid.clone(), newfid);
+        while let Some(twalk) = walk.request() {
+            if let Err(e) = walk.reply(self.client.rpc(twalk)) {
+                // an earlier Twalk bound the new fid where the walk stopped
+                if walk.bound() {
+                    let _ = self.client.clunk(newfid);
+                } else {
+                    self.client.release_fid(newfid);
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(Fid::new(self.client.clone(), newfid, walk.qid()))
+    }
+
+    fn open(&mut self, mode: FlagSet<OpenMode>) -> Result<u32> {
+        let topen = Topen { fid: self.id, mode };
+        match self.client.rpc(Message::Topen(topen))? {
+            Message::Ropen(ropen) => {
+                self.qid = ropen.qid;
+                Ok(ropen.iounit)
+            }
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        name: &str,
+        perm: FlagSet<FileMode>,
+        mode: FlagSet<OpenMode>,
+    ) -> Result<u32> {
+        let tcreate = Tcreate {
+            fid: self.id,
+            name: name.to_string(),
+            perm,
+            mode,
+            extension: String::new(),
+        };
+        match self.client.rpc(Message::Tcreate(tcreate))? {
+            Message::Rcreate(rcreate) => {
+                self.qid = rcreate.qid;
+                Ok(rcreate.iounit)
+            }
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    fn stat(&self) -> Result<Stat> {
+        match self.client.rpc(Message::Tstat(Tstat { fid: self.id }))? {
+            Message::Rstat(rstat) => Ok(rstat.stat),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    fn wstat(&self, stat: &Stat) -> Result<()> {
+        let twstat = Twstat {
+            fid: self.id,
+            stat: stat.clone(),
+        };
+        match self.client.rpc(Message::Twstat(twstat))? {
+            Message::Rwstat(_) => Ok(()),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    /// Remove the file, which clunks the fid whether or not that succeeds
+    fn remove(mut self) -> Result<()> {
+        self.live = false;
+        let result = self.client.rpc(Message::Tremove(Tremove { fid: self.id }));
+        self.client.release_fid(self.id);
+        result.map(|_| ())
+    }
+
+    fn clunk(mut self) -> Result<()> {
+        self.live = false;
+        self.client.clunk(self.id)
+    }
+}
+
+impl Drop for Fid {
+    fn drop(&mut self) {
+        if self.live {
+            if let Err(e) = self.client.clunk(self.id) {
+                tracing::debug!(fid = self.id, "failed to clunk: {e}");
+            }
+        }
+    }
+}
+
+/// A file or directory walked to but not opened, the starting point for walks
+pub struct Dir {
+    fid: Fid,
+}
+
+impl Dir {
+    #[must_use]
+    pub fn qid(&self) -> &Qid {
+        &self.fid.qid
+    }
+
+    /// Walk to the directory at `path`, relative to this one
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - `path` is not a directory
+    pub fn walk(&self, path: &str) -> Result<Dir> {
+        let fid = self.fid.walk(&components(path), path)?;
+        if !fid.qid.qtype.contains(QidType::Dir) {
+            return Err(Error::NotADirectory(path.to_string()));
+        }
+
+        Ok(Dir { fid })
+    }
+
+    /// Whether `path` exists
+    ///
+    /// # Errors
+    /// - the server fails the walk for another reason than the file missing
+    pub fn exists(&self, path: &str) -> Result<bool> {
+        match self.fid.walk(&components(path), path) {
+            Ok(_) => Ok(true),
+            // the walk reports missing names, whether the server said so with Rwalk or Rerror
+            Err(Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Open the file at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - the server refuses to open it with `mode`
+    pub fn open(&self, path: &str, mode: impl Into<FlagSet<OpenMode>>) -> Result<File> {
+        let mut fid = self.fid.walk(&components(path), path)?;
+        let iounit = fid.open(mode.into())?;
+        Ok(File::new(fid, iounit))
+    }
+
+    /// Create the file at `path` and open it with `mode`
+    ///
+    /// # Errors
+    /// - the parent of `path` does not exist
+    /// - the server refuses to create the file
+    pub fn create(
+        &self,
+        path: &str,
+        perm: impl Into<FlagSet<FileMode>>,
+        mode: impl Into<FlagSet<OpenMode>>,
+    ) -> Result<File> {
+        let (parent, name) = split_parent(path)?;
+        let mut fid = self.fid.walk(&parent, path)?;
+        let iounit = fid.create(&name, perm.into(), mode.into())?;
+        Ok(File::new(fid, iounit))
+    }
+
+    /// Create the directory at `path`
+    ///
+    /// # Errors
+    /// - the parent of `path` does not exist
+    /// - the server refuses to create the directory
+    pub fn create_dir(&self, path: &str, perm: impl Into<FlagSet<FileMode>>) -> Result<()> {
+        self.create(path, perm.into() | FileMode::Dir, OpenMode::Read)?
+            .clunk()
+    }
+
+    /// Read the whole file at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist or is a directory
+    /// - the server fails a read
+    pub fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let mut file = self.open(path, OpenMode::Read)?;
+        if file.qid().qtype.contains(QidType::Dir) {
+            return Err(Error::IsADirectory(path.to_string()));
+        }
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        file.clunk()?;
+        Ok(contents)
+    }
+
+    /// Write `contents` to the file at `path`, creating it or truncating it first
+    ///
+    /// # Errors
+    /// - the parent of `path` does not exist
+    /// - the server refuses to create or write the file
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+        let perm = FileMode::from_unix_perm(FILE_PERM, false);
+        let file = match self.open(path, OpenMode::Write | OpenMode::Trunc) {
+            // only the file itself may be missing, not its parent
+            Err(Error::NotFound { walked, .. }) if walked + 1 == components(path).len() => {
+                self.create(path, perm, OpenMode::Write)
+            }
+            result => result,
+        }?;
+        file.write_all_at(0, contents.as_ref())?;
+        file.clunk()
+    }
+
+    /// # Errors
+    /// - `path` does not exist
+    pub fn stat(&self, path: &str) -> Result<Stat> {
+        self.fid.walk(&components(path), path)?.stat()
+    }
+
+    /// # Errors
+    /// - `path` does not exist
+    pub fn metadata(&self, path: &str) -> Result<Metadata> {
+        self.stat(path).map(Metadata::from)
+    }
+
+    /// Change the attributes of `path` that are not "don't touch" in `stat`
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - the server refuses the change
+    pub fn wstat(&self, path: &str, stat: &Stat) -> Result<()> {
+        self.fid.walk(&components(path), path)?.wstat(stat)
+    }
+
+    /// Remove the file or empty directory at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - the server refuses to remove it
+    pub fn remove(&self, path: &str) -> Result<()> {
+        self.fid.walk(&components(path), path)?.remove()
+    }
+
+    /// Clunk the fid now rather than when dropped
+    ///
+    /// # Errors
+    /// - the server fails the clunk
+    pub fn clunk(self) -> Result<()> {
+        self.fid.clunk()
+    }
+}
+
+/// An opened file or directory
+///
+/// Reads and writes through `std::io` move at most an iounit at the current position, like
+/// those of `std::fs::File`.
+pub struct File {
+    fid: Fid,
+    iounit: u32,
+    pos: u64,
+}
+
+impl File {
+    fn new(fid: Fid, iounit: u32) -> Self {
+        // an iounit of 0 leaves it to the msize
+        let max = fid.client.max_io();
+        let iounit = if iounit == 0 { max } else { iounit.min(max) };
+        Self {
+            fid,
+            iounit,
+            pos: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn qid(&self) -> &Qid {
+        &self.fid.qid
+    }
+
+    /// The most a single read or write transfers
+    #[must_use]
+    pub fn iounit(&self) -> u32 {
+        self.iounit
+    }
+
+    /// Read at most `count` bytes, limited to the iounit, at `offset`
+    ///
+    /// # Errors
+    /// - the server fails the read
+    pub fn read_at(&self, offset: u64, count: u32) -> Result<Bytes> {
+        let tread = Tread {
+            fid: self.fid.id,
+            offset,
+            count: count.min(self.iounit),
+        };
+        match self.fid.client.rpc(Message::Tread(tread))? {
+            Message::Rread(rread) => Ok(rread.data),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    /// Write as much of `data` as fits in the iounit at `offset`, returning how much was written
+    ///
+    /// # Errors
+    /// - the server fails the write
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<u32> {
+        let count = data.len().min(self.iounit as usize);
+        let twrite = Twrite {
+            fid: self.fid.id,
+            offset,
+            data: Bytes::copy_from_slice(&data[..count]),
+        };
+        match self.fid.client.rpc(Message::Twrite(twrite))? {
+            Message::Rwrite(rwrite) => Ok(rwrite.count),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    /// Write all of `data` at `offset`, an iounit at a time
+    ///
+    /// # Errors
+    /// - the server fails a write or stops accepting data
+    pub fn write_all_at(&self, mut offset: u64, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let count = self.write_at(offset, data)?;
+            if count == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            offset += u64::from(count);
+            data = &data[count as usize..];
+        }
+
+        Ok(())
+    }
+
+    /// # Errors
+    /// - the server fails the stat
+    pub fn stat(&self) -> Result<Stat> {
+        self.fid.stat()
+    }
+
+    /// Change the attributes that are not "don't touch" in `stat`
+    ///
+    /// # Errors
+    /// - the server refuses the change
+    pub fn wstat(&self, stat: &Stat) -> Result<()> {
+        self.fid.wstat(stat)
+    }
+
+    /// Clunk the fid now rather than when dropped
+    ///
+    /// # Errors
+    /// - the server fails the clunk
+    pub fn clunk(self) -> Result<()> {
+        self.fid.clunk()
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = u32::try_from(buf.len()).unwrap_or(!0);
+        let data = self.read_at(self.pos, count)?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let count = self.write_at(self.pos, buf)?;
+        self.pos += u64::from(count);
+        Ok(count as usize)
+    }
+
+    /// Writes are sent right away, there is nothing to flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.pos = match position {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(delta) => seek_offset(self.pos, delta)?,
+            SeekFrom::End(delta) => seek_offset(self.stat()?.length, delta)?,
+        };
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stowage_filesystems::memory;
+    use stowage_service::{Plan9, SharedSecret};
+
+    /// A runtime serving a fresh in-memory tree on a local TCP port
+    fn serve() -> (tokio::runtime::Runtime, std::net::SocketAddr) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        let handler = Arc::new(memory::Handler::new());
+        runtime.spawn(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(Plan9::new(socket, handler.clone()).run());
+            }
+        });
+        (runtime, addr)
+    }
+
+    #[test]
+    fn files_are_read_written_and_seeked() {
+        let (_runtime, addr) = serve();
+        let client = Client::connect(addr).unwrap();
+        let root = client.attach("glenda", "").unwrap();
+
+        root.create_dir("etc", FileMode::from_unix_perm(0o755, false))
+            .unwrap();
+        let data: Vec<u8> = (0..3 * client.msize()).map(|i| (i % 251) as u8).collect();
+        root.write("etc/config", &data).unwrap();
+        assert_eq!(root.read("etc/config").unwrap(), data);
+        assert_eq!(
+            root.metadata("etc/config").unwrap().len(),
+            data.len() as u64
+        );
+
+        let mut file = root.open("etc/config", OpenMode::ReadWrite).unwrap();
+        assert_eq!(
+            file.seek(SeekFrom::End(-10)).unwrap(),
+            data.len() as u64 - 10
+        );
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[data.len() - 10..]);
+
+        file.seek(SeekFrom::Start(1)).unwrap();
+        file.write_all(b"xyz").unwrap();
+        drop(file);
+        let contents = root.read("etc/config").unwrap();
+        assert_eq!(&contents[1..4], b"xyz");
+        assert_eq!(contents[4..], data[4..]);
+
+        root.remove("etc/config").unwrap();
+        assert!(!root.exists("etc/config").unwrap());
+        assert!(matches!(
+            root.exists("."),
+            Err(Error::Server { ename, .. }) if ename == "invalid file name"
+        ));
+        assert!(matches!(
+            root.open("etc/config", OpenMode::Read),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn speaks_over_unix_sockets() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (client_end, server_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        server_end.set_nonblocking(true).unwrap();
+        let handler = Arc::new(memory::Handler::new());
+        runtime.spawn(async move {
+            let server_end = tokio::net::UnixStream::from_std(server_end).unwrap();
+            let _ = Plan9::new(server_end, handler).run().await;
+        });
+
+        let client = Client::new(client_end).unwrap();
+        let root = client.attach("glenda", "").unwrap();
+        root.write("file", b"hello").unwrap();
+        let mut contents = String::new();
+        root.open("file", OpenMode::Read)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attaches_are_authenticated_with_a_shared_secret() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (client_end, server_end) = std::os::unix::net::UnixStream::pair().unwrap();
+        server_end.set_nonblocking(true).unwrap();
+        let handler = Arc::new(memory::Handler::new());
+        runtime.spawn(async move {
+            let server_end = tokio::net::UnixStream::from_std(server_end).unwrap();
+            let service = Plan9::new(server_end, handler)
+                .with_authenticator(Arc::new(SharedSecret::new("secret")));
+            let _ = service.run().await;
+        });
+
+        let client = Client::new(client_end).unwrap();
+        assert!(matches!(
+            client.attach("glenda", ""),
+            Err(Error::AuthRequired)
+        ));
+        assert!(matches!(
+            client.attach_with_secret("glenda", "", b"guess"),
+            Err(Error::Server { .. })
+        ));
+        let root = client.attach_with_secret("glenda", "", b"secret").unwrap();
+        root.write("file", b"hello").unwrap();
+        assert_eq!(root.read("file").unwrap(), b"hello");
+    }
+}
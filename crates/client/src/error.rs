@@ -0,0 +1,80 @@
+use stowage_proto::MessageType;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Proto(#[from] stowage_proto::error::Error),
+
+    /// The server answered with Rerror or Rlerror
+    #[error("{ename}")]
+    Server {
+        ename: String,
+        /// zero when the server did not give one
+        errno: u32,
+    },
+
+    #[error("connection closed")]
+    ConnectionClosed,
+
+    /// The connection was lost with the request in flight, it may or may not have taken effect
+    #[error("connection lost during {0:?}, it may or may not have taken effect")]
+    Interrupted(MessageType),
+
+    #[error("the server offered a smaller msize of {0} on reconnecting")]
+    MsizeLowered(u32),
+
+    #[error("unexpected reply: {0:?}")]
+    UnexpectedReply(MessageType),
+
+    #[error("server does not speak 9P2000, offered '{0}'")]
+    Version(String),
+
+    #[error("server requires authentication")]
+    AuthRequired,
+
+    #[error("no free {0} left")]
+    Exhausted(&'static str),
+
+    #[error("{0}: invalid path")]
+    InvalidPath(String),
+
+    /// A walk stopped at `name`, after `walked` names of `path`
+    #[error("{path}: {name} not found")]
+    NotFound {
+        path: String,
+        name: String,
+        walked: usize,
+    },
+
+    #[error("{0}: not a directory")]
+    NotADirectory(String),
+
+    #[error("{0}: is a directory")]
+    IsADirectory(String),
+
+    #[error("cannot rename {from} to {to}: not in the same directory")]
+    Rename { from: String, to: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(e) => e,
+            Error::NotFound { .. } => std::io::Error::new(std::io::ErrorKind::NotFound, value),
+            Error::NotADirectory(_) => {
+                std::io::Error::new(std::io::ErrorKind::NotADirectory, value)
+            }
+            Error::IsADirectory(_) => std::io::Error::new(std::io::ErrorKind::IsADirectory, value),
+            Error::InvalidPath(_) => std::io::Error::new(std::io::ErrorKind::InvalidInput, value),
+            Error::ConnectionClosed => {
+                std::io::Error::new(std::io::ErrorKind::ConnectionAborted, value)
+            }
+            value => std::io::Error::other(value),
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use crate::requests::Walk;
+use crate::{reply_error, Client, Error, Result};
+use bytes::Bytes;
+use flagset::FlagSet;
+use futures::{future::try_join_all, stream, StreamExt, TryStreamExt};
+use stowage_proto::{
+    consts::errno::ENOENT, FileMode, Message, OpenMode, Qid, QidType, Stat, Tcreate, Topen, Tread,
+    Tremove, Tstat, Twrite, Twstat,
+};
+
+/// How many reads or writes of a bulk transfer are in flight at once
+const WINDOW: usize = 16;
+
+/// A fid of the client that is clunked once dropped
+pub(crate) struct Fid {
+    client: Client,
+    id: u32,
+    pub(crate) qid: Qid,
+    /// whether the server still knows the fid, so dropping it must clunk
+    live: bool,
+}
+
+impl Fid {
+    fn new(client: Client, id: u32, qid: Qid) -> Self {
+        Self {
+            client,
+            id,
+            qid,
+            live: true,
+        }
+    }
+
+    /// Walk `names` to a new fid, which is only bound when every name was walked
+    ///
+    /// A Twalk carries at most MAXWELEM names, longer walks continue from the new fid.
+    pub(crate) async fn walk(&self, names: Vec<String>, path: &str) -> Result<Fid> {
+        let newfid = self.client.alloc_fid()?;
+        let mut walk = Walk::new(&names, path, self.id, self.qid.clone(), newfid);
+        while let Some(twalk) = walk.request() {
+            match walk.reply(self.client.rpc(twalk).await) {
+                Ok((from, names)) => self.client.track_walk(from, newfid, names),
+                Err(e) => {
+                    // an earlier Twalk bound the new fid where the walk stopped
+                    if walk.bound() {
+                        let _ = self.client.clunk(newfid).await;
+                    } else {
+                        self.client.release_fid(newfid);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(Fid::new(self.client.clone(), newfid, walk.qid()))
+    }
+
+    async fn open(&mut self, mode: FlagSet<OpenMode>) -> Result<u32> {
+        let topen = Topen { fid: self.id, mode };
+        match self.client.rpc(Message::Topen(topen)).await? {
+            Message::Ropen(ropen) => {
+                self.client.track_open(self.id, mode, None);
+                self.qid = ropen.qid;
+                Ok(ropen.iounit)
+            }
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    async fn create(
+        &mut self,
+        name: &str,
+        perm: FlagSet<FileMode>,
+        mode: FlagSet<OpenMode>,
+    ) -> Result<u32> {
+        let tcreate = Tcreate {
+            fid: self.id,
+            name: name.to_string(),
+            perm,
+            mode,
+            extension: String::new(),
+        };
+        match self.client.rpc(Message::Tcreate(tcreate)).await? {
+            Message::Rcreate(rcreate) => {
+                self.client.track_open(self.id, mode, Some(name));
+                self.qid = rcreate.qid;
+                Ok(rcreate.iounit)
+            }
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    async fn stat(&self) -> Result<Stat> {
+        match self
+            .client
+            .rpc(Message::Tstat(Tstat { fid: self.id }))
+            .await?
+        {
+            Message::Rstat(rstat) => Ok(rstat.stat),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    async fn wstat(&self, stat: &Stat) -> Result<()> {
+        let twstat = Twstat {
+            fid: self.id,
+            stat: stat.clone(),
+        };
+        match self.client.rpc(Message::Twstat(twstat)).await? {
+            Message::Rwstat(_) => {
+                if !Stat::is_dont_touch_string(&stat.name) {
+                    self.client.track_rename(self.id, &stat.name);
+                }
+                Ok(())
+            }
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    /// Remove the file, which clunks the fid whether or not that succeeds
+    pub(crate) async fn remove(mut self) -> Result<()> {
+        self.live = false;
+        let result = self
+            .client
+            .rpc(Message::Tremove(Tremove { fid: self.id }))
+            .await;
+        self.client.release_fid(self.id);
+        result.map(|_| ())
+    }
+
+    async fn clunk(mut self) -> Result<()> {
+        self.live = false;
+        self.client.clunk(self.id).await
+    }
+}
+
+impl Drop for Fid {
+    fn drop(&mut self) {
+        if !self.live {
+            return;
+        }
+
+        // without a runtime the fid stays allocated, it cannot be reused before the server
+        // has forgotten it
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let id = self.id;
+            runtime.spawn(async move {
+                if let Err(e) = client.clunk(id).await {
+                    tracing::debug!(fid = id, "failed to clunk: {e}");
+                }
+            });
+        }
+    }
+}
+
+/// A file or directory walked to but not opened, the starting point for walks
+pub struct Dir {
+    pub(crate) fid: Fid,
+}
+
+impl Dir {
+    pub(crate) fn new(client: Client, fid: u32, qid: Qid) -> Self {
+        Self {
+            fid: Fid::new(client, fid, qid),
+        }
+    }
+
+    #[must_use]
+    pub fn qid(&self) -> &Qid {
+        &self.fid.qid
+    }
+
+    /// Walk to the directory at `path`, relative to this one
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - `path` is not a directory
+    pub async fn walk(&self, path: &str) -> Result<Dir> {
+        let fid = self.fid.walk(components(path), path).await?;
+        if !fid.qid.qtype.contains(QidType::Dir) {
+            return Err(Error::NotADirectory(path.to_string()));
+        }
+
+        Ok(Dir { fid })
+    }
+
+    /// Whether `path` exists
+    ///
+    /// # Errors
+    /// - the server fails the walk for another reason than the file missing
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        match self.fid.walk(components(path), path).await {
+            Ok(_) => Ok(true),
+            // the walk reports missing names, whether the server said so with Rwalk or Rerror
+            Err(Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Open the file at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - the server refuses to open it with `mode`
+    pub async fn open(&self, path: &str, mode: impl Into<FlagSet<OpenMode>>) -> Result<File> {
+        let mut fid = self.fid.walk(components(path), path).await?;
+        let iounit = fid.open(mode.into()).await?;
+        Ok(File::new(fid, iounit))
+    }
+
+    /// Create the file at `path` and open it with `mode`
+    ///
+    /// # Errors
+    /// - the parent of `path` does not exist
+    /// - the server refuses to create the file
+    pub async fn create(
+        &self,
+        path: &str,
+        perm: impl Into<FlagSet<FileMode>>,
+        mode: impl Into<FlagSet<OpenMode>>,
+    ) -> Result<File> {
+        let (parent, name) = split_parent(path)?;
+        let mut fid = self.fid.walk(parent, path).await?;
+        let iounit = fid.create(&name, perm.into(), mode.into()).await?;
+        Ok(File::new(fid, iounit))
+    }
+
+    /// Create the directory at `path`
+    ///
+    /// # Errors
+    /// - the parent of `path` does not exist
+    /// - the server refuses to create the directory
+    pub async fn create_dir(&self, path: &str, perm: impl Into<FlagSet<FileMode>>) -> Result<()> {
+        let file = self
+            .create(path, perm.into() | FileMode::Dir, OpenMode::Read)
+            .await?;
+        file.clunk().await
+    }
+
+    /// # Errors
+    /// - `path` does not exist
+    pub async fn stat(&self, path: &str) -> Result<Stat> {
+        self.fid.walk(components(path), path).await?.stat().await
+    }
+
+    /// Change the attributes of `path` that are not "don't touch" in `stat`
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - the server refuses the change
+    pub async fn wstat(&self, path: &str, stat: &Stat) -> Result<()> {
+        self.fid
+            .walk(components(path), path)
+            .await?
+            .wstat(stat)
+            .await
+    }
+
+    /// Remove the file or empty directory at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist
+    /// - the server refuses to remove it
+    pub async fn remove(&self, path: &str) -> Result<()> {
+        self.fid.walk(components(path), path).await?.remove().await
+    }
+
+    /// Clunk the fid now rather than when dropped
+    ///
+    /// # Errors
+    /// - the server fails the clunk
+    pub async fn clunk(self) -> Result<()> {
+        self.fid.clunk().await
+    }
+}
+
+/// An opened file or directory
+pub struct File {
+    fid: Fid,
+    iounit: u32,
+}
+
+impl File {
+    fn new(fid: Fid, iounit: u32) -> Self {
+        // an iounit of 0 leaves it to the msize
+        let max = fid.client.max_io();
+        let iounit = if iounit == 0 { max } else { iounit.min(max) };
+        Self { fid, iounit }
+    }
+
+    #[must_use]
+    pub fn qid(&self) -> &Qid {
+        &self.fid.qid
+    }
+
+    /// The most a single read or write transfers
+    #[must_use]
+    pub fn iounit(&self) -> u32 {
+        self.iounit
+    }
+
+    /// Read at most `count` bytes, limited to the iounit, at `offset`
+    ///
+    /// # Errors
+    /// - the server fails the read
+    pub async fn read_at(&self, offset: u64, count: u32) -> Result<Bytes> {
+        let tread = Tread {
+            fid: self.fid.id,
+            offset,
+            count: count.min(self.iounit),
+        };
+        match self.fid.client.rpc(Message::Tread(tread)).await? {
+            Message::Rread(rread) => Ok(rread.data),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    /// Write as much of `data` as fits in the iounit at `offset`, returning how much was written
+    ///
+    /// # Errors
+    /// - the server fails the write
+    pub async fn write_at(&self, offset: u64, data: &[u8]) -> Result<u32> {
+        let count = data.len().min(self.iounit as usize);
+        let twrite = Twrite {
+            fid: self.fid.id,
+            offset,
+            data: Bytes::copy_from_slice(&data[..count]),
+        };
+        match self.fid.client.rpc(Message::Twrite(twrite)).await? {
+            Message::Rwrite(rwrite) => Ok(rwrite.count),
+            reply => Err(reply_error(reply)),
+        }
+    }
+
+    /// Read from `offset` until the end of the file
+    ///
+    /// Reads are issued a window of iounit sized chunks at a time.
+    ///
+    /// # Errors
+    /// - the server fails a read
+    pub async fn read_to_end(&self, mut offset: u64) -> Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        loop {
+            let data = self.read_window(offset).await?;
+            if data.is_empty() {
+                return Ok(contents);
+            }
+            offset += data.len() as u64;
+            contents.extend_from_slice(&data);
+        }
+    }
+
+    /// Read a window of iounit sized chunks from `offset` in parallel
+    ///
+    /// The data ends at the first short read, nothing read is the end of the file.
+    pub(crate) async fn read_window(&self, offset: u64) -> Result<Vec<u8>> {
+        let iounit = u64::from(self.iounit);
+        let reads = (0..WINDOW as u64).map(|i| self.read_at(offset + i * iounit, self.iounit));
+
+        let mut contents = Vec::new();
+        for data in try_join_all(reads).await? {
+            contents.extend_from_slice(&data);
+            // the rest of the window was read past a gap or the end
+            if data.len() < self.iounit as usize {
+                break;
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Write all of `data` at `offset`
+    ///
+    /// Writes are issued a window of iounit sized chunks at a time, so the file must not be
+    /// append-only.
+    ///
+    /// # Errors
+    /// - the server fails a write or stops accepting data
+    pub async fn write_all_at(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let iounit = self.iounit as usize;
+        let writes = (0..data.len()).step_by(iounit).map(|start| {
+            let chunk = &data[start..data.len().min(start + iounit)];
+            self.write_chunk_at(offset + start as u64, chunk)
+        });
+        stream::iter(writes)
+            .buffer_unordered(WINDOW)
+            .try_collect()
+            .await
+    }
+
+    /// Write all of `data` at `offset` one request after the other
+    async fn write_chunk_at(&self, mut offset: u64, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let count = self.write_at(offset, data).await?;
+            if count == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into());
+            }
+            offset += u64::from(count);
+            data = &data[count as usize..];
+        }
+
+        Ok(())
+    }
+
+    /// # Errors
+    /// - the server fails the stat
+    pub async fn stat(&self) -> Result<Stat> {
+        self.fid.stat().await
+    }
+
+    /// Change the attributes that are not "don't touch" in `stat`
+    ///
+    /// # Errors
+    /// - the server refuses the change
+    pub async fn wstat(&self, stat: &Stat) -> Result<()> {
+        self.fid.wstat(stat).await
+    }
+
+    /// Clunk the fid now rather than when dropped
+    ///
+    /// # Errors
+    /// - the server fails the clunk
+    pub async fn clunk(self) -> Result<()> {
+        self.fid.clunk().await
+    }
+}
+
+/// Whether an Rerror says that a file does not exist
+///
+/// 9P2000 servers only send a string, these are the ones Plan 9, Linux and stowage use.
+pub(crate) fn is_not_found(ename: &str, errno: u32) -> bool {
+    let ename = ename.to_lowercase();
+    errno == ENOENT
+        || ["not found", "does not exist", "no such file"]
+            .iter()
+            .any(|message| ename.contains(message))
+}
+
+/// The names to walk to reach `path`
+pub(crate) fn components(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// The names leading to the parent of `path` and the name of `path` in it
+pub(crate) fn split_parent(path: &str) -> Result<(Vec<String>, String)> {
+    let mut names = components(path);
+    match names.pop() {
+        Some(name) => Ok((names, name)),
+        None => Err(Error::InvalidPath(path.to_string())),
+    }
+}
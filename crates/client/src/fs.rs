@@ -0,0 +1,493 @@
+use crate::file::{components, split_parent};
+use crate::{Dir, Error, File, Result};
+use bytes::{Buf, Bytes};
+use flagset::FlagSet;
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stowage_proto::{Dialect, FileMode, OpenMode, Qid, QidType, Stat};
+
+/// The permissions new directories get
+const DIR_PERM: u32 = 0o755;
+/// The permissions new files get
+pub(crate) const FILE_PERM: u32 = 0o644;
+
+/// Operations on paths relative to a directory, in the manner of `std::fs`
+impl Dir {
+    /// Read the whole file at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist or is a directory
+    /// - the server fails a read
+    pub async fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let file = self.open(path, OpenMode::Read).await?;
+        if file.qid().qtype.contains(QidType::Dir) {
+            return Err(Error::IsADirectory(path.to_string()));
+        }
+
+        let contents = file.read_to_end(0).await?;
+        file.clunk().await?;
+        Ok(contents)
+    }
+
+    /// Read the whole file at `path` as UTF-8
+    ///
+    /// # Errors
+    /// - `path` cannot be read
+    /// - the contents are not UTF-8
+    pub async fn read_to_string(&self, path: &str) -> Result<String> {
+        String::from_utf8(self.read(path).await?).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, e.utf8_error()).into()
+        })
+    }
+
+    /// Write `contents` to the file at `path`, creating it or truncating it first
+    ///
+    /// # Errors
+    /// - the parent of `path` does not exist
+    /// - the server refuses to create or write the file
+    pub async fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+        let file = self
+            .create_or_truncate(path, FileMode::from_unix_perm(FILE_PERM, false))
+            .await?;
+        file.write_all_at(0, contents.as_ref()).await?;
+        file.clunk().await
+    }
+
+    /// Create the directory at `path` along with any of its missing parents
+    ///
+    /// # Errors
+    /// - a component of `path` is not a directory
+    /// - the server refuses to create a directory
+    pub async fn create_dir_all(&self, path: &str) -> Result<()> {
+        let names = components(path);
+        let mut dir = self.walk("").await?;
+        for (depth, name) in names.iter().enumerate() {
+            dir = match dir.walk(name).await {
+                Ok(next) => next,
+                Err(Error::NotFound { .. }) => {
+                    let created = dir
+                        .create_dir(name, FileMode::from_unix_perm(DIR_PERM, false))
+                        .await;
+                    // someone else may have created it in the meantime
+                    match (created, dir.walk(name).await) {
+                        (_, Ok(next)) => next,
+                        (Err(e), Err(_)) | (Ok(()), Err(e)) => return Err(e),
+                    }
+                }
+                Err(Error::NotADirectory(_)) => {
+                    return Err(Error::NotADirectory(names[..=depth].join("/")));
+                }
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Remove the file at `path`, which must not be a directory
+    ///
+    /// # Errors
+    /// - `path` does not exist or is a directory
+    /// - the server refuses to remove it
+    pub async fn remove_file(&self, path: &str) -> Result<()> {
+        let fid = self.fid.walk(components(path), path).await?;
+        if fid.qid.qtype.contains(QidType::Dir) {
+            return Err(Error::IsADirectory(path.to_string()));
+        }
+        fid.remove().await
+    }
+
+    /// Remove the empty directory at `path`
+    ///
+    /// # Errors
+    /// - `path` does not exist or is not a directory
+    /// - the directory is not empty
+    pub async fn remove_dir(&self, path: &str) -> Result<()> {
+        self.walk(path).await?.fid.remove().await
+    }
+
+    /// Remove the directory at `path` after everything in it
+    ///
+    /// # Errors
+    /// - `path` does not exist or is not a directory
+    /// - the server refuses to remove an entry
+    pub async fn remove_dir_all(&self, path: &str) -> Result<()> {
+        let dir = self.walk(path).await?;
+        dir.remove_contents().await?;
+        dir.fid.remove().await
+    }
+
+    /// Remove everything in this directory, depth first
+    fn remove_contents(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let entries: Vec<DirEntry> = self.read_dir("").await?.try_collect().await?;
+            for entry in entries {
+                if entry.metadata.is_dir() {
+                    let dir = self.walk(entry.file_name()).await?;
+                    dir.remove_contents().await?;
+                    dir.fid.remove().await?;
+                } else {
+                    self.remove(entry.file_name()).await?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Rename `from` to `to`
+    ///
+    /// 9P2000 renames with a Twstat of the name, so both must be in the same directory. Whether
+    /// an existing `to` is replaced is up to the server.
+    ///
+    /// # Errors
+    /// - `from` and `to` are in different directories
+    /// - `from` does not exist
+    /// - the server refuses the rename
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (from_parent, _) = split_parent(from)?;
+        let (to_parent, name) = split_parent(to)?;
+        if from_parent != to_parent {
+            return Err(Error::Rename {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+
+        let mut stat = Stat::new_dont_touch();
+        stat.name = name;
+        self.wstat(from, &stat).await
+    }
+
+    /// # Errors
+    /// - `path` does not exist
+    pub async fn metadata(&self, path: &str) -> Result<Metadata> {
+        self.stat(path).await.map(Metadata::from)
+    }
+
+    /// The entries of the directory at `path`, decoded as they are read
+    ///
+    /// # Errors
+    /// - `path` does not exist or is not a directory
+    pub async fn read_dir(&self, path: &str) -> Result<ReadDir> {
+        let dir = self.open(path, OpenMode::Read).await?;
+        if !dir.qid().qtype.contains(QidType::Dir) {
+            return Err(Error::NotADirectory(path.to_string()));
+        }
+
+        Ok(ReadDir::new(dir, path.trim_end_matches('/').to_string()))
+    }
+
+    /// Copy the contents of the file `from` to `to`, which gets the permissions of `from`
+    ///
+    /// Returns the number of bytes copied.
+    ///
+    /// # Errors
+    /// - `from` does not exist or is a directory
+    /// - `to` cannot be created
+    /// - the server fails a read or write
+    pub async fn copy(&self, from: &str, to: &str) -> Result<u64> {
+        let source = self.open(from, OpenMode::Read).await?;
+        if source.qid().qtype.contains(QidType::Dir) {
+            return Err(Error::IsADirectory(from.to_string()));
+        }
+
+        let perm = Metadata::from(source.stat().await?).permissions();
+        let target = self
+            .create_or_truncate(to, FileMode::from_unix_perm(perm, false))
+            .await?;
+
+        let mut offset = 0;
+        loop {
+            let data = source.read_window(offset).await?;
+            if data.is_empty() {
+                break;
+            }
+            target.write_all_at(offset, &data).await?;
+            offset += data.len() as u64;
+        }
+
+        source.clunk().await?;
+        target.clunk().await?;
+        Ok(offset)
+    }
+
+    /// Open the file at `path` for writing, truncated, or create it with `perm`
+    async fn create_or_truncate(&self, path: &str, perm: FlagSet<FileMode>) -> Result<File> {
+        match self.open(path, OpenMode::Write | OpenMode::Trunc).await {
+            // only the file itself may be missing, not its parent
+            Err(Error::NotFound { walked, .. }) if walked + 1 == components(path).len() => {
+                self.create(path, perm, OpenMode::Write).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// The attributes of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    stat: Stat,
+}
+
+impl Metadata {
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.stat.name
+    }
+
+    #[must_use]
+    pub fn qid(&self) -> &Qid {
+        &self.stat.qid
+    }
+
+    #[must_use]
+    pub fn mode(&self) -> FlagSet<FileMode> {
+        self.stat.mode
+    }
+
+    #[must_use]
+    pub fn is_dir(&self) -> bool {
+        self.stat.mode.contains(FileMode::Dir)
+    }
+
+    #[must_use]
+    pub fn is_symlink(&self) -> bool {
+        self.stat.mode.contains(FileMode::Symlink)
+    }
+
+    /// Whether this is a plain file, not a directory, link, device, pipe, socket or auth file
+    #[must_use]
+    pub fn is_file(&self) -> bool {
+        (self.stat.mode
+            & (FileMode::Dir
+                | FileMode::Symlink
+                | FileMode::Device
+                | FileMode::NamedPipe
+                | FileMode::Socket
+                | FileMode::Auth))
+            .is_empty()
+    }
+
+    #[must_use]
+    pub fn is_append_only(&self) -> bool {
+        self.stat.mode.contains(FileMode::AppendOnly)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.stat.length
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stat.length == 0
+    }
+
+    /// The unix style permission bits
+    #[must_use]
+    pub fn permissions(&self) -> u32 {
+        self.stat.mode.bits() & 0o777
+    }
+
+    #[must_use]
+    pub fn modified(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.stat.mtime.into())
+    }
+
+    #[must_use]
+    pub fn accessed(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.stat.atime.into())
+    }
+
+    #[must_use]
+    pub fn owner(&self) -> &str {
+        &self.stat.uid
+    }
+
+    #[must_use]
+    pub fn group(&self) -> &str {
+        &self.stat.gid
+    }
+
+    /// The user who last modified the file
+    #[must_use]
+    pub fn modified_by(&self) -> &str {
+        &self.stat.muid
+    }
+
+    #[must_use]
+    pub fn stat(&self) -> &Stat {
+        &self.stat
+    }
+}
+
+impl From<Stat> for Metadata {
+    fn from(stat: Stat) -> Self {
+        Self { stat }
+    }
+}
+
+/// An entry of a directory listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    path: String,
+    metadata: Metadata,
+}
+
+impl DirEntry {
+    /// The path of the entry, the listed path joined with its name
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[must_use]
+    pub fn file_name(&self) -> &str {
+        self.metadata.name()
+    }
+
+    #[must_use]
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// The entries of a directory, read an iounit at a time as the stream is polled
+///
+/// The directory is clunked once the stream is dropped.
+pub struct ReadDir {
+    entries: BoxStream<'static, Result<DirEntry>>,
+}
+
+/// Where the listing stands between entries
+struct Listing {
+    dir: File,
+    offset: u64,
+    /// read but not yet decoded
+    data: Bytes,
+}
+
+impl ReadDir {
+    fn new(dir: File, path: String) -> Self {
+        let listing = Listing {
+            dir,
+            offset: 0,
+            data: Bytes::new(),
+        };
+        let entries = stream::try_unfold(listing, move |mut listing| {
+            let path = path.clone();
+            async move {
+                if listing.data.is_empty() {
+                    let iounit = listing.dir.iounit();
+                    listing.data = listing.dir.read_at(listing.offset, iounit).await?;
+                    if listing.data.is_empty() {
+                        return Ok(None);
+                    }
+                    listing.offset += listing.data.len() as u64;
+                }
+
+                // entries are never split across reads
+                let mut rest = &listing.data[..];
+                let stat = Stat::decode_as(&mut rest, Dialect::Base)?;
+                listing.data.advance(listing.data.len() - rest.len());
+
+                let entry = DirEntry {
+                    path: if path.is_empty() {
+                        stat.name.clone()
+                    } else {
+                        format!("{path}/{}", stat.name)
+                    },
+                    metadata: Metadata::from(stat),
+                };
+                Ok(Some((entry, listing)))
+            }
+        });
+
+        Self {
+            entries: entries.boxed(),
+        }
+    }
+}
+
+impl Stream for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.entries.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use std::sync::Arc;
+    use stowage_filesystems::memory;
+    use stowage_service::Plan9;
+
+    async fn setup() -> Dir {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let handler = Arc::new(memory::Handler::new());
+        tokio::spawn(Plan9::new(server_end, handler).run());
+        let client = Client::new(client_end).await.unwrap();
+        client.attach("glenda", "").await.unwrap()
+    }
+
+    async fn names(root: &Dir, path: &str) -> Vec<String> {
+        let mut names: Vec<String> = root
+            .read_dir(path)
+            .await
+            .unwrap()
+            .map_ok(|entry| entry.file_name().to_string())
+            .try_collect()
+            .await
+            .unwrap();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn mirrors_std_fs() {
+        let root = setup().await;
+
+        root.create_dir_all("a/b/c").await.unwrap();
+        root.create_dir_all("a/b/c").await.unwrap();
+        assert!(root.metadata("a/b/c").await.unwrap().is_dir());
+
+        root.write("a/b/file", "hello").await.unwrap();
+        root.write("a/b/file", "hi").await.unwrap();
+        assert_eq!(root.read_to_string("a/b/file").await.unwrap(), "hi");
+        let metadata = root.metadata("a/b/file").await.unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata.permissions(), FILE_PERM);
+        assert!(matches!(
+            root.create_dir_all("a/b/file/d").await,
+            Err(Error::NotADirectory(path)) if path == "a/b/file"
+        ));
+
+        assert_eq!(root.copy("a/b/file", "a/copy").await.unwrap(), 2);
+        assert_eq!(root.read("a/copy").await.unwrap(), b"hi");
+        root.rename("a/copy", "a/renamed").await.unwrap();
+        assert_eq!(names(&root, "a").await, ["b", "renamed"]);
+        assert!(matches!(
+            root.rename("a/renamed", "a/b/renamed").await,
+            Err(Error::Rename { .. })
+        ));
+
+        assert!(matches!(
+            root.remove_file("a/b").await,
+            Err(Error::IsADirectory(_))
+        ));
+        assert!(matches!(
+            root.remove_dir("a/renamed").await,
+            Err(Error::NotADirectory(_))
+        ));
+        root.remove_file("a/renamed").await.unwrap();
+        root.remove_dir_all("a").await.unwrap();
+        assert!(names(&root, "").await.is_empty());
+    }
+}
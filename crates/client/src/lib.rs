@@ -0,0 +1,659 @@
+use futures::{SinkExt, StreamExt};
+use mux::Mux;
+use pool::IdPool;
+use reconnect::Resilience;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use stowage_proto::{
+    consts::{P9_DEFAULT_MSIZE, P9_IOHDRSZ, P9_NOFID},
+    Dialect, Message, MessageCodec, Rversion, TaggedMessage, Tclunk, Tversion,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+pub use error::{Error, Result};
+pub use file::{Dir, File};
+pub use fs::{DirEntry, Metadata, ReadDir};
+pub use remote::RemoteFile;
+
+mod auth;
+pub mod blocking;
+mod error;
+mod file;
+mod fs;
+mod mux;
+mod pool;
+mod reconnect;
+mod remote;
+mod requests;
+
+/// The tag reserved for Tversion
+const NOTAG: u16 = !0;
+
+/// A 9P2000 connection to a server
+///
+/// The client negotiates the version when it is created and hands out fids and tags for every
+/// request made through it. Requests are pipelined, any number of them can be outstanding at
+/// once. Cloning it is cheap, all clones share the connection.
+///
+/// A client made with `connect_resilient` or `with_redial` survives losing the connection: it
+/// redials, attaches again and restores the fids it had on the new connection. Idempotent
+/// requests caught by the loss are sent again, others fail with `Error::Interrupted`.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    connection: RwLock<Connection>,
+    msize: u32,
+    fids: Mutex<IdPool>,
+    resilience: Option<Resilience>,
+}
+
+/// The current connection, counting those that came before
+struct Connection {
+    generation: u64,
+    mux: Arc<Mux>,
+}
+
+impl Client {
+    /// Connect to a server over TCP and negotiate the version
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - version negotiation fails
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        // pipelined requests are small writes that must not wait for each other's acks
+        stream.set_nodelay(true)?;
+        Self::new(stream).await
+    }
+
+    /// Connect to a server listening on the unix socket at `path` and negotiate the version
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - version negotiation fails
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        Self::new(tokio::net::UnixStream::connect(path).await?).await
+    }
+
+    /// Connect to a server over TCP, dialing `addr` again whenever the connection is lost
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - version negotiation fails
+    pub async fn connect_resilient<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        Self::with_redial(move || {
+            let addr = addr.clone();
+            async move {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+                Ok(stream)
+            }
+        })
+        .await
+    }
+
+    /// Negotiate the version over an established `transport`
+    ///
+    /// # Errors
+    /// - the transport fails
+    /// - the server does not speak 9P2000
+    pub async fn new<T>(transport: T) -> Result<Self>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mux, msize) = handshake(transport).await?;
+        Ok(Self::with_connection(mux, msize, None))
+    }
+
+    /// Connect over the transports `dial` opens, calling it again whenever the connection is
+    /// lost
+    ///
+    /// # Errors
+    /// - `dial` fails
+    /// - the server does not speak 9P2000
+    pub async fn with_redial<F, Fut, T>(dial: F) -> Result<Self>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::io::Result<T>> + Send + 'static,
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let redial: reconnect::Redial = Box::new(move || {
+            let transport = dial();
+            Box::pin(async move { handshake(transport.await?).await })
+        });
+        let (mux, msize) = redial().await?;
+        Ok(Self::with_connection(
+            mux,
+            msize,
+            Some(Resilience::new(redial)),
+        ))
+    }
+
+    fn with_connection(mux: Mux, msize: u32, resilience: Option<Resilience>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                connection: RwLock::new(Connection {
+                    generation: 0,
+                    mux: Arc::new(mux),
+                }),
+                msize,
+                fids: Mutex::new(IdPool::new(P9_NOFID)),
+                resilience,
+            }),
+        }
+    }
+
+    /// The msize agreed on with the server
+    #[must_use]
+    pub fn msize(&self) -> u32 {
+        self.inner.msize
+    }
+
+    /// Attach to the file tree `aname` of the server as `uname`
+    ///
+    /// Servers that require authentication are attached to with `attach_with_secret`.
+    ///
+    /// # Errors
+    /// - the server requires authentication
+    /// - the server refuses the attach
+    pub async fn attach(&self, uname: &str, aname: &str) -> Result<Dir> {
+        let afid = self.alloc_fid()?;
+        let reply = self.rpc(requests::tauth(afid, uname, aname)).await;
+        match requests::auth_required(reply) {
+            Ok(false) => self.release_fid(afid),
+            Ok(true) => {
+                self.clunk(afid).await?;
+                return Err(Error::AuthRequired);
+            }
+            Err(e) => {
+                self.release_fid(afid);
+                return Err(e);
+            }
+        }
+
+        self.tattach(P9_NOFID, uname, aname, None).await
+    }
+
+    /// Attach to the file tree `aname` of the server as `uname`, proving it with `secret`
+    ///
+    /// This answers the challenge of a server authenticating with a shared secret, see
+    /// `stowage_proto::auth`. A resilient client authenticates again when it reconnects.
+    ///
+    /// # Errors
+    /// - the server does not authenticate, or not this way
+    /// - `secret` is not the server's
+    /// - the server refuses the attach
+    pub async fn attach_with_secret(&self, uname: &str, aname: &str, secret: &[u8]) -> Result<Dir> {
+        let afid = self.alloc_fid()?;
+        let (_, mux) = self.connection();
+        if let Err(e) = auth::authenticate(&mux, afid, uname, aname, secret).await {
+            self.release_fid(afid);
+            return Err(e);
+        }
+
+        let attached = self.tattach(afid, uname, aname, Some(secret)).await;
+        self.clunk(afid).await?;
+        attached
+    }
+
+    async fn tattach(
+        &self,
+        afid: u32,
+        uname: &str,
+        aname: &str,
+        secret: Option<&[u8]>,
+    ) -> Result<Dir> {
+        let fid = self.alloc_fid()?;
+        let reply = self.rpc(requests::tattach(fid, afid, uname, aname)).await;
+        match requests::rattach(reply) {
+            Ok(qid) => {
+                self.track_attach(fid, uname, aname, secret);
+                Ok(Dir::new(self.clone(), fid, qid))
+            }
+            Err(e) => {
+                self.release_fid(fid);
+                Err(e)
+            }
+        }
+    }
+
+    /// Send `message` and wait for its reply
+    ///
+    /// Rerror and Rlerror replies are turned into `Error::Server`. Dropping the future before
+    /// the reply arrived flushes the request.
+    ///
+    /// # Errors
+    /// - the connection fails
+    /// - the server answers with an error
+    pub async fn rpc(&self, message: Message) -> Result<Message> {
+        let reply = if self.inner.resilience.is_some() {
+            self.rpc_resilient(message).await
+        } else {
+            let (_, mux) = self.connection();
+            mux.rpc(message).await
+        };
+
+        match reply? {
+            reply @ (Message::Rerror(_) | Message::Rlerror(_)) => Err(reply_error(reply)),
+            reply => Ok(reply),
+        }
+    }
+
+    /// The current connection and its generation
+    pub(crate) fn connection(&self) -> (u64, Arc<Mux>) {
+        let connection = self.inner.connection.read().unwrap();
+        (connection.generation, connection.mux.clone())
+    }
+
+    pub(crate) fn set_connection(&self, generation: u64, mux: Mux) {
+        *self.inner.connection.write().unwrap() = Connection {
+            generation,
+            mux: Arc::new(mux),
+        };
+    }
+
+    /// The largest count a single Tread or Twrite can carry
+    pub(crate) fn max_io(&self) -> u32 {
+        self.inner.msize - P9_IOHDRSZ
+    }
+
+    pub(crate) fn alloc_fid(&self) -> Result<u32> {
+        self.inner
+            .fids
+            .lock()
+            .unwrap()
+            .alloc()
+            .ok_or(Error::Exhausted("fids"))
+    }
+
+    /// Give back a fid the server does not know about
+    pub(crate) fn release_fid(&self, fid: u32) {
+        self.untrack(fid);
+        self.inner.fids.lock().unwrap().release(fid);
+    }
+
+    /// Clunk `fid`, which the server frees even when the clunk fails
+    pub(crate) async fn clunk(&self, fid: u32) -> Result<()> {
+        let result = self.rpc(Message::Tclunk(Tclunk { fid })).await;
+        self.release_fid(fid);
+        result.map(|_| ())
+    }
+}
+
+/// Negotiate the version over `transport`, returning the connection and its msize
+async fn handshake<T>(transport: T) -> Result<(Mux, u32)>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut connection = Framed::new(transport, MessageCodec::new());
+
+    let tversion = Tversion {
+        msize: P9_DEFAULT_MSIZE,
+        version: Dialect::Base.as_str().to_string(),
+    };
+    connection
+        .send(TaggedMessage::new(NOTAG, Message::Tversion(tversion)))
+        .await?;
+    let rversion = match connection.next().await {
+        Some(Ok(TaggedMessage {
+            message: Message::Rversion(rversion),
+            ..
+        })) => rversion,
+        Some(Ok(reply)) => return Err(reply_error(reply.message)),
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err(Error::ConnectionClosed),
+    };
+    let msize = negotiated_msize(rversion)?;
+    connection.codec_mut().set_msize(msize);
+    Ok((Mux::new(connection), msize))
+}
+
+/// The msize to use after the server answered the Tversion with `rversion`
+///
+/// # Errors
+/// - the server does not speak 9P2000
+/// - the server's msize leaves no room for the data of a read or write
+pub(crate) fn negotiated_msize(rversion: Rversion) -> Result<u32> {
+    if rversion.version != Dialect::Base.as_str() {
+        return Err(Error::Version(rversion.version));
+    }
+    if rversion.msize <= P9_IOHDRSZ {
+        return Err(stowage_proto::error::Error::Protocol(format!(
+            "msize {} is too small to carry any data",
+            rversion.msize
+        ))
+        .into());
+    }
+
+    Ok(rversion.msize.min(P9_DEFAULT_MSIZE))
+}
+
+/// The error to report for a reply that is not the one expected
+fn reply_error(reply: Message) -> Error {
+    match reply {
+        Message::Rerror(rerror) => Error::Server {
+            ename: rerror.ename,
+            errno: rerror.errno,
+        },
+        Message::Rlerror(rlerror) => Error::Server {
+            ename: format!("errno {}", rlerror.ecode),
+            errno: rlerror.ecode,
+        },
+        reply => Error::UnexpectedReply(reply.message_type()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use stowage_filesystems::memory;
+    use stowage_proto::{
+        consts::{P9_MAXWELEM, P9_NONUNAME},
+        FileMode, OpenMode, Rerror, Rflush, Tattach, Tflush, Tstat, Twalk,
+    };
+    use stowage_service::{Peer, PeerPolicy, Plan9, SharedSecret};
+
+    /// A client attached to a fresh in-memory server
+    async fn setup() -> (Client, Dir) {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let handler = Arc::new(memory::Handler::new());
+        tokio::spawn(Plan9::new(server_end, handler).run());
+
+        let client = Client::new(client_end).await.unwrap();
+        let root = client.attach("glenda", "").await.unwrap();
+        (client, root)
+    }
+
+    #[tokio::test]
+    async fn files_round_trip_in_iounit_chunks() {
+        let (client, root) = setup().await;
+        root.create_dir("dir", FileMode::from_unix_perm(0o755, false))
+            .await
+            .unwrap();
+
+        // larger than a single message can carry
+        let data: Vec<u8> = (0..3 * client.msize()).map(|i| (i % 251) as u8).collect();
+        let file = root
+            .create(
+                "dir/file",
+                FileMode::from_unix_perm(0o644, false),
+                OpenMode::ReadWrite,
+            )
+            .await
+            .unwrap();
+        file.write_all_at(0, &data).await.unwrap();
+        assert_eq!(file.read_to_end(0).await.unwrap(), data);
+        file.clunk().await.unwrap();
+
+        let entries: Vec<DirEntry> = root
+            .read_dir("dir")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path(), "dir/file");
+        assert_eq!(entries[0].metadata().len(), data.len() as u64);
+
+        root.remove("dir/file").await.unwrap();
+        assert!(!root.exists("dir/file").await.unwrap());
+        // other failures are not taken to mean the file is missing
+        assert!(matches!(
+            root.exists(".").await,
+            Err(Error::Server { ename, .. }) if ename == "invalid file name"
+        ));
+        assert!(matches!(
+            root.walk("dir/file").await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn long_walks_are_split() {
+        let (client, root) = setup().await;
+        let names: Vec<String> = (0..40).map(|i| format!("d{i}")).collect();
+        let path = names.join("/");
+        root.create_dir_all(&path).await.unwrap();
+        root.write(&format!("{path}/file"), b"deep").await.unwrap();
+        assert_eq!(root.read(&format!("{path}/file")).await.unwrap(), b"deep");
+
+        // the missing name lies in the third Twalk
+        let missing = format!("{}/nope/{}", names[..35].join("/"), names[35]);
+        match root.walk(&missing).await {
+            Err(Error::NotFound { name, walked, .. }) => {
+                assert_eq!(name, "nope");
+                assert_eq!(walked, 35);
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("walked past a missing name"),
+        }
+
+        // the server itself still refuses anything longer than a single walk may be
+        let fid = client.alloc_fid().unwrap();
+        let tattach = Tattach {
+            fid,
+            afid: P9_NOFID,
+            uname: "glenda".to_string(),
+            aname: String::new(),
+            n_uname: P9_NONUNAME,
+        };
+        client.rpc(Message::Tattach(tattach)).await.unwrap();
+        let twalk = Twalk {
+            fid,
+            newfid: client.alloc_fid().unwrap(),
+            wnames: names[..=P9_MAXWELEM].to_vec(),
+        };
+        assert!(matches!(
+            client.rpc(Message::Twalk(twalk)).await,
+            Err(Error::Server { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn attaches_are_authenticated_with_a_shared_secret() {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let handler = Arc::new(memory::Handler::new());
+        let service = Plan9::new(server_end, handler)
+            .with_authenticator(Arc::new(SharedSecret::new("secret")));
+        tokio::spawn(service.run());
+        let client = Client::new(client_end).await.unwrap();
+
+        assert!(matches!(
+            client.attach("glenda", "").await,
+            Err(Error::AuthRequired)
+        ));
+        assert!(matches!(
+            client.attach_with_secret("glenda", "", b"guess").await,
+            Err(Error::Server { .. })
+        ));
+        let root = client
+            .attach_with_secret("glenda", "", b"secret")
+            .await
+            .unwrap();
+        root.write("file", b"hello").await.unwrap();
+        assert_eq!(root.read("file").await.unwrap(), b"hello");
+
+        // an afid only admits the user and tree it was authenticated for
+        let afid = client.alloc_fid().unwrap();
+        let (_, mux) = client.connection();
+        auth::authenticate(&mux, afid, "glenda", "", b"secret")
+            .await
+            .unwrap();
+        for (uname, aname) in [("bootes", ""), ("glenda", "other")] {
+            let tattach = Tattach {
+                fid: client.alloc_fid().unwrap(),
+                afid,
+                uname: uname.to_string(),
+                aname: aname.to_string(),
+                n_uname: P9_NONUNAME,
+            };
+            assert!(matches!(
+                client.rpc(Message::Tattach(tattach)).await,
+                Err(Error::Server { .. })
+            ));
+        }
+        // nor can it be attached with
+        let tattach = Tattach {
+            fid: afid,
+            afid: P9_NOFID,
+            uname: "glenda".to_string(),
+            aname: String::new(),
+            n_uname: P9_NONUNAME,
+        };
+        assert!(matches!(
+            client.rpc(Message::Tattach(tattach)).await,
+            Err(Error::Server { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn attaches_are_held_to_the_peer() {
+        let handler = Arc::new(memory::Handler::new());
+        let peer = Peer {
+            name: "glenda".to_string(),
+            uid: Some(1000),
+            gid: Some(1000),
+        };
+        let connect = |policy| {
+            let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+            let service = Plan9::new(server_end, handler.clone()).with_peer(peer.clone(), policy);
+            tokio::spawn(service.run());
+            Client::new(client_end)
+        };
+
+        let client = connect(PeerPolicy::Verify).await.unwrap();
+        client.attach("glenda", "").await.unwrap();
+        client.attach("1000", "").await.unwrap();
+        assert!(matches!(
+            client.attach("bootes", "").await,
+            Err(Error::Server { .. })
+        ));
+
+        // whoever the client claims to be, it is the peer that creates files
+        let client = connect(PeerPolicy::Trust).await.unwrap();
+        let root = client.attach("bootes", "").await.unwrap();
+        root.write("file", b"").await.unwrap();
+        assert_eq!(root.stat("file").await.unwrap().uid, "glenda");
+    }
+
+    #[tokio::test]
+    async fn requests_are_answered_concurrently() {
+        let (_client, root) = setup().await;
+        let names: Vec<String> = (0..64).map(|i| format!("file{i}")).collect();
+        let creates = names.iter().map(|name| async {
+            root.create(
+                name,
+                FileMode::from_unix_perm(0o644, false),
+                OpenMode::Write,
+            )
+            .await?
+            .write_all_at(0, name.as_bytes())
+            .await
+        });
+        futures::future::try_join_all(creates).await.unwrap();
+
+        let stats = names.iter().map(|name| root.stat(name));
+        let stats = futures::future::try_join_all(stats).await.unwrap();
+        for (name, stat) in names.iter().zip(stats) {
+            assert_eq!(&stat.name, name);
+            assert_eq!(stat.length, name.len() as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn msizes_too_small_for_data_are_refused() {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let mut server = Framed::new(server_end, MessageCodec::new());
+        let client = tokio::spawn(Client::new(client_end));
+
+        server.next().await.unwrap().unwrap();
+        let rversion = Rversion {
+            msize: P9_IOHDRSZ,
+            version: Dialect::Base.as_str().to_string(),
+        };
+        server
+            .send(TaggedMessage::new(NOTAG, Message::Rversion(rversion)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.await.unwrap(),
+            Err(Error::Proto(stowage_proto::error::Error::Protocol(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_requests_are_flushed() {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let mut server = Framed::new(server_end, MessageCodec::new());
+        let client = tokio::spawn(Client::new(client_end));
+
+        let tversion = server.next().await.unwrap().unwrap();
+        assert_eq!(tversion.tag, NOTAG);
+        let rversion = Rversion {
+            msize: P9_DEFAULT_MSIZE,
+            version: Dialect::Base.as_str().to_string(),
+        };
+        server
+            .send(TaggedMessage::new(NOTAG, Message::Rversion(rversion)))
+            .await
+            .unwrap();
+        let client = client.await.unwrap().unwrap();
+
+        // the request is dropped once the server has seen it, without an answer
+        let tstat = client.rpc(Message::Tstat(Tstat { fid: 0 }));
+        let request = tokio::select! {
+            _ = tstat => panic!("the request was answered"),
+            request = server.next() => request.unwrap().unwrap(),
+        };
+        let flush = server.next().await.unwrap().unwrap();
+        assert_ne!(flush.tag, request.tag);
+        assert_eq!(
+            flush.message,
+            Message::Tflush(Tflush {
+                oldtag: request.tag
+            })
+        );
+
+        // a late answer to the flushed request is dropped
+        server
+            .send(TaggedMessage::new(
+                request.tag,
+                Message::error("too late".to_string()),
+            ))
+            .await
+            .unwrap();
+        server
+            .send(TaggedMessage::new(flush.tag, Message::Rflush(Rflush)))
+            .await
+            .unwrap();
+
+        let tstat = tokio::spawn({
+            let client = client.clone();
+            async move { client.rpc(Message::Tstat(Tstat { fid: 0 })).await }
+        });
+        let request = server.next().await.unwrap().unwrap();
+        let rerror = Rerror {
+            ename: "unknown fid".to_string(),
+            errno: 0,
+        };
+        server
+            .send(TaggedMessage::new(request.tag, Message::Rerror(rerror)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            tstat.await.unwrap(),
+            Err(Error::Server { ename, .. }) if ename == "unknown fid"
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{pool::IdPool, Error, Result, NOTAG};
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use stowage_proto::{Message, MessageCodec, TaggedMessage, Tflush};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::codec::Framed;
+
+type Connection<T> = Framed<T, MessageCodec>;
+
+/// Multiplexes requests over one connection
+///
+/// A writer task sends the requests and a reader task routes every reply to whoever waits on its
+/// tag, so any number of requests can be outstanding at once.
+pub(crate) struct Mux {
+    requests: mpsc::UnboundedSender<TaggedMessage>,
+    tags: Arc<Mutex<Tags>>,
+}
+
+/// What to do with the reply to a tag
+enum Waiter {
+    /// hand it to the caller
+    Reply(oneshot::Sender<Result<Message>>),
+    /// the caller went away and the request was flushed, drop it
+    Flushed,
+    /// the reply to a Tflush, after which `oldtag` can be reused
+    Flush { oldtag: u16 },
+}
+
+/// The tags in use and who is waiting on them
+struct Tags {
+    pool: IdPool,
+    waiters: HashMap<u16, Waiter>,
+    /// the connection is gone, no reply will arrive anymore
+    closed: bool,
+}
+
+impl Tags {
+    fn alloc(&mut self) -> Option<u16> {
+        // unwrap - the pool stops below NOTAG
+        self.pool.alloc().map(|tag| u16::try_from(tag).unwrap())
+    }
+
+    fn release(&mut self, tag: u16) {
+        self.pool.release(u32::from(tag));
+    }
+
+    /// Route the outcome of the request with `tag`
+    fn complete(&mut self, tag: u16, reply: Result<Message>) {
+        match self.waiters.remove(&tag) {
+            // the caller releases the tag once it has the reply
+            Some(Waiter::Reply(sender)) => {
+                let _ = sender.send(reply);
+            }
+            // the tag is released when the flush is answered
+            Some(Waiter::Flushed) => {}
+            Some(Waiter::Flush { oldtag }) => {
+                // the flushed request may or may not have been answered before
+                self.waiters.remove(&oldtag);
+                self.release(oldtag);
+                self.release(tag);
+            }
+            None => tracing::debug!(tag, "reply to an unknown tag"),
+        }
+    }
+
+    /// Fail every outstanding request
+    fn close(&mut self) {
+        self.closed = true;
+        self.waiters.clear();
+    }
+}
+
+impl Mux {
+    /// Take over `connection`, spawning the tasks that drive it
+    pub(crate) fn new<T>(connection: Connection<T>) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let tags = Arc::new(Mutex::new(Tags {
+            pool: IdPool::new(u32::from(NOTAG)),
+            waiters: HashMap::new(),
+            closed: false,
+        }));
+        let (sink, stream) = connection.split();
+        let (requests, queue) = mpsc::unbounded_channel();
+        tokio::spawn(write_requests(sink, queue, tags.clone()));
+        tokio::spawn(read_replies(stream, tags.clone()));
+
+        Self { requests, tags }
+    }
+
+    /// Send `message` under a fresh tag and wait for its reply
+    ///
+    /// Dropping the future before the reply arrived flushes the request.
+    pub(crate) async fn rpc(&self, message: Message) -> Result<Message> {
+        let (sender, reply) = oneshot::channel();
+        let tag = {
+            let mut tags = self.tags();
+            if tags.closed {
+                return Err(Error::ConnectionClosed);
+            }
+            let tag = tags.alloc().ok_or(Error::Exhausted("tags"))?;
+            tags.waiters.insert(tag, Waiter::Reply(sender));
+            tag
+        };
+
+        let mut request = Request {
+            mux: self,
+            tag,
+            reply,
+            answered: false,
+        };
+        self.requests
+            .send(TaggedMessage::new(tag, message))
+            .map_err(|_| Error::ConnectionClosed)?;
+
+        let reply = (&mut request.reply).await;
+        request.answered = true;
+        reply.unwrap_or(Err(Error::ConnectionClosed))
+    }
+
+    /// Whether the connection is known to be gone
+    pub(crate) fn is_closed(&self) -> bool {
+        self.tags().closed
+    }
+
+    fn tags(&self) -> MutexGuard<'_, Tags> {
+        self.tags.lock().unwrap()
+    }
+}
+
+/// An outstanding request, flushed when dropped before it was answered
+struct Request<'a> {
+    mux: &'a Mux,
+    tag: u16,
+    reply: oneshot::Receiver<Result<Message>>,
+    answered: bool,
+}
+
+impl Drop for Request<'_> {
+    fn drop(&mut self) {
+        let mut tags = self.mux.tags();
+        // the reader only hands out replies under the lock, so an empty channel means the
+        // request is still outstanding
+        if self.answered
+            || !matches!(
+                self.reply.try_recv(),
+                Err(oneshot::error::TryRecvError::Empty)
+            )
+        {
+            tags.release(self.tag);
+            return;
+        }
+
+        // the tag stays in use until the server confirms the flush, without a tag to flush
+        // with it is never reused
+        tags.waiters.insert(self.tag, Waiter::Flushed);
+        let Some(flush) = tags.alloc() else {
+            return;
+        };
+        tags.waiters
+            .insert(flush, Waiter::Flush { oldtag: self.tag });
+
+        let tflush = Tflush { oldtag: self.tag };
+        if self
+            .mux
+            .requests
+            .send(TaggedMessage::new(flush, Message::Tflush(tflush)))
+            .is_err()
+        {
+            tags.waiters.remove(&self.tag);
+            tags.waiters.remove(&flush);
+            tags.release(self.tag);
+            tags.release(flush);
+        }
+    }
+}
+
+/// Write the queued requests, flushing the transport whenever the queue runs dry
+async fn write_requests<T>(
+    mut sink: SplitSink<Connection<T>, TaggedMessage>,
+    mut queue: mpsc::UnboundedReceiver<TaggedMessage>,
+    tags: Arc<Mutex<Tags>>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(mut request) = queue.recv().await {
+        loop {
+            let tag = request.tag;
+            if let Err(e) = sink.feed(request).await {
+                tags.lock().unwrap().complete(tag, Err(e.into()));
+            }
+            match queue.try_recv() {
+                Ok(next) => request = next,
+                Err(_) => break,
+            }
+        }
+
+        if let Err(e) = sink.flush().await {
+            tracing::debug!("failed to write requests: {e}");
+            tags.lock().unwrap().close();
+            return;
+        }
+    }
+
+    // every client is gone, shutting down the write side makes the server hang up, which in
+    // turn ends the reader
+    if let Err(e) = sink.close().await {
+        tracing::debug!("failed to close the connection: {e}");
+    }
+}
+
+/// Route replies to their waiters until the connection ends
+async fn read_replies<T>(mut stream: SplitStream<Connection<T>>, tags: Arc<Mutex<Tags>>)
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(reply) = stream.next().await {
+        match reply {
+            Ok(reply) => tags.lock().unwrap().complete(reply.tag, Ok(reply.message)),
+            Err(e) => {
+                tracing::debug!("failed to read a reply: {e}");
+                break;
+            }
+        }
+    }
+
+    tags.lock().unwrap().close();
+}
@@ -0,0 +1,33 @@
+/// Hands out the numbers below `limit`, reusing the ones given back
+pub(crate) struct IdPool {
+    next: u32,
+    limit: u32,
+    free: Vec<u32>,
+}
+
+impl IdPool {
+    pub(crate) fn new(limit: u32) -> Self {
+        Self {
+            next: 0,
+            limit,
+            free: Vec::new(),
+        }
+    }
+
+    pub(crate) fn alloc(&mut self) -> Option<u32> {
+        if let Some(id) = self.free.pop() {
+            return Some(id);
+        }
+        if self.next == self.limit {
+            return None;
+        }
+
+        let id = self.next;
+        self.next += 1;
+        Some(id)
+    }
+
+    pub(crate) fn release(&mut self, id: u32) {
+        self.free.push(id);
+    }
+}
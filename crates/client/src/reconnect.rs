@@ -0,0 +1,429 @@
+use crate::{auth, mux::Mux, requests, Client, Error, Result};
+use flagset::FlagSet;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use stowage_proto::{
+    consts::{P9_MAXWELEM, P9_NOFID},
+    Message, OpenMode, Tclunk, Topen, Twalk,
+};
+
+/// Opens a new connection and negotiates the version on it
+pub(crate) type Redial = Box<dyn Fn() -> BoxFuture<'static, Result<(Mux, u32)>> + Send + Sync>;
+
+/// How many times a request is sent again after the connection was lost
+const RETRIES: usize = 3;
+
+/// What a resilient client needs to get its fids back on a new connection
+pub(crate) struct Resilience {
+    redial: Redial,
+    /// how every live fid was reached
+    paths: Mutex<HashMap<u32, FidPath>>,
+    /// held while reconnecting, so that only one caller redials
+    reconnecting: tokio::sync::Mutex<()>,
+}
+
+/// The attach and walk that lead to a fid, and how it was opened
+#[derive(Clone)]
+struct FidPath {
+    uname: String,
+    aname: String,
+    /// what the attach was authenticated with
+    secret: Option<Arc<[u8]>>,
+    names: Vec<String>,
+    mode: Option<FlagSet<OpenMode>>,
+}
+
+impl Resilience {
+    pub(crate) fn new(redial: Redial) -> Self {
+        Self {
+            redial,
+            paths: Mutex::new(HashMap::new()),
+            reconnecting: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn paths(&self) -> std::sync::MutexGuard<'_, HashMap<u32, FidPath>> {
+        self.paths.lock().unwrap()
+    }
+}
+
+/// Whether sending `message` twice does no more than sending it once
+fn idempotent(message: &Message) -> bool {
+    match message {
+        Message::Topen(topen) => !topen.mode.contains(OpenMode::Trunc),
+        Message::Tversion(_)
+        | Message::Tauth(_)
+        | Message::Tattach(_)
+        | Message::Twalk(_)
+        | Message::Tread(_)
+        | Message::Tstat(_)
+        | Message::Tclunk(_) => true,
+        _ => false,
+    }
+}
+
+impl Client {
+    /// Send `message`, reconnecting when the connection is lost
+    ///
+    /// Requests that are not idempotent are not sent again, they fail with `Error::Interrupted`.
+    pub(crate) async fn rpc_resilient(&self, message: Message) -> Result<Message> {
+        let mut attempts = 0;
+        loop {
+            let (generation, mux) = self.connection();
+            // a connection known to be gone never saw the request
+            if mux.is_closed() {
+                self.reconnect(generation).await?;
+                continue;
+            }
+
+            match mux.rpc(message.clone()).await {
+                Err(e @ (Error::ConnectionClosed | Error::Io(_))) => {
+                    attempts += 1;
+                    if attempts > RETRIES {
+                        return Err(e);
+                    }
+                    self.reconnect(generation).await?;
+                    if !idempotent(&message) {
+                        return Err(Error::Interrupted(message.message_type()));
+                    }
+                }
+                reply => return reply,
+            }
+        }
+    }
+
+    /// Replace the connection of `generation` with a new one and restore every live fid on it
+    ///
+    /// Fids keep their numbers, the new connection starts out without any.
+    async fn reconnect(&self, generation: u64) -> Result<()> {
+        let Some(resilience) = &self.inner.resilience else {
+            return Err(Error::ConnectionClosed);
+        };
+        let _reconnecting = resilience.reconnecting.lock().await;
+        if self.connection().0 != generation {
+            // someone else already did
+            return Ok(());
+        }
+
+        tracing::debug!("connection lost, reconnecting");
+        let (mux, msize) = (resilience.redial)().await?;
+        if msize < self.inner.msize {
+            return Err(Error::MsizeLowered(msize));
+        }
+
+        let paths = resilience.paths().clone();
+        let root = self.alloc_fid()?;
+        let afid = match self.alloc_fid() {
+            Ok(afid) => afid,
+            Err(e) => {
+                self.release_fid(root);
+                return Err(e);
+            }
+        };
+        let restored = restore(&mux, root, afid, paths).await;
+        self.release_fid(afid);
+        self.release_fid(root);
+        restored?;
+
+        self.set_connection(generation + 1, mux);
+        Ok(())
+    }
+
+    /// Remember `fid` as the root of the tree `aname` attached to as `uname`
+    pub(crate) fn track_attach(&self, fid: u32, uname: &str, aname: &str, secret: Option<&[u8]>) {
+        if let Some(resilience) = &self.inner.resilience {
+            let path = FidPath {
+                uname: uname.to_string(),
+                aname: aname.to_string(),
+                secret: secret.map(Arc::from),
+                names: Vec::new(),
+                mode: None,
+            };
+            resilience.paths().insert(fid, path);
+        }
+    }
+
+    /// Remember `newfid` as reached by walking `names` from `fid`
+    pub(crate) fn track_walk(&self, fid: u32, newfid: u32, names: &[String]) {
+        if let Some(resilience) = &self.inner.resilience {
+            let mut paths = resilience.paths();
+            if let Some(path) = paths.get(&fid) {
+                let mut path = path.clone();
+                path.names.extend_from_slice(names);
+                path.mode = None;
+                paths.insert(newfid, path);
+            }
+        }
+    }
+
+    /// Remember `fid` as opened with `mode`, after creating `created` in it if given
+    pub(crate) fn track_open(&self, fid: u32, mode: FlagSet<OpenMode>, created: Option<&str>) {
+        if let Some(resilience) = &self.inner.resilience {
+            if let Some(path) = resilience.paths().get_mut(&fid) {
+                path.names.extend(created.map(ToString::to_string));
+                // reopening must not throw away what was written since
+                path.mode = Some(mode - OpenMode::Trunc);
+            }
+        }
+    }
+
+    /// Follow the rename of `fid` to `name`, for it and every fid beneath it
+    pub(crate) fn track_rename(&self, fid: u32, name: &str) {
+        if let Some(resilience) = &self.inner.resilience {
+            let mut paths = resilience.paths();
+            let Some(renamed) = paths.get(&fid).cloned() else {
+                return;
+            };
+            let Some((_, parent)) = renamed.names.split_last() else {
+                return;
+            };
+
+            let depth = renamed.names.len();
+            for path in paths.values_mut() {
+                if path.uname == renamed.uname
+                    && path.aname == renamed.aname
+                    && path.names.starts_with(&renamed.names)
+                {
+                    path.names.splice(..depth, parent.iter().cloned());
+                    path.names.insert(depth - 1, name.to_string());
+                }
+            }
+        }
+    }
+
+    /// Forget `fid`, the server no longer knows it
+    pub(crate) fn untrack(&self, fid: u32) {
+        if let Some(resilience) = &self.inner.resilience {
+            resilience.paths().remove(&fid);
+        }
+    }
+}
+
+/// Recreate the fids in `paths` on `mux`, using `root` for the attaches and `afid` to
+/// authenticate them
+///
+/// A fid whose file is gone is left out, requests on it fail like those on any unknown fid.
+async fn restore(mux: &Mux, root: u32, afid: u32, paths: HashMap<u32, FidPath>) -> Result<()> {
+    type Tree = (String, String, Option<Arc<[u8]>>);
+    let mut trees: HashMap<Tree, Vec<(u32, FidPath)>> = HashMap::new();
+    for (fid, path) in paths {
+        let tree = (path.uname.clone(), path.aname.clone(), path.secret.clone());
+        trees.entry(tree).or_default().push((fid, path));
+    }
+
+    for ((uname, aname, secret), fids) in trees {
+        if let Some(secret) = &secret {
+            auth::authenticate(mux, afid, &uname, &aname, secret).await?;
+        }
+        let auth = if secret.is_some() { afid } else { P9_NOFID };
+        let attached = mux
+            .rpc(requests::tattach(root, auth, &uname, &aname))
+            .await?;
+        if secret.is_some() {
+            mux.rpc(Message::Tclunk(Tclunk { fid: afid })).await?;
+        }
+        match attached {
+            Message::Rattach(_) => {}
+            reply => return Err(crate::reply_error(reply)),
+        }
+
+        for (fid, path) in fids {
+            if let Err(e) = restore_fid(mux, root, fid, path).await {
+                tracing::debug!(fid, "failed to restore: {e}");
+            }
+        }
+
+        mux.rpc(Message::Tclunk(Tclunk { fid: root })).await?;
+    }
+
+    Ok(())
+}
+
+async fn restore_fid(mux: &Mux, root: u32, fid: u32, path: FidPath) -> Result<()> {
+    let mut from = root;
+    let chunks = path
+        .names
+        .chunks(P9_MAXWELEM)
+        .chain(path.names.is_empty().then_some(&[][..]));
+    for chunk in chunks {
+        let twalk = Twalk {
+            fid: from,
+            newfid: fid,
+            wnames: chunk.to_vec(),
+        };
+        match mux.rpc(Message::Twalk(twalk)).await? {
+            Message::Rwalk(rwalk) if rwalk.wqids.len() == chunk.len() => from = fid,
+            reply => {
+                if from == fid {
+                    mux.rpc(Message::Tclunk(Tclunk { fid })).await?;
+                }
+                return Err(crate::reply_error(reply));
+            }
+        }
+    }
+
+    if let Some(mode) = path.mode {
+        match mux.rpc(Message::Topen(Topen { fid, mode })).await? {
+            Message::Ropen(_) => {}
+            reply => return Err(crate::reply_error(reply)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::Arc;
+    use stowage_filesystems::memory;
+    use stowage_proto::{
+        consts::P9_DEFAULT_MSIZE, Dialect, FileMode, MessageCodec, MessageType, Rversion,
+        TaggedMessage, Twrite,
+    };
+    use stowage_service::{Authenticator, Plan9, SharedSecret};
+    use tokio::task::JoinHandle;
+    use tokio_util::codec::Framed;
+
+    /// Serves one shared tree, each dial over a new connection whose server task can be killed
+    #[derive(Clone)]
+    struct Server {
+        handler: Arc<memory::Handler>,
+        authenticator: Option<Arc<dyn Authenticator>>,
+        task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    }
+
+    impl Server {
+        fn new(authenticator: Option<Arc<dyn Authenticator>>) -> Self {
+            Self {
+                handler: Arc::new(memory::Handler::new()),
+                authenticator,
+                task: Arc::new(Mutex::new(None)),
+            }
+        }
+
+        fn dial(&self) -> tokio::io::DuplexStream {
+            let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+            let mut service = Plan9::new(server_end, self.handler.clone());
+            if let Some(authenticator) = &self.authenticator {
+                service = service.with_authenticator(authenticator.clone());
+            }
+            let task = tokio::spawn(async move {
+                let _ = service.run().await;
+            });
+            *self.task.lock().unwrap() = Some(task);
+            client_end
+        }
+
+        fn kill(&self) {
+            self.task.lock().unwrap().take().unwrap().abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn fids_survive_a_lost_connection() {
+        let server = Server::new(None);
+        let client = Client::with_redial({
+            let server = server.clone();
+            move || {
+                let transport = server.dial();
+                async move { Ok(transport) }
+            }
+        })
+        .await
+        .unwrap();
+
+        let root = client.attach("glenda", "").await.unwrap();
+        root.create_dir_all("a/b").await.unwrap();
+        let file = root
+            .create(
+                "a/b/file",
+                FileMode::from_unix_perm(0o644, false),
+                OpenMode::ReadWrite,
+            )
+            .await
+            .unwrap();
+        file.write_all_at(0, b"hello").await.unwrap();
+        let dir = root.walk("a").await.unwrap();
+        root.rename("a/b", "a/c").await.unwrap();
+
+        server.kill();
+        // the open file is reopened where it was renamed to
+        assert_eq!(file.read_to_end(0).await.unwrap(), b"hello");
+        assert!(dir.metadata("c/file").await.unwrap().is_file());
+
+        // a write only fails when it may have reached the lost connection
+        let (_, lost) = client.connection();
+        server.kill();
+        while !lost.is_closed() {
+            tokio::task::yield_now().await;
+        }
+        file.write_all_at(5, b" world").await.unwrap();
+        assert_eq!(root.read("a/c/file").await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn authenticated_attaches_survive_a_lost_connection() {
+        let server = Server::new(Some(Arc::new(SharedSecret::new("secret"))));
+        let client = Client::with_redial({
+            let server = server.clone();
+            move || {
+                let transport = server.dial();
+                async move { Ok(transport) }
+            }
+        })
+        .await
+        .unwrap();
+
+        let root = client
+            .attach_with_secret("glenda", "", b"secret")
+            .await
+            .unwrap();
+        root.write("file", b"hello").await.unwrap();
+
+        server.kill();
+        assert_eq!(root.read("file").await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn interrupted_writes_are_not_sent_again() {
+        // a server that hangs up on the first request it gets
+        let client = Client::with_redial(|| {
+            let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(async move {
+                let mut server = Framed::new(server_end, MessageCodec::new());
+                let tversion = server.next().await.unwrap().unwrap();
+                let rversion = Rversion {
+                    msize: P9_DEFAULT_MSIZE,
+                    version: Dialect::Base.as_str().to_string(),
+                };
+                let reply = TaggedMessage::new(tversion.tag, Message::Rversion(rversion));
+                server.send(reply).await.unwrap();
+                server.next().await;
+            });
+            async move { Ok(client_end) }
+        })
+        .await
+        .unwrap();
+
+        let twrite = Twrite {
+            fid: 0,
+            offset: 0,
+            data: Bytes::from_static(b"data"),
+        };
+        assert!(matches!(
+            client.rpc(Message::Twrite(twrite)).await,
+            Err(Error::Interrupted(MessageType::Twrite))
+        ));
+        // a read is tried again on every new connection until the retries run out
+        assert!(matches!(
+            client
+                .rpc(Message::Tstat(stowage_proto::Tstat { fid: 0 }))
+                .await,
+            Err(Error::ConnectionClosed)
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{File, Result};
+use bytes::{Buf, Bytes};
+use futures::future::BoxFuture;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+/// An opened file read and written through tokio's I/O traits
+///
+/// Every read or write moves at most an iounit, at the current position. Writes are sent in the
+/// background like those of `tokio::fs::File`, their errors show up on the next operation or on
+/// flush. The fid is clunked once the handle is dropped and a write still in flight is done.
+pub struct RemoteFile {
+    file: Arc<File>,
+    pos: u64,
+    /// data read ahead of `pos`
+    buffer: Bytes,
+    pending: Option<Pending>,
+}
+
+/// The operation in flight
+enum Pending {
+    Read(BoxFuture<'static, Result<Bytes>>),
+    Write(BoxFuture<'static, Result<()>>),
+    Seek(BoxFuture<'static, io::Result<u64>>),
+}
+
+impl RemoteFile {
+    #[must_use]
+    pub fn new(file: File) -> Self {
+        Self {
+            file: Arc::new(file),
+            pos: 0,
+            buffer: Bytes::new(),
+            pending: None,
+        }
+    }
+
+    /// The position the next read or write happens at
+    #[must_use]
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    #[must_use]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Drive the operation in flight to completion
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = match &mut self.pending {
+            None => return Poll::Ready(Ok(())),
+            Some(Pending::Read(read)) => ready!(read.as_mut().poll(cx)).map(|data| {
+                self.buffer = data;
+            }),
+            Some(Pending::Write(write)) => ready!(write.as_mut().poll(cx)),
+            Some(Pending::Seek(seek)) => {
+                let result = ready!(seek.as_mut().poll(cx));
+                self.pending = None;
+                self.pos = result?;
+                return Poll::Ready(Ok(()));
+            }
+        };
+
+        self.pending = None;
+        Poll::Ready(result.map_err(io::Error::from))
+    }
+}
+
+impl From<File> for RemoteFile {
+    fn from(file: File) -> Self {
+        Self::new(file)
+    }
+}
+
+impl AsyncRead for RemoteFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.buffer.is_empty() {
+            if !matches!(this.pending, Some(Pending::Read(_))) {
+                ready!(this.poll_pending(cx))?;
+                if buf.remaining() == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+
+                let file = this.file.clone();
+                let (offset, count) = (this.pos, u32::try_from(buf.remaining()).unwrap_or(!0));
+                this.pending = Some(Pending::Read(Box::pin(async move {
+                    file.read_at(offset, count).await
+                })));
+            }
+            // nothing read is the end of the file
+            ready!(this.poll_pending(cx))?;
+        }
+
+        let count = this.buffer.len().min(buf.remaining());
+        buf.put_slice(&this.buffer[..count]);
+        this.buffer.advance(count);
+        this.pos += count as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for RemoteFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        this.buffer.clear();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let count = buf.len().min(this.file.iounit() as usize);
+        let data = Bytes::copy_from_slice(&buf[..count]);
+        let file = this.file.clone();
+        let offset = this.pos;
+        this.pending = Some(Pending::Write(Box::pin(async move {
+            file.write_all_at(offset, &data).await
+        })));
+        this.pos += count as u64;
+        Poll::Ready(Ok(count))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl AsyncSeek for RemoteFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        if this.pending.is_some() {
+            return Err(io::Error::other(
+                "other file operation is pending, call poll_complete before start_seek",
+            ));
+        }
+
+        this.buffer.clear();
+        match position {
+            SeekFrom::Start(offset) => this.pos = offset,
+            SeekFrom::Current(delta) => this.pos = seek_offset(this.pos, delta)?,
+            SeekFrom::End(delta) => {
+                let file = this.file.clone();
+                this.pending = Some(Pending::Seek(Box::pin(async move {
+                    seek_offset(file.stat().await?.length, delta)
+                })));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Poll::Ready(Ok(this.pos))
+    }
+}
+
+impl Drop for RemoteFile {
+    fn drop(&mut self) {
+        // a write already reported as done has to land, it keeps the fid alive until then
+        if let Some(Pending::Write(write)) = self.pending.take() {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move {
+                    if let Err(e) = write.await {
+                        tracing::debug!("failed to write behind: {e}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+pub(crate) fn seek_offset(base: u64, delta: i64) -> io::Result<u64> {
+    base.checked_add_signed(delta).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use stowage_filesystems::memory;
+    use stowage_proto::{FileMode, OpenMode};
+    use stowage_service::Plan9;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn copies_in_and_out_and_seeks() {
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let handler = Arc::new(memory::Handler::new());
+        tokio::spawn(Plan9::new(server_end, handler).run());
+        let client = Client::new(client_end).await.unwrap();
+        let root = client.attach("glenda", "").await.unwrap();
+
+        let data: Vec<u8> = (0..5 * client.msize()).map(|i| (i % 253) as u8).collect();
+        let file = root
+            .create(
+                "file",
+                FileMode::from_unix_perm(0o644, false),
+                OpenMode::ReadWrite,
+            )
+            .await
+            .unwrap();
+        let mut file = RemoteFile::new(file);
+
+        let copied = tokio::io::copy(&mut &data[..], &mut file).await.unwrap();
+        assert_eq!(copied, data.len() as u64);
+        file.flush().await.unwrap();
+        assert_eq!(file.position(), data.len() as u64);
+
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents, data);
+
+        let end = file.seek(SeekFrom::End(-10)).await.unwrap();
+        assert_eq!(end, data.len() as u64 - 10);
+        let mut tail = [0; 10];
+        file.read_exact(&mut tail).await.unwrap();
+        assert_eq!(tail, data[data.len() - 10..]);
+        assert!(file
+            .seek(SeekFrom::Current(-i64::try_from(data.len()).unwrap() - 1))
+            .await
+            .is_err());
+
+        // overwriting in the middle leaves the rest alone
+        file.seek(SeekFrom::Start(1)).await.unwrap();
+        file.write_all(b"xyz").await.unwrap();
+        file.shutdown().await.unwrap();
+        drop(file);
+
+        let reread = root.open("file", OpenMode::Read).await.unwrap();
+        let contents = reread.read_to_end(0).await.unwrap();
+        assert_eq!(&contents[1..4], b"xyz");
+        assert_eq!(contents[4..], data[4..]);
+    }
+}
@@ -0,0 +1,219 @@
+//! The requests and reply checks the async and blocking clients share
+//!
+//! Nothing here does IO, the clients send the requests and hand back the replies in their own
+//! way.
+
+use crate::file::is_not_found;
+use crate::{reply_error, Error, Result};
+use bytes::Bytes;
+use stowage_proto::{
+    auth::{self, CHALLENGE_LEN, RESPONSE_LEN},
+    consts::{P9_MAXWELEM, P9_NONUNAME},
+    Message, MessageType, Qid, Tattach, Tauth, Tread, Twalk, Twrite,
+};
+
+pub(crate) fn tauth(afid: u32, uname: &str, aname: &str) -> Message {
+    Message::Tauth(Tauth {
+        afid,
+        uname: uname.to_string(),
+        aname: aname.to_string(),
+        n_uname: P9_NONUNAME,
+    })
+}
+
+pub(crate) fn tattach(fid: u32, afid: u32, uname: &str, aname: &str) -> Message {
+    Message::Tattach(Tattach {
+        fid,
+        afid,
+        uname: uname.to_string(),
+        aname: aname.to_string(),
+        n_uname: P9_NONUNAME,
+    })
+}
+
+/// Whether the reply to a Tauth says the server wants the attach authenticated
+///
+/// # Errors
+/// - the Tauth failed for another reason than the server answering it
+pub(crate) fn auth_required(reply: Result<Message>) -> Result<bool> {
+    match reply {
+        // an error is what a server without authentication answers
+        Err(Error::Server { .. }) => Ok(false),
+        Ok(_) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// The qid of the root attached to by a Tattach answered with `reply`
+///
+/// # Errors
+/// - the Tattach failed
+pub(crate) fn rattach(reply: Result<Message>) -> Result<Qid> {
+    match reply? {
+        Message::Rattach(rattach) => Ok(rattach.qid),
+        reply => Err(reply_error(reply)),
+    }
+}
+
+/// The reads of a challenge from an afid and the writes of the response to it
+pub(crate) struct Challenge<'a> {
+    afid: u32,
+    uname: &'a str,
+    aname: &'a str,
+    secret: &'a [u8],
+    /// the challenge read so far
+    read: Vec<u8>,
+    /// the response once the whole challenge was read
+    response: Option<[u8; RESPONSE_LEN]>,
+    written: usize,
+}
+
+impl<'a> Challenge<'a> {
+    pub(crate) fn new(afid: u32, uname: &'a str, aname: &'a str, secret: &'a [u8]) -> Self {
+        Self {
+            afid,
+            uname,
+            aname,
+            secret,
+            read: Vec::with_capacity(CHALLENGE_LEN),
+            response: None,
+            written: 0,
+        }
+    }
+
+    /// The next Tread or Twrite to send, none once the challenge is answered
+    pub(crate) fn request(&self) -> Option<Message> {
+        match &self.response {
+            None => Some(Message::Tread(Tread {
+                fid: self.afid,
+                offset: self.read.len() as u64,
+                // unwrap - CHALLENGE_LEN is small
+                count: u32::try_from(CHALLENGE_LEN - self.read.len()).unwrap(),
+            })),
+            Some(response) if self.written < response.len() => Some(Message::Twrite(Twrite {
+                fid: self.afid,
+                offset: self.written as u64,
+                data: Bytes::copy_from_slice(&response[self.written..]),
+            })),
+            Some(_) => None,
+        }
+    }
+
+    /// Take the reply to the last request
+    ///
+    /// # Errors
+    /// - the server failed the request, or read or wrote nothing
+    pub(crate) fn reply(&mut self, reply: Message) -> Result<()> {
+        match reply {
+            Message::Rread(rread) if self.response.is_none() && !rread.data.is_empty() => {
+                self.read.extend_from_slice(&rread.data);
+                if self.read.len() >= CHALLENGE_LEN {
+                    let response = auth::respond(self.secret, &self.read, self.uname, self.aname);
+                    self.response = Some(response);
+                }
+            }
+            Message::Rwrite(rwrite) if self.response.is_some() && rwrite.count > 0 => {
+                self.written += rwrite.count as usize;
+            }
+            reply => return Err(reply_error(reply)),
+        }
+
+        Ok(())
+    }
+}
+
+/// A walk of `names` from a fid to a new fid, in Twalks of at most MAXWELEM names
+///
+/// The new fid is only bound when every name was walked. Even walking no names sends a Twalk,
+/// which clones the fid.
+pub(crate) struct Walk<'a> {
+    names: &'a [String],
+    path: &'a str,
+    newfid: u32,
+    /// the fid the next Twalk starts from, the new fid after the first one
+    from: u32,
+    qid: Qid,
+    walked: usize,
+    started: bool,
+}
+
+impl<'a> Walk<'a> {
+    pub(crate) fn new(names: &'a [String], path: &'a str, fid: u32, qid: Qid, newfid: u32) -> Self {
+        Self {
+            names,
+            path,
+            newfid,
+            from: fid,
+            qid,
+            walked: 0,
+            started: false,
+        }
+    }
+
+    /// The next Twalk to send, none once every name was walked
+    pub(crate) fn request(&self) -> Option<Message> {
+        if self.started && self.walked == self.names.len() {
+            return None;
+        }
+
+        Some(Message::Twalk(Twalk {
+            fid: self.from,
+            newfid: self.newfid,
+            wnames: self.chunk().to_vec(),
+        }))
+    }
+
+    /// Take the reply to the last Twalk, returning the fid it started from and the names it
+    /// walked
+    ///
+    /// # Errors
+    /// - a name was not found
+    /// - the server failed the walk for another reason
+    pub(crate) fn reply(&mut self, reply: Result<Message>) -> Result<(u32, &'a [String])> {
+        let reply = reply.and_then(|reply| match reply {
+            Message::Rwalk(rwalk) => Ok(rwalk.wqids),
+            reply => Err(reply_error(reply)),
+        });
+        let wqids = match reply {
+            Ok(wqids) => wqids,
+            // a walk failing on its first name is answered with an error
+            Err(Error::Server { ename, errno }) if is_not_found(&ename, errno) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let chunk = self.chunk();
+        if wqids.len() > chunk.len() {
+            return Err(Error::UnexpectedReply(MessageType::Rwalk));
+        }
+        if wqids.len() < chunk.len() {
+            let walked = self.walked + wqids.len();
+            return Err(Error::NotFound {
+                path: self.path.to_string(),
+                name: self.names[walked].clone(),
+                walked,
+            });
+        }
+
+        let from = self.from;
+        self.qid = wqids.last().unwrap_or(&self.qid).clone();
+        self.walked += chunk.len();
+        self.from = self.newfid;
+        self.started = true;
+        Ok((from, chunk))
+    }
+
+    /// Whether the new fid is bound where a failed walk stopped, and must be clunked
+    pub(crate) fn bound(&self) -> bool {
+        self.from == self.newfid
+    }
+
+    /// The qid of the file walked to
+    pub(crate) fn qid(self) -> Qid {
+        self.qid
+    }
+
+    fn chunk(&self) -> &'a [String] {
+        let end = self.names.len().min(self.walked + P9_MAXWELEM);
+        &self.names[self.walked..end]
+    }
+}
@@ -26,6 +26,10 @@ pub(crate) struct MountCommand {
     /// - `tcp!host!port`
     ///
     /// - `unix!socket`
+    ///
+    /// - `net!host!9fs`
+    ///
+    /// - `-`
     pub mount_point: PathBuf,
 
     #[arg(long, short)]
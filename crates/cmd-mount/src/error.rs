@@ -26,16 +26,12 @@ pub enum Error {
     Unmount(PathBuf, String),
     #[error(transparent)]
     Nix(#[from] nix::Error),
-    #[error("Empty dial string")]
-    EmptyDialString,
-    #[error("{0}: unknown network (expecting unix, tcp, or virtio)")]
-    UnknownNetwork(String),
-    #[error("Missing dial address")]
-    MissingDialAddress,
+    #[error(transparent)]
+    Dial(#[from] stowage_proto::dial::ParseError),
+    #[error("{0}: the kernel cannot mount over TLS")]
+    TlsUnsupported(String),
     #[error("{0}: cannot access socket")]
     SocketAccess(String),
-    #[error("{0}: invalid port")]
-    InvalidPort(String),
     #[error("{0}: could not resolve hostname")]
     HostResolution(String),
     #[error("{0}: username contains commas")]
@@ -44,10 +40,6 @@ pub enum Error {
     InvalidAname(String),
     #[error("{0}: msize must be a positive integer")]
     InvalidMsize(u32),
-    #[error("{0}: IPv6 address not supported for 9p mounts")]
-    IPv6NotSupported(String),
-    #[error("{0}: No IPv4 address found for hostname")]
-    NoIPv4Address(String),
     #[error("Mount error: {0}")]
     Mount(String),
     #[error(transparent)]
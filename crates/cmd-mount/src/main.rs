@@ -13,8 +13,9 @@ use std::{
     ffi::CString,
     fs::File,
     io::{BufRead, BufReader},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
+use stowage_proto::dial::DialString;
 use tracing::info;
 
 mod commands;
@@ -75,7 +76,6 @@ fn main() -> Result<()> {
                     continue; // invalid line format
                 }
 
-                let _mount_source = fields[0];
                 let mount_point = fields[1];
                 let mount_type = fields[2];
                 let mount_options = fields[3];
@@ -138,16 +138,6 @@ fn do_mount(mount: &commands::MountCommand) -> Result<()> {
         return Err(Error::StickyDirectory(mount.mount_point.clone()));
     }
 
-    let parts: Vec<&str> = mount.dial.split('!').collect();
-    if parts.is_empty() {
-        return Err(Error::EmptyDialString);
-    }
-
-    let proto = parts[0];
-    if proto != "unix" && proto != "tcp" && proto != "virtio" && proto != "-" {
-        return Err(Error::UnknownNetwork(proto.to_string()));
-    }
-
     let transport = parse_dial_string(&mount.dial)?;
     let mut options = transport.options;
 
@@ -254,64 +244,38 @@ fn do_mount(mount: &commands::MountCommand) -> Result<()> {
 
 /// Parse a 9P dial string and return (transport, address)
 fn parse_dial_string(dial: &str) -> Result<TransportContext> {
-    let parts: Vec<&str> = dial.split('!').collect();
-    if parts.is_empty() {
-        return Err(Error::EmptyDialString);
-    }
-
-    let proto = parts[0];
-    if proto != "unix" && proto != "tcp" && proto != "virtio" && proto != "-" {
-        return Err(Error::UnknownNetwork(proto.to_string()));
-    }
-
-    let mut context = if proto == "-" {
-        return Ok(TransportContext {
-            addr: "nodev".to_string(),
-            options: vec!["rfdno=0".to_string(), "wfdno=1".to_string()],
-            trans: "fd".to_string(),
-        });
-    } else {
-        // need address part for all other protocols
-        if parts.len() < 2 {
-            return Err(Error::MissingDialAddress);
+    let mut context = match dial.parse()? {
+        DialString::Stdio => {
+            return Ok(TransportContext {
+                addr: "nodev".to_string(),
+                options: vec!["rfdno=0".to_string(), "wfdno=1".to_string()],
+                trans: "fd".to_string(),
+            })
         }
+        DialString::Unix(path) => {
+            // for unix sockets, check accessibility
+            if access(&path, AccessFlags::R_OK | AccessFlags::W_OK).is_err() {
+                return Err(Error::SocketAccess(path.display().to_string()));
+            }
 
-        let addr = parts[1];
-        let mut options = Vec::new();
-
-        // for unix sockets, check accessibility
-        if proto == "unix"
-            && access(Path::new(addr), AccessFlags::R_OK | AccessFlags::W_OK).is_err()
-        {
-            return Err(Error::SocketAccess(addr.to_string()));
+            TransportContext {
+                addr: path.display().to_string(),
+                options: Vec::new(),
+                trans: "unix".to_string(),
+            }
         }
-
         // for TCP, resolve hostname and port
-        let address = if proto == "tcp" {
-            let port = if parts.len() > 2 {
-                match parts[2].parse::<u16>() {
-                    Ok(p) => p,
-                    Err(_) => return Err(Error::InvalidPort(parts[2].to_string())),
-                }
-            } else {
-                564 // default 9P port
-            };
-
-            let ip = resolve_to_ipv4(addr, port)?;
-
-            options.push(format!("port={port}"));
-
-            ip
-        } else {
-            // for unix and virtio, use address as is
-            addr.to_string()
-        };
-
-        TransportContext {
-            addr: address,
-            options,
-            trans: proto.to_string(),
-        }
+        DialString::Tcp { host, port } => TransportContext {
+            addr: resolve_to_ip(&host, port)?,
+            options: vec![format!("port={port}")],
+            trans: "tcp".to_string(),
+        },
+        dial @ DialString::Tls { .. } => return Err(Error::TlsUnsupported(dial.to_string())),
+        DialString::Virtio(tag) => TransportContext {
+            addr: tag,
+            options: Vec::new(),
+            trans: "virtio".to_string(),
+        },
     };
 
     context.options.push(format!("trans={}", context.trans));
@@ -324,30 +288,24 @@ struct TransportContext {
     trans: String,
 }
 
-fn resolve_to_ipv4(hostname: &str, port: u16) -> Result<String> {
+fn resolve_to_ip(hostname: &str, port: u16) -> Result<String> {
     use std::net::{IpAddr, ToSocketAddrs};
 
-    // First try direct IPv4 parsing - if it's already an IP address
-    if let Ok(ip) = hostname.parse::<std::net::IpAddr>() {
-        if let IpAddr::V4(ipv4) = ip {
-            return Ok(ipv4.to_string());
-        }
-
-        return Err(Error::IPv6NotSupported(hostname.to_string()));
+    // First try direct parsing - if it's already an IP address
+    if let Ok(ip) = hostname.parse::<IpAddr>() {
+        return Ok(ip.to_string());
     }
 
     // Otherwise, use DNS resolution with IPv4 preference
-    let socket_addrs = (hostname, port)
+    let socket_addrs: Vec<_> = (hostname, port)
         .to_socket_addrs()
-        .map_err(|_| Error::HostResolution(hostname.to_string()))?;
-
-    // Find the first IPv4 address
-    for addr in socket_addrs {
-        if let IpAddr::V4(ipv4) = addr.ip() {
-            return Ok(ipv4.to_string());
-        }
-    }
-
-    // No IPv4 addresses found
-    Err(Error::NoIPv4Address(hostname.to_string()))
+        .map_err(|_| Error::HostResolution(hostname.to_string()))?
+        .collect();
+
+    socket_addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or_else(|| socket_addrs.first())
+        .map(|addr| addr.ip().to_string())
+        .ok_or_else(|| Error::HostResolution(hostname.to_string()))
 }
@@ -1,628 +1,969 @@
-use std::collections::HashMap;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
-use std::path::{Path, PathBuf};
+// blocking work fails with the Rerror sent back to the client, as large as any other reply
+#![allow(clippy::result_large_err)]
+
+use crate::users::{Credentials, Users};
+use crate::{invalid_name, valid_name};
+use flagset::FlagSet;
+use nix::{
+    fcntl::{open, openat, openat2, renameat, OFlag, OpenHow, ResolveFlag},
+    sys::stat::{mkdirat, Mode},
+    unistd::{fchown, symlinkat, unlinkat, Gid, Uid, UnlinkatFlags},
+};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use stowage_proto::{
-    Encodable, FileMode, Message, OpenMode, Qid, QidType, Rattach, Rclunk, Rcreate, Rerror, Rflush,
-    Ropen, Rread, Rremove, Rstat, Rwalk, Rwrite, Rwstat, Stat, Tattach, Tclunk, Tcreate, Tflush,
-    Topen, Tread, Tremove, Tstat, Twalk, Twrite, Twstat,
+    consts::{
+        errno::{EACCES, EBUSY, EINVAL, EPERM, EXDEV},
+        P9_NONUNAME,
+    },
+    Dialect, FileMode, Message, OpenMode, Qid, QidType, Rattach, Rclunk, Rcreate, Ropen, Rread,
+    Rremove, Rstat, Rwalk, Rwrite, Rwstat, Stat, Tattach, Tclunk, Tcreate, Topen, Tread, Tremove,
+    Tstat, Twalk, Twrite, Twstat,
 };
-use stowage_service::MessageHandler;
-
+use stowage_service::{FidError, MessageHandler, Session};
+
+/// Serves a directory of the host filesystem
+///
+/// Every path is resolved beneath the exported directory with `openat2(RESOLVE_BENEATH)`, so
+/// neither `..` nor a symlink can reach anything outside of it. Filesystem calls run on tokio's
+/// blocking pool, so a slow disk only holds up the requests waiting on it.
+///
+/// Given a users database with `with_users`, every operation is also checked against the
+/// permissions of the files for the user attached as, and files are stat'ed with the names of
+/// their owner and group.
 pub struct Handler {
-    dir: PathBuf,
-    fids: Arc<Mutex<HashMap<u32, FidEntry>>>,
+    root: Root,
+    users: Option<Arc<dyn Users>>,
 }
 
-struct FidEntry {
+/// What the disk handler knows about a fid
+pub struct FidEntry {
+    /// relative to the exported directory, empty for the root itself
     path: PathBuf,
-    opened: bool,
     is_dir: bool,
     file: Option<File>,
+    cursor: Mutex<DirCursor>,
+    /// who the fid acts for, when permissions are checked
+    user: Option<Arc<Credentials>>,
 }
 
-impl Handler {
-    // accept a path to use as the root directory
-    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+impl FidEntry {
+    fn new(
+        path: PathBuf,
+        is_dir: bool,
+        file: Option<File>,
+        user: Option<Arc<Credentials>>,
+    ) -> Self {
         Self {
-            dir: dir.into(),
-            fids: Arc::new(Mutex::new(HashMap::new())),
+            path,
+            is_dir,
+            file,
+            cursor: Mutex::default(),
+            user,
         }
     }
 
-    // helper methods
-    fn path_for_fid(&self, fid: u32) -> Result<PathBuf, io::Error> {
-        let fids = self.fids.lock().unwrap();
-        match fids.get(&fid) {
-            Some(entry) => Ok(entry.path.clone()),
-            None => Err(io::Error::new(io::ErrorKind::NotFound, "fid not found")),
+    /// Refuse unless the fid's user has the permissions `want` on what `metadata` describes
+    fn check(&self, metadata: &fs::Metadata, want: u32) -> Result<(), Message> {
+        check(self.user.as_deref(), metadata, want)
+    }
+}
+
+/// The permission bits asked for, as in the `other` bits of a mode
+const READ: u32 = 0o4;
+const WRITE: u32 = 0o2;
+const EXEC: u32 = 0o1;
+
+/// Refuse unless `user` has the permissions `want` on what `metadata` describes
+fn check(user: Option<&Credentials>, metadata: &fs::Metadata, want: u32) -> Result<(), Message> {
+    match user {
+        Some(user) if !user.permits(metadata.mode(), metadata.uid(), metadata.gid(), want) => {
+            Err(permission_denied())
         }
+        _ => Ok(()),
     }
 }
 
-impl MessageHandler for Handler {
-    async fn attach(&self, message: &Tattach) -> Message {
-        // establish a new fid that points to the root directory
-        let root_path = self.dir.clone();
+/// Refuse unless `user` owns what `metadata` describes
+fn check_owner(user: Option<&Credentials>, metadata: &fs::Metadata) -> Result<(), Message> {
+    match user {
+        Some(user) if user.uid != 0 && user.uid != metadata.uid() => {
+            Err(Message::error_with_errno("not owner".to_string(), EPERM))
+        }
+        _ => Ok(()),
+    }
+}
 
-        // verify the root directory exists
-        match fs::metadata(&root_path) {
-            Ok(metadata) => {
-                if !metadata.is_dir() {
-                    return Message::Rerror(Rerror {
-                        ename: "Not a directory".to_string(),
-                    });
-                }
+fn permission_denied() -> Message {
+    Message::error_with_errno("permission denied".to_string(), EACCES)
+}
 
-                // create a qid for the root directory
-                let qid = create_qid_from_metadata(&metadata);
+/// The permissions to open a file with `mode`
+fn open_permissions(mode: FlagSet<OpenMode>) -> u32 {
+    let want = match mode.bits() & 3 {
+        0 => READ,
+        1 => WRITE,
+        2 => READ | WRITE,
+        _ => EXEC,
+    };
+    if mode.contains(OpenMode::Trunc) {
+        want | WRITE
+    } else {
+        want
+    }
+}
 
-                // store this fid in our mapping
-                let mut fids = self.fids.lock().unwrap();
-                fids.insert(
-                    message.fid,
-                    FidEntry {
-                        path: root_path,
-                        opened: false,
-                        is_dir: true,
-                        file: None,
-                    },
-                );
-
-                Message::Rattach(Rattach { qid })
-            }
-            Err(e) => Message::Rerror(Rerror {
-                ename: format!("Cannot attach: {e}"),
-            }),
+/// How far a directory has been read, so the next read can carry on from the same offset
+#[derive(Default)]
+struct DirCursor {
+    /// the offset the next read must ask for, or 0 to start over
+    offset: u64,
+    entries: Option<fs::ReadDir>,
+    /// an encoded entry that did not fit in the previous read
+    pending: Option<Vec<u8>>,
+}
+
+/// The exported directory, cheap to clone into blocking tasks
+#[derive(Clone)]
+struct Root {
+    dir: Arc<Path>,
+}
+
+impl Handler {
+    // accept a path to use as the root directory
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            root: Root {
+                dir: dir.into().into(),
+            },
+            users: None,
         }
     }
 
-    async fn flush(&self, _: &Tflush) -> Message {
-        // flush doesn't need special file operations for this implementation
-        // it simply acknowledges the flush request
-        Message::Rflush(Rflush)
+    /// Check every operation against the permissions of the files for the user attached as
+    ///
+    /// The user is looked up in `users`, attaching as one it does not know is refused. What
+    /// users are permitted to do the server itself still needs the privileges for, files it
+    /// creates are only given to their user when it runs as root. The names in stats are looked
+    /// up there too, and a wstat may move a file to a group by its name.
+    #[must_use]
+    pub fn with_users(mut self, users: Arc<dyn Users>) -> Self {
+        self.users = Some(users);
+        self
     }
+}
 
-    async fn walk(&self, message: &Twalk) -> Message {
-        let fid = message.fid;
-        let newfid = message.newfid;
-        let wnames = message.wnames.clone();
+impl Root {
+    /// Open `path` without letting its resolution leave the exported directory
+    ///
+    /// Symlinks are followed while they stay beneath the root, absolute ones and any that climb
+    /// out of it fail with EXDEV.
+    fn open_beneath(&self, path: &Path, flags: OFlag) -> io::Result<File> {
+        let root = File::open(&self.dir)?;
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        let how = OpenHow::new()
+            .flags(flags | OFlag::O_CLOEXEC)
+            .resolve(ResolveFlag::RESOLVE_BENEATH | ResolveFlag::RESOLVE_NO_MAGICLINKS);
+        Ok(File::from(openat2(&root, path, how)?))
+    }
 
-        // get the source path
-        let Ok(source_path) = self.path_for_fid(fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
-        };
-
-        // if newfid differs from fid, clone the fid
-        if fid != newfid {
-            let mut fids = self.fids.lock().unwrap();
-            // clone the path and check if source exists before attempting to insert
-            let entry_opt = fids.get(&fid);
-            if let Some(entry) = entry_opt {
-                // clone the needed values outside the borrow
-                let path_clone = entry.path.clone();
-                let is_dir = entry.is_dir;
-
-                // now insert without borrowing entry
-                fids.insert(
-                    newfid,
-                    FidEntry {
-                        path: path_clone,
-                        opened: false,
-                        is_dir,
-                        file: None,
-                    },
-                );
-            } else {
-                return Message::Rerror(Rerror {
-                    ename: "Fid not found".to_string(),
-                });
-            }
-        }
+    fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+        self.open_beneath(path, OFlag::O_PATH)?.metadata()
+    }
 
-        // empty walk is just a fid clone
-        if wnames.is_empty() {
-            return Message::Rwalk(Rwalk { wqids: vec![] });
-        }
+    /// Open the directory holding `path` for operations on the entry itself
+    fn open_parent<'a>(&self, path: &'a Path) -> io::Result<(File, &'a OsStr)> {
+        // the root has no entry to operate on
+        let Some(name) = path.file_name() else {
+            return Err(io::Error::from_raw_os_error(EBUSY.cast_signed()));
+        };
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let dir = self.open_beneath(parent, OFlag::O_PATH | OFlag::O_DIRECTORY)?;
+        Ok((dir, name))
+    }
 
-        // walk through each path component
+    /// Walk `wnames` from `source`, returning the qids walked and where the walk ended up
+    fn walk(
+        &self,
+        source: &FidEntry,
+        wnames: &[String],
+    ) -> Result<(Vec<Qid>, PathBuf, bool), Message> {
         let mut wqids = Vec::with_capacity(wnames.len());
-        let mut current_path = source_path;
+        let mut current_path = source.path.clone();
+        let mut is_dir = source.is_dir;
+        // the directory being walked from, once it had to be looked at
+        let mut dir_metadata = None;
 
-        for wname in &wnames {
-            current_path = current_path.join(wname);
+        for wname in wnames {
+            if !is_dir {
+                if wqids.is_empty() {
+                    return Err(FidError::WalkNonDirectory.into());
+                }
+                break;
+            }
 
-            if let Ok(metadata) = fs::metadata(&current_path) {
-                let qid = create_qid_from_metadata(&metadata);
-                wqids.push(qid);
+            // walking out of a directory needs search permission on it
+            if source.user.is_some() {
+                let metadata = match dir_metadata.take() {
+                    Some(metadata) => metadata,
+                    None => self
+                        .metadata(&current_path)
+                        .map_err(|e| io_error("Cannot walk", &e))?,
+                };
+                if let Err(denied) = source.check(&metadata, EXEC) {
+                    if wqids.is_empty() {
+                        return Err(denied);
+                    }
+                    break;
+                }
+            }
+
+            // ".." is resolved lexically, at the root it names the root itself
+            if wname == ".." {
+                current_path.pop();
+            } else if valid_name(wname) {
+                current_path.push(wname);
+            } else if wqids.is_empty() {
+                return Err(invalid_name());
             } else {
+                break;
+            }
+
+            match self.metadata(&current_path) {
+                Ok(metadata) => {
+                    wqids.push(create_qid_from_metadata(&metadata));
+                    is_dir = metadata.is_dir();
+                    dir_metadata = Some(metadata);
+                }
                 // path component not found, return what we have
-                if wqids.is_empty() {
-                    return Message::Rerror(Rerror {
-                        ename: "File not found".to_string(),
-                    });
+                Err(_) if !wqids.is_empty() => break,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(FidError::NotFound.into());
                 }
-                break;
+                Err(e) => return Err(io_error("Cannot walk", &e)),
             }
         }
 
-        // if we successfully walked all components, update the newfid's path
-        if wqids.len() == wnames.len() {
-            let mut fids = self.fids.lock().unwrap();
-            if let Some(entry) = fids.get_mut(&newfid) {
-                entry.path.clone_from(&current_path);
-                entry.is_dir = fs::metadata(&current_path)
-                    .map(|m| m.is_dir())
-                    .unwrap_or(false);
+        Ok((wqids, current_path, is_dir))
+    }
+
+    /// Open the file of `source` with `mode`, directories are only ever opened to read their
+    /// entries
+    ///
+    /// The permissions are checked on the file found at the path, which is then opened again
+    /// through its descriptor, so a file swapped in under the name meanwhile is never opened.
+    fn open(&self, source: &FidEntry, mode: FlagSet<OpenMode>) -> Result<(Qid, FidEntry), Message> {
+        let path = &source.path;
+        let found = self
+            .open_beneath(path, OFlag::O_PATH)
+            .map_err(|e| io_error("Cannot open file", &e))?;
+        let metadata = found
+            .metadata()
+            .map_err(|e| io_error("Cannot stat file", &e))?;
+        source.check(&metadata, open_permissions(mode))?;
+
+        let flags = if metadata.is_dir() {
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY
+        } else {
+            open_flags(mode)
+        };
+        let file = open(&proc_path(&found), flags | OFlag::O_CLOEXEC, Mode::empty())
+            .map(File::from)
+            .map_err(|e| io_error("Cannot open file", &e.into()))?;
+
+        // a truncating open changed the file since it was looked at
+        match file.metadata() {
+            Ok(metadata) => {
+                let is_dir = metadata.is_dir();
+                let user = source.user.clone();
+                let qid = create_qid_from_metadata(&metadata);
+                let entry = FidEntry::new(path.clone(), is_dir, Some(file), user);
+                Ok((qid, entry))
             }
+            Err(e) => Err(io_error("Cannot stat file", &e)),
         }
-
-        Message::Rwalk(Rwalk { wqids })
     }
 
-    async fn open(&self, message: &Topen) -> Message {
-        let fid = message.fid;
-        let mode = message.mode;
+    /// Create `message.name` in the directory of `source` and open it
+    fn create(&self, source: &FidEntry, message: &Tcreate) -> Result<(Qid, FidEntry), Message> {
+        let dir_path = &source.path;
+        let name = message.name.as_str();
 
-        let Ok(path) = self.path_for_fid(fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
+        // the parent must be a directory beneath the root
+        let dir = match self.open_beneath(dir_path, OFlag::O_PATH | OFlag::O_DIRECTORY) {
+            Ok(dir) => dir,
+            Err(e) => return Err(io_error("Cannot open directory", &e)),
+        };
+        let dir_metadata = match dir.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => return Err(io_error("Cannot stat directory", &e)),
         };
+        source.check(&dir_metadata, WRITE)?;
 
-        // convert 9P2000 open modes to rust file open options
-        let mut options = OpenOptions::new();
+        if !valid_name(name) {
+            return Err(invalid_name());
+        }
 
-        match mode {
-            mode if mode.contains(OpenMode::Read) && !mode.contains(OpenMode::Write) => {
-                options.read(true);
-            }
-            mode if mode.contains(OpenMode::Write) && !mode.contains(OpenMode::Read) => {
-                options.write(true);
-            }
-            mode if mode.contains(OpenMode::ReadWrite)
-                || (mode.contains(OpenMode::Read) && mode.contains(OpenMode::Write)) =>
-            {
-                options.read(true).write(true);
-            }
-            mode if mode.contains(OpenMode::Exec) => {
-                options.read(true); // Execute mode is just read in this implementation
-            }
-            _ => {
-                return Message::Rerror(Rerror {
-                    ename: "Invalid open mode".to_string(),
-                })
+        // prepare the new file path
+        let file_path = dir_path.join(name);
+        let mut perm = message.perm.bits() & 0o777;
+        // as in Plan 9, a checked user cannot grant more than the directory does
+        if source.user.is_some() {
+            let inherited = if message.perm.contains(FileMode::Dir) {
+                0o777
+            } else {
+                0o666
+            };
+            perm &= !inherited | (dir_metadata.mode() & inherited);
+        }
+        let perm = Mode::from_bits_truncate(perm);
+
+        // 9P2000.u symlinks carry their target in the extension
+        if message.perm.contains(FileMode::Symlink) {
+            if !target_beneath(dir_path, &message.extension) {
+                return Err(Message::error_with_errno(
+                    "symlink target outside the exported tree".to_string(),
+                    EXDEV,
+                ));
             }
+            return self.create_symlink(&dir, file_path, message, source.user.clone());
         }
 
-        // attempt to open the file
-        let result = if fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
-            // for directories, just get metadata and don't actually open a file
-            Ok(None)
+        if message.perm.contains(FileMode::Device)
+            || message.perm.contains(FileMode::NamedPipe)
+            || message.perm.contains(FileMode::Socket)
+        {
+            return Err(Message::error("Cannot create special files".to_string()));
+        }
+
+        // handle directory creation
+        let (result, is_dir) = if message.perm.contains(FileMode::Dir) {
+            let result = mkdirat(&dir, name, perm)
+                .map_err(io::Error::from)
+                .and_then(|()| self.open_beneath(&file_path, OFlag::O_RDONLY | OFlag::O_DIRECTORY));
+            (result, true)
         } else {
-            options.open(&path).map(Some)
+            // an existing file is not taken over, nor a symlink planted under the name followed
+            // out of the root
+            let flags = open_flags(message.mode)
+                | OFlag::O_CREAT
+                | OFlag::O_EXCL
+                | OFlag::O_NOFOLLOW
+                | OFlag::O_CLOEXEC;
+            let result = openat(&dir, name, flags, perm)
+                .map(File::from)
+                .map_err(io::Error::from);
+            (result, false)
         };
 
         match result {
-            Ok(file_opt) => {
-                match fs::metadata(&path) {
+            Ok(file) => {
+                // set permissions, the umask does not apply to them
+                let _ = file.set_permissions(fs::Permissions::from_mode(perm.bits()));
+                // the file is the user's, in the group of its directory
+                if let Some(user) = &source.user {
+                    give(&file, user, &dir_metadata);
+                }
+
+                match file.metadata() {
                     Ok(metadata) => {
                         let qid = create_qid_from_metadata(&metadata);
-                        let is_dir = metadata.is_dir();
-
-                        // update the fid entry
-                        let mut fids = self.fids.lock().unwrap();
-                        if let Some(entry) = fids.get_mut(&fid) {
-                            entry.opened = true;
-                            entry.is_dir = is_dir;
-                            entry.file = file_opt;
-                        }
-
-                        // reasonable iounit size
-                        let iounit = 4096;
-
-                        Message::Ropen(Ropen { qid, iounit })
+                        let entry =
+                            FidEntry::new(file_path, is_dir, Some(file), source.user.clone());
+                        Ok((qid, entry))
                     }
-                    Err(e) => Message::Rerror(Rerror {
-                        ename: format!("Cannot stat file: {e}"),
-                    }),
+                    Err(e) => Err(io_error("Cannot stat new file", &e)),
                 }
             }
-            Err(e) => Message::Rerror(Rerror {
-                ename: format!("Cannot open file: {e}"),
-            }),
+            Err(e) if is_dir => Err(io_error("Cannot create directory", &e)),
+            Err(e) => Err(io_error("Cannot create file", &e)),
         }
     }
 
-    async fn create(&self, message: &Tcreate) -> Message {
-        let name = message.name.clone();
-
-        let Ok(dir_path) = self.path_for_fid(message.fid) else {
-            return Message::error("Fid not found".to_string());
-        };
-
-        // check if the parent is a directory
-        match fs::metadata(&dir_path) {
+    fn create_symlink(
+        &self,
+        dir: &File,
+        path: PathBuf,
+        message: &Tcreate,
+        user: Option<Arc<Credentials>>,
+    ) -> Result<(Qid, FidEntry), Message> {
+        let target = message.extension.as_str();
+        let result = symlinkat(target, dir, message.name.as_str())
+            .map_err(io::Error::from)
+            .and_then(|()| self.open_beneath(&path, OFlag::O_PATH | OFlag::O_NOFOLLOW))
+            .and_then(|link| link.metadata());
+        match result {
             Ok(metadata) => {
-                if !metadata.is_dir() {
-                    return Message::Rerror(Rerror {
-                        ename: "Not a directory".to_string(),
-                    });
-                }
+                let qid = create_qid_from_metadata(&metadata);
+                Ok((qid, FidEntry::new(path, false, None, user)))
             }
-            Err(e) => return Message::error(format!("Cannot stat directory: {e}")),
+            Err(e) => Err(io_error("Cannot create symlink", &e)),
         }
+    }
 
-        // prepare the new file path
-        let file_path = dir_path.join(&name);
-
-        // convert 9P mode to rust file open options
-        let mut options = OpenOptions::new();
-        options.create(true);
-        match message.mode {
-            mode if mode.contains(OpenMode::Read) && !mode.contains(OpenMode::Write) => {
-                options.read(true);
+    /// Apply the fields of `stat` that are not "don't touch" to the file at `entry.path`
+    ///
+    /// Returns the entry the fid should refer to when the file was renamed.
+    fn wstat(
+        &self,
+        entry: &FidEntry,
+        stat: &Stat,
+        users: Option<&dyn Users>,
+    ) -> Result<Option<FidEntry>, Message> {
+        let path = &entry.path;
+        let mut error = None;
+        let mut renamed = None;
+        let gid = new_gid(users, stat)?;
+
+        // nothing is changed unless all of it may be
+        if let Some(user) = entry.user.as_deref() {
+            self.check_wstat(path, user, stat, gid)?;
+        }
+
+        // change permissions if mode is not ~0
+        if stat.mode != FileMode::DontTouch {
+            // an O_PATH descriptor cannot be chmod'ed, go through its /proc link instead
+            let perms = fs::Permissions::from_mode(stat.mode.bits() & 0o777);
+            if let Err(e) = self
+                .open_beneath(path, OFlag::O_PATH)
+                .and_then(|file| fs::set_permissions(proc_path(&file), perms))
+            {
+                error = Some(e);
             }
-            mode if mode.contains(OpenMode::Write) && !mode.contains(OpenMode::Read) => {
-                options.write(true);
+        }
+
+        // change file size if length is not ~0
+        if error.is_none() && stat.length != 0xFFFF_FFFF_FFFF_FFFF {
+            if let Err(e) = self
+                .open_beneath(path, OFlag::O_WRONLY)
+                .and_then(|file| file.set_len(stat.length))
+            {
+                error = Some(e);
             }
-            mode if mode.contains(OpenMode::ReadWrite)
-                || (mode.contains(OpenMode::Read) && mode.contains(OpenMode::Write)) =>
+        }
+
+        // change group if one is named
+        if let (None, Some(gid)) = (&error, gid) {
+            if let Err(e) = self
+                .open_beneath(path, OFlag::O_PATH)
+                .and_then(|file| std::os::unix::fs::chown(proc_path(&file), None, Some(gid)))
             {
-                options.read(true).write(true);
+                error = Some(e);
             }
-            mode if mode.contains(OpenMode::Exec) => {
-                options.read(true); // Execute mode is just read in this implementation
+        }
+
+        // change name if not empty (rename file)
+        if error.is_none() && !stat.name.is_empty() && stat.name != "." && stat.name != ".." {
+            if !valid_name(&stat.name) {
+                return Err(invalid_name());
             }
-            _ => {
-                return Message::Rerror(Rerror {
-                    ename: "Invalid open mode".to_string(),
-                })
+            let new_path = path.with_file_name(&stat.name);
+
+            // the renamed entry shares any open file with the old one
+            let result = entry
+                .file
+                .as_ref()
+                .map(File::try_clone)
+                .transpose()
+                .and_then(|file| {
+                    let (dir, name) = self.open_parent(path)?;
+                    renameat(&dir, name, &dir, stat.name.as_str())?;
+                    Ok(file)
+                });
+            match result {
+                Ok(file) => {
+                    let user = entry.user.clone();
+                    renamed = Some(FidEntry::new(new_path, entry.is_dir, file, user));
+                }
+                Err(e) => error = Some(e),
             }
         }
 
-        // handle directory creation
-        if message.perm.contains(FileMode::Dir) {
-            match fs::create_dir(&file_path) {
-                Ok(()) => {
-                    // Set permissions
-                    #[cfg(unix)]
-                    {
-                        if let Ok(mut perms) = fs::metadata(&file_path).map(|m| m.permissions()) {
-                            perms.set_mode(message.perm.bits() & 0o777);
-                            let _ = fs::set_permissions(&file_path, perms);
-                        }
-                    }
+        // note: In a full implementation, you might also handle:
+        // - change owner (requires root)
+        // - change modification times (requires specialized calls)
+
+        match error {
+            Some(e) => Err(io_error("Cannot change file attributes", &e)),
+            None => Ok(renamed),
+        }
+    }
+
+    /// Refuse a wstat of `stat` on the file at `path` that `user` may not make
+    ///
+    /// The mode is the owner's to change, the length is changed by writing and the name by
+    /// writing the directory. The owner may move a file to any group they are a member of.
+    fn check_wstat(
+        &self,
+        path: &Path,
+        user: &Credentials,
+        stat: &Stat,
+        gid: Option<u32>,
+    ) -> Result<(), Message> {
+        let metadata = self
+            .metadata(path)
+            .map_err(|e| io_error("Cannot stat file", &e))?;
+        if stat.mode != FileMode::DontTouch {
+            check_owner(Some(user), &metadata)?;
+        }
+        if let Some(gid) = gid.filter(|gid| *gid != metadata.gid()) {
+            check_owner(Some(user), &metadata)?;
+            if user.uid != 0 && !user.gids.contains(&gid) {
+                return Err(Message::error_with_errno("not in group".to_string(), EPERM));
+            }
+        }
+        if stat.length != 0xFFFF_FFFF_FFFF_FFFF {
+            check(Some(user), &metadata, WRITE)?;
+        }
+        if !stat.name.is_empty() && stat.name != "." && stat.name != ".." {
+            let dir_metadata = self
+                .open_parent(path)
+                .and_then(|(dir, _)| dir.metadata())
+                .map_err(|e| io_error("Cannot stat directory", &e))?;
+            check(Some(user), &dir_metadata, WRITE)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hand a file just created for `user` over to it, in the group of the directory it is in
+///
+/// Only root can, a server running as anyone else keeps the files it creates.
+fn give(file: &File, user: &Credentials, dir_metadata: &fs::Metadata) {
+    if !Uid::effective().is_root() {
+        return;
+    }
+    let owner = Uid::from_raw(user.uid);
+    let group = Gid::from_raw(dir_metadata.gid());
+    if let Err(e) = fchown(file, Some(owner), Some(group)) {
+        tracing::warn!("failed to give a created file to uid {}: {e}", user.uid);
+    }
+}
 
-                    match fs::metadata(&file_path) {
-                        Ok(metadata) => {
-                            let qid = create_qid_from_metadata(&metadata);
-
-                            // Update the fid to point to the new directory
-                            let mut fids = self.fids.lock().unwrap();
-                            if let Some(entry) = fids.get_mut(&message.fid) {
-                                entry.path = file_path;
-                                entry.opened = true;
-                                entry.is_dir = true;
-                                entry.file = None;
-                            }
-
-                            Message::Rcreate(Rcreate { qid, iounit: 4096 })
-                        }
-                        Err(e) => Message::Rerror(Rerror {
-                            ename: format!("Cannot stat new directory: {e}"),
-                        }),
+/// Whether a symlink in `dir` pointing at `target` stays beneath the exported directory
+///
+/// The target is resolved lexically, absolute targets are outside by definition.
+fn target_beneath(dir: &Path, target: &str) -> bool {
+    let mut depth = dir.components().count();
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    !target.is_empty()
+}
+
+/// The group a wstat of `stat` moves a file to, if it names one
+///
+/// A group is named by `gid`, or a number in it, or by the 9P2000.u `n_gid`.
+fn new_gid(users: Option<&dyn Users>, stat: &Stat) -> Result<Option<u32>, Message> {
+    if stat.gid.is_empty() {
+        return Ok(Some(stat.n_gid).filter(|gid| *gid != P9_NONUNAME));
+    }
+    users
+        .and_then(|users| users.gid(&stat.gid))
+        .or_else(|| stat.gid.parse().ok())
+        .map(Some)
+        .ok_or_else(|| Message::error_with_errno(format!("{}: unknown group", stat.gid), EINVAL))
+}
+
+/// The credentials of whoever attaches with `message`
+///
+/// A uid the transport vouches for is taken over any name, a numeric uname is only used when
+/// no name is given.
+fn credentials(users: &dyn Users, message: &Tattach, peer: Option<u32>) -> Option<Credentials> {
+    let uid = match peer {
+        Some(uid) => uid,
+        None if !message.uname.is_empty() => users.uid(&message.uname)?,
+        None if message.n_uname != P9_NONUNAME => message.n_uname,
+        None => return None,
+    };
+    Some(Credentials::of(users, uid))
+}
+
+/// Run blocking filesystem work on tokio's blocking pool
+async fn unblock<T, F>(work: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .expect("blocking filesystem task panicked")
+}
+
+impl MessageHandler for Handler {
+    type Fid = FidEntry;
+
+    fn dialects(&self) -> &[Dialect] {
+        &[Dialect::Unix, Dialect::Base]
+    }
+
+    async fn attach(&self, session: &Session<FidEntry>, message: &Tattach) -> Message {
+        let user = match &self.users {
+            Some(users) => {
+                let users = users.clone();
+                let tattach = message.clone();
+                let peer = session.peer().and_then(|peer| peer.uid);
+                match unblock(move || credentials(&*users, &tattach, peer)).await {
+                    Some(user) => Some(Arc::new(user)),
+                    None => {
+                        return Message::error_with_errno(
+                            format!("{}: unknown user", message.uname),
+                            EACCES,
+                        )
                     }
                 }
-                Err(e) => Message::error(format!("Cannot create directory: {e}")),
             }
-        } else {
-            // Handle regular file creation
-            match options.open(&file_path) {
-                Ok(file) => {
-                    // set file permissions
-                    #[cfg(unix)]
-                    {
-                        if let Ok(mut perms) = fs::metadata(&file_path).map(|m| m.permissions()) {
-                            perms.set_mode(message.perm.bits() & 0o777);
-                            let _ = fs::set_permissions(&file_path, perms);
-                        }
-                    }
+            None => None,
+        };
 
-                    match fs::metadata(&file_path) {
-                        Ok(metadata) => {
-                            let qid = create_qid_from_metadata(&metadata);
-
-                            // update the fid entry to point to the new file
-                            let mut fids = self.fids.lock().unwrap();
-                            if let Some(entry) = fids.get_mut(&message.fid) {
-                                entry.path = file_path;
-                                entry.opened = true;
-                                entry.is_dir = false;
-                                entry.file = Some(file);
-                            }
-
-                            Message::Rcreate(Rcreate { qid, iounit: 4096 })
-                        }
-                        Err(e) => Message::error(format!("Cannot stat new file: {e}")),
-                    }
+        // establish a new fid that points to the root directory
+        let root = self.root.clone();
+
+        // verify the root directory exists
+        match unblock(move || root.metadata(Path::new(""))).await {
+            Ok(metadata) => {
+                if !metadata.is_dir() {
+                    return Message::error("Not a directory".to_string());
+                }
+
+                // create a qid for the root directory
+                let qid = create_qid_from_metadata(&metadata);
+
+                // store this fid in our mapping
+                let entry = FidEntry::new(PathBuf::new(), true, None, user);
+                match session.fids().attach(message.fid, entry) {
+                    Ok(_) => Message::Rattach(Rattach { qid }),
+                    Err(e) => e.into(),
                 }
-                Err(e) => Message::error(format!("Cannot create file: {e}")),
             }
+            Err(e) => io_error("Cannot attach", &e),
         }
     }
 
-    async fn read(&self, message: &Tread) -> Message {
-        let fid = message.fid;
-        let offset = message.offset;
-        let count = message.count;
+    async fn walk(&self, session: &Session<FidEntry>, message: &Twalk) -> Message {
+        // newfid is only bound once every name has been walked
+        let source = match session.fids().walk(message.fid, message.newfid) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
+        };
 
-        // get the fid entry
-        let mut fids = self.fids.lock().unwrap();
-        let Some(entry) = fids.get_mut(&fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
+        let root = self.root.clone();
+        let wnames = message.wnames.clone();
+        let user = source.user.clone();
+        let (wqids, path, is_dir) = match unblock(move || root.walk(&source, &wnames)).await {
+            Ok(walked) => walked,
+            Err(message) => return message,
         };
 
-        // ensure the file is opened
-        if !entry.opened {
-            return Message::Rerror(Rerror {
-                ename: "File not open".to_string(),
-            });
+        if wqids.len() == message.wnames.len() {
+            let entry = FidEntry::new(path, is_dir, None, user);
+            if let Err(e) = session.fids().bind(message.fid, message.newfid, entry) {
+                return e.into();
+            }
         }
 
-        // handle different types of reads
-        if entry.is_dir {
-            // for directories, we need to read directory entries
-            // and format them as stat structures
+        Message::Rwalk(Rwalk { wqids })
+    }
 
-            let path = entry.path.clone();
-            drop(fids); // release lock before filesystem operations
-
-            let mut data = Vec::new();
-
-            match fs::read_dir(&path) {
-                Ok(read_dir) => {
-                    // skip entries before offset
-                    let entries: Vec<_> = read_dir.collect();
-                    let entries_to_process = entries
-                        .into_iter()
-                        .skip(usize::try_from(offset).unwrap())
-                        .take(count as usize / 128); // rough estimate of stat size
-
-                    // create a stat for each entry
-                    for dir_entry in entries_to_process.flatten() {
-                        if let Ok(metadata) = dir_entry.metadata() {
-                            let stat = stat_from_metadata(&metadata, &dir_entry.path());
-                            match stat.encode(&mut data) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    return Message::Rerror(Rerror {
-                                        ename: format!("failed to encode stat: {e}"),
-                                    })
-                                }
-                            }
-                        }
-                    }
+    async fn open(&self, session: &Session<FidEntry>, message: &Topen) -> Message {
+        let fid = message.fid;
+        let mode = message.mode;
+
+        let source = match session.fids().opening(fid) {
+            Ok(entry) => entry,
+            Err(e) => return e.into(),
+        };
 
-                    Message::Rread(Rread { data: data.into() })
+        let root = self.root.clone();
+        match unblock(move || root.open(&source, mode)).await {
+            Ok((qid, entry)) => {
+                // update the fid entry
+                if let Err(e) = session.fids().opened(fid, mode, entry) {
+                    return e.into();
                 }
-                Err(e) => Message::Rerror(Rerror {
-                    ename: format!("Cannot read directory: {e}"),
-                }),
-            }
-        } else {
-            // read from regular file
-            let Some(file) = &mut entry.file else {
-                return Message::Rerror(Rerror {
-                    ename: "No file handle".to_string(),
-                });
-            };
 
-            // seek to the offset
-            if let Err(e) = file.seek(SeekFrom::Start(offset)) {
-                return Message::Rerror(Rerror {
-                    ename: format!("Seek error: {e}"),
-                });
+                // reasonable iounit size
+                let iounit = 4096;
+
+                Message::Ropen(Ropen { qid, iounit })
             }
+            Err(message) => message,
+        }
+    }
 
-            // allocate buffer and read data
-            let mut buffer = vec![0; count as usize];
-            match file.read(&mut buffer) {
-                Ok(n) => {
-                    buffer.truncate(n);
-                    Message::Rread(Rread {
-                        data: buffer.into(),
-                    })
+    async fn create(&self, session: &Session<FidEntry>, message: &Tcreate) -> Message {
+        let source = match session.fids().opening(message.fid) {
+            Ok(entry) => entry,
+            Err(e) => return e.into(),
+        };
+
+        let root = self.root.clone();
+        let tcreate = message.clone();
+        match unblock(move || root.create(&source, &tcreate)).await {
+            Ok((qid, entry)) => {
+                // update the fid entry to point to the new file
+                if let Err(e) = session.fids().opened(message.fid, message.mode, entry) {
+                    return e.into();
                 }
-                Err(e) => Message::Rerror(Rerror {
-                    ename: format!("Read error: {e}"),
-                }),
+
+                Message::Rcreate(Rcreate { qid, iounit: 4096 })
             }
+            Err(message) => message,
         }
     }
 
-    async fn write(&self, message: &Twrite) -> Message {
+    async fn read(&self, session: &Session<FidEntry>, message: &Tread) -> Message {
         let fid = message.fid;
         let offset = message.offset;
-        let data = message.data.clone();
+        let count = message.count;
 
-        // get the fid entry
-        let mut fids = self.fids.lock().unwrap();
-        let Some(entry) = fids.get_mut(&fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
+        // get the fid entry, which must have been opened
+        let entry = match session.fids().get_open(fid) {
+            Ok((entry, _)) => entry,
+            Err(e) => return e.into(),
         };
 
-        // ensure the file is opened
-        if !entry.opened {
-            return Message::Rerror(Rerror {
-                ename: "File not open".to_string(),
-            });
+        // handle different types of reads
+        if entry.is_dir {
+            // for directories, we need to read directory entries
+            // and format them as stat structures
+            let dialect = session.dialect();
+            let users = self.users.clone();
+            unblock(move || read_dir(&entry, offset, count, dialect, users.as_deref())).await
+        } else {
+            unblock(move || {
+                // read from regular file
+                let Some(file) = entry.file.as_ref() else {
+                    return Message::error("No file handle".to_string());
+                };
+
+                // allocate buffer and read data at the offset
+                let mut buffer = vec![0; count as usize];
+                match file.read_at(&mut buffer, offset) {
+                    Ok(n) => {
+                        buffer.truncate(n);
+                        Message::Rread(Rread {
+                            data: buffer.into(),
+                        })
+                    }
+                    Err(e) => io_error("Read error", &e),
+                }
+            })
+            .await
         }
+    }
+
+    async fn write(&self, session: &Session<FidEntry>, message: &Twrite) -> Message {
+        let fid = message.fid;
+        let offset = message.offset;
+        let data = message.data.clone();
 
-        // get the file handle
-        let Some(file) = &mut entry.file else {
-            return Message::Rerror(Rerror {
-                ename: "No file handle".to_string(),
-            });
+        // get the fid entry, which must have been opened
+        let entry = match session.fids().get_open(fid) {
+            Ok((entry, _)) => entry,
+            Err(e) => return e.into(),
         };
 
-        // seek to the offset
-        if let Err(e) = file.seek(SeekFrom::Start(offset)) {
-            return Message::Rerror(Rerror {
-                ename: format!("Seek error: {e}"),
-            });
-        }
+        unblock(move || {
+            // get the file handle
+            let Some(file) = entry.file.as_ref() else {
+                return Message::error("No file handle".to_string());
+            };
 
-        // write the data
-        match file.write(&data) {
-            Ok(count) => Message::Rwrite(Rwrite {
-                count: u32::try_from(count).unwrap(), // unwrap - 9p data cannot exceed u32 size
-            }),
-            Err(e) => Message::Rerror(Rerror {
-                ename: format!("Write error: {e}"),
-            }),
-        }
+            // write the data at the offset
+            match file.write_at(&data, offset) {
+                Ok(count) => Message::Rwrite(Rwrite {
+                    count: u32::try_from(count).unwrap(), // unwrap - 9p data cannot exceed u32 size
+                }),
+                Err(e) => io_error("Write error", &e),
+            }
+        })
+        .await
     }
 
-    async fn clunk(&self, message: &Tclunk) -> Message {
+    async fn clunk(&self, session: &Session<FidEntry>, message: &Tclunk) -> Message {
         let fid = message.fid;
 
-        // remove the fid from the map
-        let mut fids = self.fids.lock().unwrap();
-
-        // close any open file handle before removing
-        if fids.remove(&fid).is_some() {
-            // file will be closed when dropped by remove
+        // remove the fid from the table, any open file is closed when the entry is dropped
+        match session.fids().clunk(fid) {
+            Ok(_) => Message::Rclunk(Rclunk),
+            Err(e) => e.into(),
         }
-
-        // return success
-        Message::Rclunk(Rclunk)
     }
 
-    async fn remove(&self, message: &Tremove) -> Message {
+    async fn remove(&self, session: &Session<FidEntry>, message: &Tremove) -> Message {
         let fid = message.fid;
 
-        // get the path
-        let Ok(path) = self.path_for_fid(fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
+        // remove clunks the fid even when the file cannot be removed
+        let entry = match session.fids().clunk(fid) {
+            Ok(entry) => entry,
+            Err(e) => return e.into(),
         };
 
-        // remove the fid from the map first (similar to clunk)
-        let mut fids = self.fids.lock().unwrap();
-        fids.remove(&fid);
-
         // attempt to remove the file or directory
-        let result = if path.is_dir() {
-            fs::remove_dir_all(&path)
+        let flag = if entry.is_dir {
+            UnlinkatFlags::RemoveDir
         } else {
-            fs::remove_file(&path)
+            UnlinkatFlags::NoRemoveDir
         };
+        let root = self.root.clone();
+        let result = unblock(move || {
+            let (dir, name) = root
+                .open_parent(&entry.path)
+                .map_err(|e| io_error("Remove error", &e))?;
+            // removing a file is writing its directory
+            if entry.user.is_some() {
+                let metadata = dir
+                    .metadata()
+                    .map_err(|e| io_error("Cannot stat directory", &e))?;
+                entry.check(&metadata, WRITE)?;
+            }
+            unlinkat(&dir, name, flag).map_err(|e| io_error("Remove error", &e.into()))
+        })
+        .await;
 
         match result {
             Ok(()) => Message::Rremove(Rremove),
-            Err(e) => Message::Rerror(Rerror {
-                ename: format!("Remove error: {e}"),
-            }),
+            Err(message) => message,
         }
     }
 
-    async fn stat(&self, message: &Tstat) -> Message {
+    async fn stat(&self, session: &Session<FidEntry>, message: &Tstat) -> Message {
         let fid = message.fid;
 
         // get the path and metadata for this fid
-        let Ok(path) = self.path_for_fid(fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
+        let path = match session.fids().get(fid) {
+            Ok(entry) => entry.path.clone(),
+            Err(e) => return e.into(),
         };
 
-        match fs::metadata(&path) {
+        let root = self.root.clone();
+        let dialect = session.dialect();
+        let users = self.users.clone();
+        unblock(move || match root.metadata(&path) {
             Ok(metadata) => {
-                let stat = stat_from_metadata(&metadata, &path);
+                let path = root.dir.join(&path);
+                let stat = stat_from_metadata(&metadata, &path, dialect, users.as_deref());
                 Message::Rstat(Rstat { stat })
             }
-            Err(e) => Message::Rerror(Rerror {
-                ename: format!("Stat error: {e}"),
-            }),
-        }
+            Err(e) => io_error("Stat error", &e),
+        })
+        .await
     }
 
-    async fn wstat(&self, message: &Twstat) -> Message {
+    async fn wstat(&self, session: &Session<FidEntry>, message: &Twstat) -> Message {
         let fid = message.fid;
-        let stat = &message.stat;
 
-        // get the path
-        let Ok(path) = self.path_for_fid(fid) else {
-            return Message::Rerror(Rerror {
-                ename: "Fid not found".to_string(),
-            });
+        // get the fid entry
+        let entry = match session.fids().get(fid) {
+            Ok(entry) => entry,
+            Err(e) => return e.into(),
         };
 
-        let mut error = None;
-
-        // change permissions if mode is not ~0
-        if stat.mode != FileMode::DontTouch {
-            #[cfg(unix)]
-            {
-                if let Ok(mut perms) = fs::metadata(&path).map(|m| m.permissions()) {
-                    perms.set_mode(stat.mode.bits() & 0o777);
-                    if let Err(e) = fs::set_permissions(&path, perms) {
-                        error = Some(e);
+        let root = self.root.clone();
+        let stat = message.stat.clone();
+        let users = self.users.clone();
+        match unblock(move || root.wstat(&entry, &stat, users.as_deref())).await {
+            Ok(renamed) => {
+                // update the path in our fid table
+                if let Some(entry) = renamed {
+                    if let Err(e) = session.fids().replace(fid, entry) {
+                        return e.into();
                     }
-                } else {
-                    error = Some(io::Error::other("Failed to get permissions"));
                 }
+                Message::Rwstat(Rwstat)
             }
+            Err(message) => message,
         }
+    }
+}
 
-        // change file size if length is not ~0
-        if error.is_none() && stat.length != 0xFFFF_FFFF_FFFF_FFFF {
-            if let Err(e) = fs::OpenOptions::new()
-                .write(true)
-                .open(&path)
-                .and_then(|file| file.set_len(stat.length))
-            {
-                error = Some(e);
-            }
-        }
-
-        // change name if not empty (rename file)
-        if error.is_none() && !stat.name.is_empty() && stat.name != "." && stat.name != ".." {
-            let parent = path.parent().unwrap_or(Path::new("."));
-            let new_path = parent.join(&stat.name);
+/// Read the next whole entries of an opened directory that fit in `count` bytes
+fn read_dir(
+    entry: &FidEntry,
+    offset: u64,
+    count: u32,
+    dialect: Dialect,
+    users: Option<&dyn Users>,
+) -> Message {
+    let Some(dir) = entry.file.as_ref() else {
+        return Message::error("No file handle".to_string());
+    };
 
-            if let Err(e) = fs::rename(&path, &new_path) {
-                error = Some(e);
-            } else {
-                // Update the path in our fid table
-                let mut fids = self.fids.lock().unwrap();
-                if let Some(entry) = fids.get_mut(&fid) {
-                    entry.path = new_path;
-                }
+    // only whole entries are returned, so offsets other than where the previous read stopped
+    // would land in the middle of one
+    let mut cursor = entry.cursor.lock().unwrap();
+    if offset == 0 {
+        match fs::read_dir(proc_path(dir)) {
+            Ok(read_dir) => {
+                *cursor = DirCursor {
+                    offset: 0,
+                    entries: Some(read_dir),
+                    pending: None,
+                };
             }
+            Err(e) => return io_error("Cannot read directory", &e),
         }
+    } else if offset != cursor.offset {
+        return FidError::BadOffset.into();
+    }
 
-        // note: In a full implementation, you might also handle:
-        // - change owner/group (requires root)
-        // - change modification times (requires specialized calls)
+    let mut data = Vec::new();
+
+    loop {
+        let record = match cursor.pending.take() {
+            Some(record) => record,
+            None => match cursor.entries.as_mut().and_then(Iterator::next) {
+                // create a stat for each entry, skipping any that vanished meanwhile
+                Some(Ok(dir_entry)) => {
+                    let Ok(metadata) = dir_entry.metadata() else {
+                        continue;
+                    };
+                    let stat = stat_from_metadata(&metadata, &dir_entry.path(), dialect, users);
+                    let mut record = Vec::new();
+                    if let Err(e) = stat.encode_as(&mut record, dialect) {
+                        return Message::error(format!("failed to encode stat: {e}"));
+                    }
+                    record
+                }
+                Some(Err(_)) => continue,
+                None => break,
+            },
+        };
 
-        match error {
-            Some(e) => Message::Rerror(Rerror {
-                ename: format!("Cannot change file attributes: {e}"),
-            }),
-            None => Message::Rwstat(Rwstat),
+        if data.len() + record.len() > count as usize {
+            cursor.pending = Some(record);
+            break;
         }
+        data.extend_from_slice(&record);
+    }
+
+    if data.is_empty() && cursor.pending.is_some() {
+        return Message::error_with_errno(
+            "read count too small for a directory entry".to_string(),
+            EINVAL,
+        );
     }
+
+    cursor.offset += data.len() as u64;
+    Message::Rread(Rread { data: data.into() })
 }
 
 fn create_qid_from_metadata(metadata: &fs::Metadata) -> Qid {
@@ -639,11 +980,51 @@ fn create_qid_from_metadata(metadata: &fs::Metadata) -> Qid {
     }
 }
 
-fn stat_from_metadata(metadata: &fs::Metadata, path: &Path) -> Stat {
+/// Convert a 9P2000 open mode to the flags used to open the file
+fn open_flags(mode: FlagSet<OpenMode>) -> OFlag {
+    let mut flags = match mode.bits() & 3 {
+        1 => OFlag::O_WRONLY,
+        2 => OFlag::O_RDWR,
+        // execute is just read in this implementation
+        _ => OFlag::O_RDONLY,
+    };
+    if mode.contains(OpenMode::Trunc) {
+        flags |= OFlag::O_TRUNC;
+    }
+
+    flags
+}
+
+/// A path that reaches the file behind `file` without resolving its name again
+fn proc_path(file: &File) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd()))
+}
+
+fn io_error(context: &str, e: &io::Error) -> Message {
+    let errno = e
+        .raw_os_error()
+        .and_then(|errno| u32::try_from(errno).ok())
+        .unwrap_or(0);
+    Message::error_with_errno(format!("{context}: {e}"), errno)
+}
+
+/// The stat of the file at `path`, naming its owner and group as `users` does, or by number
+fn stat_from_metadata(
+    metadata: &fs::Metadata,
+    path: &Path,
+    dialect: Dialect,
+    users: Option<&dyn Users>,
+) -> Stat {
     let qid = create_qid_from_metadata(metadata);
     let mode = FileMode::from_unix_perm(metadata.mode(), metadata.is_dir());
-
-    Stat {
+    let uid = users
+        .and_then(|users| users.uname(metadata.uid()))
+        .unwrap_or_else(|| metadata.uid().to_string());
+    let gid = users
+        .and_then(|users| users.gname(metadata.gid()))
+        .unwrap_or_else(|| metadata.gid().to_string());
+
+    let mut stat = Stat {
         r#type: u16::from(qid.qtype.bits()),
         dev: 0, // not needed for this implementation
         qid,
@@ -656,8 +1037,601 @@ fn stat_from_metadata(metadata: &fs::Metadata, path: &Path) -> Stat {
             .unwrap_or_default()
             .to_string_lossy()
             .to_string(),
-        uid: metadata.uid().to_string(),
-        gid: metadata.gid().to_string(),
-        muid: String::new(), // not tracked in this implementation
+        // who last modified a file is not tracked, so its owner stands in
+        muid: uid.clone(),
+        uid,
+        gid,
+        extension: String::new(),
+        n_uid: P9_NONUNAME,
+        n_gid: P9_NONUNAME,
+        n_muid: P9_NONUNAME,
+    };
+
+    if dialect == Dialect::Unix {
+        let (mode, extension) = unix_extension(metadata, path);
+        stat.mode |= mode;
+        stat.extension = extension;
+        stat.n_uid = metadata.uid();
+        stat.n_gid = metadata.gid();
+        stat.n_muid = metadata.uid();
+    }
+
+    stat
+}
+
+/// The 9P2000.u mode bits and extension string describing special files
+fn unix_extension(metadata: &fs::Metadata, path: &Path) -> (FlagSet<FileMode>, String) {
+    let file_type = metadata.file_type();
+    let mut mode = FlagSet::empty();
+    let mut extension = String::new();
+
+    if file_type.is_symlink() {
+        mode |= FileMode::Symlink;
+        extension = fs::read_link(path)
+            .map(|target| target.to_string_lossy().to_string())
+            .unwrap_or_default();
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        mode |= FileMode::Device;
+        let kind = if file_type.is_block_device() {
+            'b'
+        } else {
+            'c'
+        };
+        let rdev = metadata.rdev();
+        let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+        let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+        extension = format!("{kind} {major} {minor}");
+    } else if file_type.is_fifo() {
+        mode |= FileMode::NamedPipe;
+    } else if file_type.is_socket() {
+        mode |= FileMode::Socket;
+    }
+
+    if metadata.mode() & 0o4000 != 0 {
+        mode |= FileMode::SetUid;
+    }
+    if metadata.mode() & 0o2000 != 0 {
+        mode |= FileMode::SetGid;
+    }
+
+    (mode, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use stowage_proto::{consts::errno::EEXIST, Decodable};
+    use tokio_test::block_on;
+
+    const ROOT_FID: u32 = 0;
+
+    /// An exported `root` directory next to an `outside` one holding a secret
+    fn setup() -> (tempfile::TempDir, Handler, Session<FidEntry>) {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("root/dir")).unwrap();
+        fs::write(tmp.path().join("root/dir/file"), "inside").unwrap();
+        fs::create_dir(tmp.path().join("outside")).unwrap();
+        fs::write(tmp.path().join("outside/secret"), "outside").unwrap();
+
+        let handler = Handler::new(tmp.path().join("root"));
+        let session = Session::new();
+        let attach = Tattach {
+            fid: ROOT_FID,
+            afid: !0,
+            uname: String::new(),
+            aname: String::new(),
+            n_uname: P9_NONUNAME,
+        };
+        assert!(matches!(
+            block_on(handler.attach(&session, &attach)),
+            Message::Rattach(_)
+        ));
+
+        (tmp, handler, session)
+    }
+
+    fn walk(
+        handler: &Handler,
+        session: &Session<FidEntry>,
+        newfid: u32,
+        names: &[&str],
+    ) -> Message {
+        let twalk = Twalk {
+            fid: ROOT_FID,
+            newfid,
+            wnames: names.iter().map(ToString::to_string).collect(),
+        };
+        block_on(handler.walk(session, &twalk))
+    }
+
+    /// Walking `names` must fail, a partial walk is fine as long as it binds nothing
+    fn assert_refused(handler: &Handler, session: &Session<FidEntry>, names: &[&str]) {
+        match walk(handler, session, 9, names) {
+            Message::Rerror(_) => {}
+            Message::Rwalk(rwalk) if rwalk.wqids.len() < names.len() => {}
+            message => panic!("walk of {names:?} escaped: {message:?}"),
+        }
+        assert!(session.fids().get(9).is_err());
+    }
+
+    fn root_qid(tmp: &tempfile::TempDir) -> Qid {
+        create_qid_from_metadata(&fs::metadata(tmp.path().join("root")).unwrap())
+    }
+
+    fn read_all(handler: &Handler, session: &Session<FidEntry>, fid: u32) -> Message {
+        let topen = Topen {
+            fid,
+            mode: OpenMode::Read.into(),
+        };
+        if let message @ Message::Rerror(_) = block_on(handler.open(session, &topen)) {
+            return message;
+        }
+        let tread = Tread {
+            fid,
+            offset: 0,
+            count: 4096,
+        };
+        block_on(handler.read(session, &tread))
+    }
+
+    #[test]
+    fn dotdot_at_root_stays_at_root() {
+        let (tmp, handler, session) = setup();
+
+        let Message::Rwalk(rwalk) = walk(&handler, &session, 1, &["..", "..", ".."]) else {
+            panic!("walk failed");
+        };
+        assert_eq!(rwalk.wqids.len(), 3);
+        assert!(rwalk.wqids.iter().all(|qid| *qid == root_qid(&tmp)));
+    }
+
+    #[test]
+    fn dotdot_cannot_climb_out_through_a_subdirectory() {
+        let (tmp, handler, session) = setup();
+
+        let Message::Rwalk(rwalk) = walk(&handler, &session, 1, &["dir", "..", "..", ".."]) else {
+            panic!("walk failed");
+        };
+        assert_eq!(rwalk.wqids.last(), Some(&root_qid(&tmp)));
+
+        // the sibling of the root is out of reach
+        assert_refused(&handler, &session, &["..", "outside", "secret"]);
+    }
+
+    #[test]
+    fn names_with_slashes_are_refused() {
+        let (_tmp, handler, session) = setup();
+
+        assert_refused(&handler, &session, &["../outside"]);
+        assert_refused(&handler, &session, &["dir/file"]);
+    }
+
+    #[test]
+    fn symlinks_out_of_the_root_are_refused() {
+        let (tmp, handler, session) = setup();
+        let root = tmp.path().join("root");
+        symlink(tmp.path().join("outside"), root.join("absolute")).unwrap();
+        symlink("../outside", root.join("relative")).unwrap();
+        symlink("../../outside/secret", root.join("dir/deep")).unwrap();
+
+        for names in [
+            &["absolute"][..],
+            &["absolute", "secret"],
+            &["relative", "secret"],
+            &["dir", "deep"],
+        ] {
+            assert_refused(&handler, &session, names);
+        }
+    }
+
+    #[test]
+    fn creates_do_not_follow_symlinks_out_of_the_root() {
+        let (tmp, handler, session) = setup();
+        let secret = tmp.path().join("outside/secret");
+        symlink(&secret, handler.root.dir.join("planted")).unwrap();
+
+        assert!(matches!(
+            walk(&handler, &session, 1, &[]),
+            Message::Rwalk(_)
+        ));
+        let tcreate = Tcreate {
+            fid: 1,
+            name: "planted".to_string(),
+            perm: FileMode::OwnerRead | FileMode::OwnerWrite | FileMode::OtherWrite,
+            mode: OpenMode::Write | OpenMode::Trunc,
+            extension: String::new(),
+        };
+        assert!(matches!(
+            block_on(handler.create(&session, &tcreate)),
+            Message::Rerror(_)
+        ));
+        assert_eq!(fs::read_to_string(&secret).unwrap(), "outside");
+        let mode = fs::metadata(&secret).unwrap().mode();
+        assert_eq!(mode & 0o002, 0, "the secret was chmod'ed");
+    }
+
+    #[test]
+    fn creates_do_not_take_over_existing_files() {
+        let (tmp, handler, session) = setup();
+        let file = tmp.path().join("root/dir/file");
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+
+        assert!(matches!(
+            walk(&handler, &session, 1, &["dir"]),
+            Message::Rwalk(_)
+        ));
+        let tcreate = Tcreate {
+            fid: 1,
+            name: "file".to_string(),
+            perm: FlagSet::new_truncated(0o666),
+            mode: OpenMode::Write | OpenMode::Trunc,
+            extension: String::new(),
+        };
+        assert_eq!(
+            errno(&block_on(handler.create(&session, &tcreate))),
+            Some(EEXIST)
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "inside");
+        assert_eq!(fs::metadata(&file).unwrap().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn symlinks_within_the_root_are_followed() {
+        let (_tmp, handler, session) = setup();
+        symlink("dir/file", handler.root.dir.join("link")).unwrap();
+
+        assert!(matches!(
+            walk(&handler, &session, 1, &["link"]),
+            Message::Rwalk(_)
+        ));
+        let Message::Rread(rread) = read_all(&handler, &session, 1) else {
+            panic!("read failed");
+        };
+        assert_eq!(&rread.data[..], b"inside");
+    }
+
+    #[test]
+    fn created_symlinks_cannot_escape() {
+        let (tmp, handler, session) = setup();
+
+        assert!(matches!(
+            walk(&handler, &session, 1, &["dir"]),
+            Message::Rwalk(_)
+        ));
+        let absolute = tmp.path().join("outside/secret");
+        let mut tcreate = Tcreate {
+            fid: 1,
+            name: "escape".to_string(),
+            perm: FileMode::Symlink | FileMode::OwnerRead,
+            mode: OpenMode::Read.into(),
+            extension: String::new(),
+        };
+        for target in [
+            absolute.to_str().unwrap(),
+            "../../outside/secret",
+            "a/../../..",
+        ] {
+            tcreate.extension = target.to_string();
+            assert!(
+                matches!(
+                    block_on(handler.create(&session, &tcreate)),
+                    Message::Rerror(_)
+                ),
+                "created a symlink to {target}"
+            );
+            assert!(fs::symlink_metadata(handler.root.dir.join("dir/escape")).is_err());
+        }
+
+        tcreate.extension = "../dir/file".to_string();
+        assert!(matches!(
+            block_on(handler.create(&session, &tcreate)),
+            Message::Rcreate(_)
+        ));
+        assert!(matches!(
+            walk(&handler, &session, 2, &["dir", "escape"]),
+            Message::Rwalk(_)
+        ));
+        let Message::Rread(rread) = read_all(&handler, &session, 2) else {
+            panic!("read through the symlink failed");
+        };
+        assert_eq!(&rread.data[..], b"inside");
+    }
+
+    #[test]
+    fn directory_swapped_for_a_symlink_cannot_escape() {
+        let (tmp, handler, session) = setup();
+        let root = tmp.path().join("root");
+
+        assert!(matches!(
+            walk(&handler, &session, 1, &["dir"]),
+            Message::Rwalk(_)
+        ));
+
+        // replace the directory the fid points at once the walk has been checked
+        fs::rename(root.join("dir"), root.join("moved")).unwrap();
+        symlink(tmp.path().join("outside"), root.join("dir")).unwrap();
+
+        let twalk = Twalk {
+            fid: 1,
+            newfid: 2,
+            wnames: vec!["secret".to_string()],
+        };
+        assert!(matches!(
+            block_on(handler.walk(&session, &twalk)),
+            Message::Rerror(_)
+        ));
+        assert!(matches!(
+            read_all(&handler, &session, 1),
+            Message::Rerror(_)
+        ));
+    }
+
+    #[test]
+    fn directory_reads_return_whole_entries_at_byte_offsets() {
+        let (_tmp, handler, session) = setup();
+        for i in 0..40 {
+            fs::write(
+                handler
+                    .root
+                    .dir
+                    .join(format!("file-with-a-long-name-{i:02}")),
+                "",
+            )
+            .unwrap();
+        }
+
+        let topen = Topen {
+            fid: ROOT_FID,
+            mode: OpenMode::Read.into(),
+        };
+        assert!(matches!(
+            block_on(handler.open(&session, &topen)),
+            Message::Ropen(_)
+        ));
+
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let tread = Tread {
+                fid: ROOT_FID,
+                offset,
+                count: 300,
+            };
+            let Message::Rread(rread) = block_on(handler.read(&session, &tread)) else {
+                panic!("read at {offset} failed");
+            };
+            if rread.data.is_empty() {
+                break;
+            }
+            assert!(rread.data.len() <= 300);
+
+            let mut cursor = std::io::Cursor::new(&rread.data[..]);
+            while cursor.position() < rread.data.len() as u64 {
+                names.push(Stat::decode(&mut cursor).unwrap().name);
+            }
+            offset += rread.data.len() as u64;
+        }
+
+        names.sort();
+        assert_eq!(names.len(), 41);
+        assert_eq!(names[0], "dir");
+
+        // starting over is allowed, anything but the last offset is not
+        for (offset, ok) in [(0, true), (1, false), (offset + 1, false)] {
+            let tread = Tread {
+                fid: ROOT_FID,
+                offset,
+                count: 300,
+            };
+            let message = block_on(handler.read(&session, &tread));
+            assert_eq!(matches!(message, Message::Rread(_)), ok, "offset {offset}");
+        }
+    }
+
+    /// Knows only glenda, who is neither root nor in any group of the files in the tests, and
+    /// the groups glenda and staff she is in and wheel she is not
+    struct Glenda;
+
+    const GROUPS: [(&str, u32); 3] = [("glenda", 4242), ("staff", 4343), ("wheel", 4444)];
+
+    impl Users for Glenda {
+        fn uid(&self, name: &str) -> Option<u32> {
+            (name == "glenda").then_some(4242)
+        }
+
+        fn uname(&self, uid: u32) -> Option<String> {
+            (uid == 4242).then(|| "glenda".to_string())
+        }
+
+        fn gid(&self, name: &str) -> Option<u32> {
+            GROUPS
+                .iter()
+                .find(|group| group.0 == name)
+                .map(|group| group.1)
+        }
+
+        fn gname(&self, gid: u32) -> Option<String> {
+            GROUPS
+                .iter()
+                .find(|group| group.1 == gid)
+                .map(|group| group.0.to_string())
+        }
+
+        fn groups(&self, _uid: u32) -> Vec<u32> {
+            vec![4242, 4343]
+        }
+    }
+
+    fn errno(message: &Message) -> Option<u32> {
+        match message {
+            Message::Rerror(rerror) => Some(rerror.errno),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn attached_users_are_held_to_the_mode_bits() {
+        let (tmp, _, _) = setup();
+        let root = tmp.path().join("root");
+        fs::create_dir(root.join("private")).unwrap();
+        fs::write(root.join("private/file"), "").unwrap();
+        fs::create_dir(root.join("shared")).unwrap();
+        for (path, mode) in [
+            ("", 0o755),
+            ("dir", 0o755),
+            ("dir/file", 0o644),
+            ("private", 0o700),
+            ("shared", 0o757),
+        ] {
+            fs::set_permissions(root.join(path), fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let handler = Handler::new(root.clone()).with_users(Arc::new(Glenda));
+        let session = Session::new();
+        let mut attach = Tattach {
+            fid: ROOT_FID,
+            afid: !0,
+            uname: "nobody".to_string(),
+            aname: String::new(),
+            n_uname: P9_NONUNAME,
+        };
+        assert_eq!(
+            errno(&block_on(handler.attach(&session, &attach))),
+            Some(EACCES)
+        );
+        attach.uname = "glenda".to_string();
+        assert!(matches!(
+            block_on(handler.attach(&session, &attach)),
+            Message::Rattach(_)
+        ));
+
+        // searching a directory takes execute permission
+        assert!(matches!(
+            walk(&handler, &session, 1, &["private"]),
+            Message::Rwalk(_)
+        ));
+        assert_refused(&handler, &session, &["private", "file"]);
+
+        // others may read dir/file but not write it
+        assert!(matches!(
+            walk(&handler, &session, 2, &["dir", "file"]),
+            Message::Rwalk(_)
+        ));
+        let topen = Topen {
+            fid: 2,
+            mode: OpenMode::Write.into(),
+        };
+        assert_eq!(
+            errno(&block_on(handler.open(&session, &topen))),
+            Some(EACCES)
+        );
+        assert!(matches!(read_all(&handler, &session, 2), Message::Rread(_)));
+
+        // nor remove it, nor change its mode
+        let mut stat = Stat::new_dont_touch();
+        stat.mode = FlagSet::new_truncated(0o666);
+        let twstat = Twstat { fid: 2, stat };
+        assert_eq!(
+            errno(&block_on(handler.wstat(&session, &twstat))),
+            Some(EPERM)
+        );
+        let tremove = Tremove { fid: 2 };
+        assert_eq!(
+            errno(&block_on(handler.remove(&session, &tremove))),
+            Some(EACCES)
+        );
+        assert!(root.join("dir/file").exists());
+
+        // creating takes write permission on the directory, whose mode masks the new file's
+        let mut tcreate = Tcreate {
+            fid: 3,
+            name: "new".to_string(),
+            perm: FlagSet::new_truncated(0o666),
+            mode: OpenMode::Write.into(),
+            extension: String::new(),
+        };
+        assert!(matches!(
+            walk(&handler, &session, 3, &["dir"]),
+            Message::Rwalk(_)
+        ));
+        assert_eq!(
+            errno(&block_on(handler.create(&session, &tcreate))),
+            Some(EACCES)
+        );
+        tcreate.fid = 4;
+        assert!(matches!(
+            walk(&handler, &session, 4, &["shared"]),
+            Message::Rwalk(_)
+        ));
+        assert!(matches!(
+            block_on(handler.create(&session, &tcreate)),
+            Message::Rcreate(_)
+        ));
+        let metadata = fs::metadata(root.join("shared/new")).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o646);
+        if Uid::effective().is_root() {
+            assert_eq!(metadata.uid(), 4242);
+        }
+    }
+
+    #[test]
+    fn files_are_stated_and_regrouped_by_name() {
+        // giving files away to glenda takes root
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let (tmp, _, _) = setup();
+        let root = tmp.path().join("root");
+        std::os::unix::fs::chown(root.join("dir/file"), Some(4242), Some(4242)).unwrap();
+
+        let handler = Handler::new(root.clone()).with_users(Arc::new(Glenda));
+        let session = Session::new();
+        let attach = Tattach {
+            fid: ROOT_FID,
+            afid: !0,
+            uname: "glenda".to_string(),
+            aname: String::new(),
+            n_uname: P9_NONUNAME,
+        };
+        assert!(matches!(
+            block_on(handler.attach(&session, &attach)),
+            Message::Rattach(_)
+        ));
+        assert!(matches!(
+            walk(&handler, &session, 1, &["dir", "file"]),
+            Message::Rwalk(_)
+        ));
+        let stat = || match block_on(handler.stat(&session, &Tstat { fid: 1 })) {
+            Message::Rstat(rstat) => rstat.stat,
+            message => panic!("stat failed: {message:?}"),
+        };
+        let stat_of_file = stat();
+        assert_eq!(
+            (stat_of_file.uid.as_str(), stat_of_file.gid.as_str()),
+            ("glenda", "glenda")
+        );
+        assert_eq!(stat_of_file.muid, "glenda");
+
+        let wstat = |gid: &str| {
+            let mut stat = Stat::new_dont_touch();
+            stat.gid = gid.to_string();
+            block_on(handler.wstat(&session, &Twstat { fid: 1, stat }))
+        };
+        assert_eq!(errno(&wstat("nobody")), Some(EINVAL));
+        assert_eq!(errno(&wstat("wheel")), Some(EPERM));
+        assert!(matches!(wstat("staff"), Message::Rwstat(_)));
+        assert_eq!(fs::metadata(root.join("dir/file")).unwrap().gid(), 4343);
+        assert_eq!(stat().gid, "staff");
+
+        // users glenda does not know of are named by number
+        let Message::Rstat(rstat) = block_on(handler.stat(&session, &Tstat { fid: ROOT_FID }))
+        else {
+            panic!("stat of the root failed");
+        };
+        assert_eq!(
+            (rstat.stat.uid.as_str(), rstat.stat.gid.as_str()),
+            ("0", "0")
+        );
     }
 }
@@ -1,2 +1,14 @@
+use stowage_proto::{consts::errno::EINVAL, Message};
+
 pub mod disk;
-// pub mod memory;
+pub mod memory;
+pub mod users;
+
+/// Names a client may give to walk, create or wstat, a single path element
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+fn invalid_name() -> Message {
+    Message::error_with_errno("invalid file name".to_string(), EINVAL)
+}
@@ -1,912 +1,922 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
-use stowage_proto::{Message, Qid, Stat};
-use stowage_service::MessageHandler;
-use tracing::info;
-
-#[derive(Debug, Clone)]
-enum FsNode {
-    File {
-        name: String,
-        content: Vec<u8>,
-        qid: Qid,
-        mode: u32,
-        atime: Timespec,
-        mtime: Timespec,
-        ctime: Timespec,
-        creation_time: Timespec,
-    },
-    Directory {
-        name: String,
-        children: HashMap<String, Arc<Mutex<FsNode>>>,
-        qid: Qid,
-        mode: u32,
-        atime: Timespec,
-        mtime: Timespec,
-        ctime: Timespec,
-        creation_time: Timespec,
+use crate::{invalid_name, valid_name};
+use flagset::FlagSet;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+use stowage_proto::{
+    consts::{
+        errno::{EBADF, EBUSY, EEXIST, EFBIG, EINVAL, EISDIR, ENOTDIR, ENOTEMPTY},
+        P9_IOHDRSZ, P9_NONUNAME,
     },
+    Dialect, FileMode, Message, OpenMode, Qid, QidType, Rattach, Rclunk, Rcreate, Ropen, Rread,
+    Rremove, Rstat, Rwalk, Rwrite, Rwstat, Stat, Tattach, Tclunk, Tcreate, Topen, Tread, Tremove,
+    Tstat, Twalk, Twrite, Twstat,
+};
+use stowage_service::{FidError, MessageHandler, Session};
+
+/// Serves a tree of files kept in memory
+///
+/// Every connection served by the same handler sees the same tree, and all of it is gone once
+/// the handler is dropped. Useful for tests and as scratch space.
+pub struct Handler {
+    root: Arc<Node>,
+    /// the qid path given to the next node created
+    next_path: AtomicU64,
 }
 
-impl FsNode {
-    pub fn qid(&self) -> Qid {
-        match self {
-            FsNode::Directory { qid, .. } => qid.clone(),
-            FsNode::File { qid, .. } => qid.clone(),
-        }
-    }
+/// The largest a file may grow, so that no write or wstat can take all of the server's memory
+const MAX_FILE_SIZE: usize = 1 << 30;
+
+/// A file or directory in the tree
+struct Node {
+    /// the qid path, never reused while the handler lives
+    path: u64,
+    /// the directory holding this node, dangling for the root
+    parent: Weak<Node>,
+    state: Mutex<NodeState>,
+}
 
-    pub fn atime_sec(&self) -> u64 {
-        match self {
-            FsNode::Directory { atime, .. } => atime.sec,
-            FsNode::File { atime, .. } => atime.sec,
+struct NodeState {
+    name: String,
+    mode: FlagSet<FileMode>,
+    version: u32,
+    atime: u32,
+    mtime: u32,
+    uid: String,
+    muid: String,
+    contents: Contents,
+}
+
+enum Contents {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Arc<Node>>),
+}
+
+/// What the memory handler knows about a fid
+pub struct FidEntry {
+    node: Arc<Node>,
+    cursor: Mutex<DirCursor>,
+}
+
+impl FidEntry {
+    fn new(node: Arc<Node>) -> Self {
+        Self {
+            node,
+            cursor: Mutex::default(),
         }
     }
+}
 
-    pub fn atime_nsec(&self) -> u64 {
-        match self {
-            FsNode::Directory { atime, .. } => atime.nsec,
-            FsNode::File { atime, .. } => atime.nsec,
+/// How far a directory has been read, so the next read can carry on from the same offset
+#[derive(Default)]
+struct DirCursor {
+    /// the offset the next read must ask for, or 0 to start over
+    offset: u64,
+    /// entries not returned yet, encoded when the directory was read from offset 0
+    entries: VecDeque<Vec<u8>>,
+}
+
+impl Handler {
+    #[must_use]
+    pub fn new() -> Self {
+        let root = NodeState::new(
+            String::from("/"),
+            FileMode::Dir | FlagSet::new_truncated(0o755),
+            String::from("none"),
+            Contents::Dir(BTreeMap::new()),
+        );
+
+        Self {
+            root: Arc::new(Node {
+                path: 0,
+                parent: Weak::new(),
+                state: Mutex::new(root),
+            }),
+            next_path: AtomicU64::new(1),
         }
     }
+}
 
-    pub fn mtime_sec(&self) -> u64 {
-        match self {
-            FsNode::Directory { mtime, .. } => mtime.sec,
-            FsNode::File { mtime, .. } => mtime.sec,
-        }
+impl Default for Handler {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn mtime_nsec(&self) -> u64 {
-        match self {
-            FsNode::Directory { mtime, .. } => mtime.nsec,
-            FsNode::File { mtime, .. } => mtime.nsec,
-        }
+impl Node {
+    fn state(&self) -> MutexGuard<'_, NodeState> {
+        self.state.lock().unwrap()
     }
 
-    pub fn ctime_sec(&self) -> u64 {
-        match self {
-            FsNode::Directory { ctime, .. } => ctime.sec,
-            FsNode::File { ctime, .. } => ctime.sec,
-        }
+    fn qid(&self) -> Qid {
+        self.state().qid(self.path)
     }
 
-    pub fn ctime_nsec(&self) -> u64 {
-        match self {
-            FsNode::Directory { ctime, .. } => ctime.nsec,
-            FsNode::File { ctime, .. } => ctime.nsec,
+    /// The nodes of a directory, empty for a file
+    fn children(&self) -> Vec<Arc<Node>> {
+        match &self.state().contents {
+            Contents::Dir(children) => children.values().cloned().collect(),
+            Contents::File(_) => Vec::new(),
         }
     }
 
-    pub fn creation_time_sec(&self) -> u64 {
-        match self {
-            FsNode::Directory { creation_time, .. } => creation_time.sec,
-            FsNode::File { creation_time, .. } => creation_time.sec,
+    fn stat(&self) -> Stat {
+        let state = self.state();
+        let length = match &state.contents {
+            Contents::File(data) => data.len() as u64,
+            Contents::Dir(_) => 0,
+        };
+        let qid = state.qid(self.path);
+
+        Stat {
+            r#type: 0,
+            dev: 0,
+            qid,
+            mode: state.mode,
+            atime: state.atime,
+            mtime: state.mtime,
+            length,
+            name: state.name.clone(),
+            uid: state.uid.clone(),
+            gid: state.uid.clone(),
+            muid: state.muid.clone(),
+            extension: String::new(),
+            n_uid: P9_NONUNAME,
+            n_gid: P9_NONUNAME,
+            n_muid: P9_NONUNAME,
         }
     }
+}
 
-    pub fn creation_time_nsec(&self) -> u64 {
-        match self {
-            FsNode::Directory { creation_time, .. } => creation_time.nsec,
-            FsNode::File { creation_time, .. } => creation_time.nsec,
+impl NodeState {
+    fn new(name: String, mode: FlagSet<FileMode>, uid: String, contents: Contents) -> Self {
+        let now = now();
+        Self {
+            name,
+            mode,
+            version: 0,
+            atime: now,
+            mtime: now,
+            muid: uid.clone(),
+            uid,
+            contents,
         }
     }
 
-    pub fn update_atime(&mut self) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0));
+    fn is_dir(&self) -> bool {
+        matches!(self.contents, Contents::Dir(_))
+    }
 
-        let timestamp = Timespec {
-            sec: now.as_secs(),
-            nsec: now.subsec_nanos() as u64,
+    fn qid(&self, path: u64) -> Qid {
+        let qtype = if self.is_dir() {
+            QidType::Dir
+        } else {
+            QidType::File
         };
 
-        match self {
-            FsNode::Directory { atime, .. } => *atime = timestamp,
-            FsNode::File { atime, .. } => *atime = timestamp,
+        Qid {
+            qtype: qtype.into(),
+            version: self.version,
+            path,
         }
     }
 
-    pub fn update_mtime(&mut self) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0));
+    /// Record a change to the contents made by `uid`
+    fn modified(&mut self, uid: &str) {
+        self.version = self.version.wrapping_add(1);
+        self.mtime = now();
+        uid.clone_into(&mut self.muid);
+    }
+}
 
-        let timestamp = Timespec {
-            sec: now.as_secs(),
-            nsec: now.subsec_nanos() as u64,
+impl MessageHandler for Handler {
+    type Fid = FidEntry;
+
+    async fn attach(&self, session: &Session<FidEntry>, message: &Tattach) -> Message {
+        match session
+            .fids()
+            .attach(message.fid, FidEntry::new(self.root.clone()))
+        {
+            Ok(_) => Message::Rattach(Rattach {
+                qid: self.root.qid(),
+            }),
+            Err(e) => e.into(),
+        }
+    }
+
+    async fn walk(&self, session: &Session<FidEntry>, message: &Twalk) -> Message {
+        // newfid is only bound once every name has been walked
+        let source = match session.fids().walk(message.fid, message.newfid) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
         };
 
-        match self {
-            FsNode::Directory { mtime, qid, .. } => {
-                *mtime = timestamp;
-                qid.version += 1;
+        let mut node = source.node.clone();
+        let mut wqids = Vec::with_capacity(message.wnames.len());
+
+        for wname in &message.wnames {
+            let next = {
+                let state = node.state();
+                let Contents::Dir(children) = &state.contents else {
+                    if wqids.is_empty() {
+                        return FidError::WalkNonDirectory.into();
+                    }
+                    break;
+                };
+
+                // at the root ".." names the root itself
+                if wname == ".." {
+                    Some(node.parent.upgrade().unwrap_or_else(|| node.clone()))
+                } else if valid_name(wname) {
+                    children.get(wname).cloned()
+                } else if wqids.is_empty() {
+                    return invalid_name();
+                } else {
+                    break;
+                }
+            };
+
+            match next {
+                Some(next) => {
+                    wqids.push(next.qid());
+                    node = next;
+                }
+                None if wqids.is_empty() => return FidError::NotFound.into(),
+                // path component not found, return what we have
+                None => break,
             }
-            FsNode::File { mtime, qid, .. } => {
-                *mtime = timestamp;
-                qid.version += 1;
+        }
+
+        if wqids.len() == message.wnames.len() {
+            let entry = FidEntry::new(node);
+            if let Err(e) = session.fids().bind(message.fid, message.newfid, entry) {
+                return e.into();
             }
         }
-    }
 
-    pub fn update_ctime(&mut self) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0));
+        Message::Rwalk(Rwalk { wqids })
+    }
 
-        let timestamp = Timespec {
-            sec: now.as_secs(),
-            nsec: now.subsec_nanos() as u64,
+    async fn open(&self, session: &Session<FidEntry>, message: &Topen) -> Message {
+        let node = match session.fids().opening(message.fid) {
+            Ok(entry) => entry.node.clone(),
+            Err(e) => return e.into(),
         };
 
-        match self {
-            FsNode::Directory { ctime, .. } => *ctime = timestamp,
-            FsNode::File { ctime, .. } => *ctime = timestamp,
-        }
-    }
-
-    fn to_stat(&self) -> Stat {
-        match self {
-            FsNode::Directory {
-                name,
-                children,
-                qid,
-                mode,
-                atime,
-                mtime,
-                ..
-            } => {
-                Stat {
-                    qtype: 0x80, // QTDIR
-                    dev: 0,
-                    qid: qid.clone(),
-                    mode: *mode | 0x80000000, // directory permission + DMDIR flag
-                    atime: atime.sec as u32,
-                    mtime: mtime.sec as u32,
-                    length: 0, // directories have zero length
-                    name: name.clone(),
-                    uid: "user".to_string(),
-                    gid: "user".to_string(),
-                    muid: "user".to_string(),
+        {
+            let mut state = node.state();
+            if state.is_dir() && (writes(message.mode) || message.mode.contains(OpenMode::Trunc)) {
+                return is_a_directory();
+            }
+            if message.mode.contains(OpenMode::Trunc) {
+                if let Contents::File(data) = &mut state.contents {
+                    data.clear();
+                    state.modified(&owner(session));
                 }
             }
-            FsNode::File {
-                name,
-                content,
-                qid,
-                mode,
-                atime,
-                mtime,
-                ..
-            } => Stat {
-                qtype: 0,
-                dev: 0,
-                qid: qid.clone(),
-                mode: *mode,
-                atime: atime.sec as u32,
-                mtime: mtime.sec as u32,
-                length: content.len() as u64,
-                name: name.clone(),
-                uid: "user".to_string(),
-                gid: "user".to_string(),
-                muid: "user".to_string(),
-            },
+            state.atime = now();
+        }
+
+        let qid = node.qid();
+        if let Err(e) = session
+            .fids()
+            .opened(message.fid, message.mode, FidEntry::new(node))
+        {
+            return e.into();
         }
+
+        Message::Ropen(Ropen {
+            qid,
+            iounit: iounit(session),
+        })
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct Timespec {
-    pub sec: u64,
-    pub nsec: u64,
-}
+    async fn create(&self, session: &Session<FidEntry>, message: &Tcreate) -> Message {
+        let dir = match session.fids().opening(message.fid) {
+            Ok(entry) => entry.node.clone(),
+            Err(e) => return e.into(),
+        };
+
+        if !valid_name(&message.name) {
+            return invalid_name();
+        }
+
+        if message.perm.contains(FileMode::Symlink)
+            || message.perm.contains(FileMode::Device)
+            || message.perm.contains(FileMode::NamedPipe)
+            || message.perm.contains(FileMode::Socket)
+        {
+            return Message::error("Cannot create special files".to_string());
+        }
+
+        let is_dir = message.perm.contains(FileMode::Dir);
+        if is_dir && (writes(message.mode) || message.mode.contains(OpenMode::Trunc)) {
+            return is_a_directory();
+        }
+
+        let uid = owner(session);
+        let node = {
+            let mut state = dir.state();
+
+            // the new file only gets the permissions its directory has, as in Plan 9
+            let dir_perm = state.mode.bits() & 0o777;
+            let (perm, contents) = if is_dir {
+                let perm = message.perm.bits() & (!0o777 | dir_perm);
+                (perm, Contents::Dir(BTreeMap::new()))
+            } else {
+                let perm = message.perm.bits() & (!0o666 | (dir_perm & 0o666));
+                (perm, Contents::File(Vec::new()))
+            };
+
+            let Contents::Dir(children) = &mut state.contents else {
+                return Message::error_with_errno("not a directory".to_string(), ENOTDIR);
+            };
+            if children.contains_key(&message.name) {
+                return file_exists();
+            }
 
-impl Timespec {
-    pub fn now() -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0));
+            let node = Arc::new(Node {
+                path: self.next_path.fetch_add(1, Ordering::Relaxed),
+                parent: Arc::downgrade(&dir),
+                state: Mutex::new(NodeState::new(
+                    message.name.clone(),
+                    FlagSet::new_truncated(perm),
+                    uid.clone(),
+                    contents,
+                )),
+            });
+            children.insert(message.name.clone(), node.clone());
+            state.modified(&uid);
+            node
+        };
 
-        Timespec {
-            sec: now.as_secs(),
-            nsec: now.subsec_nanos() as u64,
+        // update the fid entry to point to the new file
+        let qid = node.qid();
+        if let Err(e) = session
+            .fids()
+            .opened(message.fid, message.mode, FidEntry::new(node))
+        {
+            return e.into();
         }
+
+        Message::Rcreate(Rcreate {
+            qid,
+            iounit: iounit(session),
+        })
     }
-}
 
-/// Tracks an opened fid and its state
-#[derive(Debug, Clone)]
-struct FidState {
-    node: Arc<Mutex<FsNode>>,
-    is_open: bool,
-    open_mode: Option<u32>,
-    path: Vec<String>,
-}
+    async fn read(&self, session: &Session<FidEntry>, message: &Tread) -> Message {
+        let (entry, mode) = match session.fids().get_open(message.fid) {
+            Ok(open) => open,
+            Err(e) => return e.into(),
+        };
+        if mode.bits() & 3 == OpenMode::Write as u8 {
+            return Message::error_with_errno("fid not open for reading".to_string(), EBADF);
+        }
 
-pub struct Handler {
-    root: Arc<Mutex<FsNode>>,
-    fids: Mutex<HashMap<u32, FidState>>,
-    next_qid_path: Mutex<u64>,
-}
+        let mut state = entry.node.state();
+        state.atime = now();
+        match &state.contents {
+            Contents::File(data) => {
+                let start = usize::try_from(message.offset)
+                    .unwrap_or(usize::MAX)
+                    .min(data.len());
+                let end = start.saturating_add(message.count as usize).min(data.len());
+                Message::Rread(Rread {
+                    data: data[start..end].to_vec().into(),
+                })
+            }
+            Contents::Dir(_) => {
+                drop(state);
+                read_dir(&entry, message.offset, message.count, session.dialect())
+            }
+        }
+    }
 
-impl Handler {
-    pub fn new() -> Self {
-        let now = Timespec::now();
-        let root = FsNode::Directory {
-            name: "/".to_string(),
-            children: HashMap::new(),
-            qid: Qid {
-                qtype: 0x80, // QTDIR
-                version: 0,
-                path: 0,
-            },
-            mode: 0o755,
-            atime: now.clone(),
-            mtime: now.clone(),
-            ctime: now.clone(),
-            creation_time: now,
+    async fn write(&self, session: &Session<FidEntry>, message: &Twrite) -> Message {
+        let (entry, mode) = match session.fids().get_open(message.fid) {
+            Ok(open) => open,
+            Err(e) => return e.into(),
         };
+        if !writes(mode) {
+            return Message::error_with_errno("fid not open for writing".to_string(), EBADF);
+        }
 
-        Self {
-            root: Arc::new(Mutex::new(root)),
-            fids: Mutex::new(HashMap::new()),
-            next_qid_path: Mutex::new(1), // 0 is reserved for root
+        let mut state = entry.node.state();
+        let append = state.mode.contains(FileMode::AppendOnly);
+        let Contents::File(data) = &mut state.contents else {
+            return is_a_directory();
+        };
+
+        // append-only files are always written at their end
+        let start = if append {
+            data.len()
+        } else {
+            let Ok(offset) = usize::try_from(message.offset) else {
+                return FidError::BadOffset.into();
+            };
+            offset
+        };
+        let Some(end) = start
+            .checked_add(message.data.len())
+            .filter(|end| *end <= MAX_FILE_SIZE)
+        else {
+            return file_too_large();
+        };
+        if data.len() < end {
+            data.resize(end, 0);
         }
+        data[start..end].copy_from_slice(&message.data);
+        state.modified(&owner(session));
+
+        Message::Rwrite(Rwrite {
+            count: u32::try_from(message.data.len()).unwrap(), // unwrap - 9p data cannot exceed u32 size
+        })
     }
 
-    fn next_qid(&self, is_dir: bool) -> Qid {
-        let mut next_path = self.next_qid_path.lock().unwrap();
-        let path = *next_path;
-        *next_path += 1;
+    async fn clunk(&self, session: &Session<FidEntry>, message: &Tclunk) -> Message {
+        match session.fids().clunk(message.fid) {
+            Ok(_) => Message::Rclunk(Rclunk),
+            Err(e) => e.into(),
+        }
+    }
 
-        Qid {
-            qtype: if is_dir { 0x80 } else { 0 }, // QTDIR or QTFILE
-            version: 0,
-            path,
+    async fn remove(&self, session: &Session<FidEntry>, message: &Tremove) -> Message {
+        // remove clunks the fid even when the file cannot be removed
+        let entry = match session.fids().clunk(message.fid) {
+            Ok(entry) => entry,
+            Err(e) => return e.into(),
+        };
+        let node = &entry.node;
+
+        if Arc::ptr_eq(node, &self.root) {
+            return Message::error_with_errno("cannot remove the root".to_string(), EBUSY);
         }
+        let Some(parent) = node.parent.upgrade() else {
+            return FidError::NotFound.into();
+        };
+
+        // the parent is locked before the node, like everywhere else
+        let mut parent_state = parent.state();
+        let name = {
+            let state = node.state();
+            if let Contents::Dir(children) = &state.contents {
+                if !children.is_empty() {
+                    return Message::error_with_errno("directory not empty".to_string(), ENOTEMPTY);
+                }
+            }
+            state.name.clone()
+        };
+
+        let Contents::Dir(children) = &mut parent_state.contents else {
+            return FidError::NotFound.into();
+        };
+        // the node may already be gone, removed through another fid
+        if !children
+            .get(&name)
+            .is_some_and(|child| Arc::ptr_eq(child, node))
+        {
+            return FidError::NotFound.into();
+        }
+        children.remove(&name);
+        parent_state.modified(&owner(session));
+
+        Message::Rremove(Rremove)
     }
 
-    /// Find a node by path elements
-    fn find_node(&self, path_elements: &[String]) -> Option<Arc<Mutex<FsNode>>> {
-        if path_elements.is_empty() {
-            return Some(self.root.clone());
+    async fn stat(&self, session: &Session<FidEntry>, message: &Tstat) -> Message {
+        match session.fids().get(message.fid) {
+            Ok(entry) => Message::Rstat(Rstat {
+                stat: entry.node.stat(),
+            }),
+            Err(e) => e.into(),
         }
+    }
 
-        let mut current = self.root.clone();
+    async fn wstat(&self, session: &Session<FidEntry>, message: &Twstat) -> Message {
+        let entry = match session.fids().get(message.fid) {
+            Ok(entry) => entry,
+            Err(e) => return e.into(),
+        };
+        let node = &entry.node;
+        let stat = &message.stat;
+
+        // the parent holds the name, so it is locked first and kept locked during a rename
+        let parent = node.parent.upgrade();
+        let mut parent_state = parent.as_ref().map(|parent| parent.state());
+        let mut state = node.state();
+
+        // every change is checked before any is made, a wstat either applies fully or not at all
+        let mode = (stat.mode != FileMode::DontTouch).then_some(stat.mode);
+        if mode.is_some_and(|mode| mode.contains(FileMode::Dir) != state.is_dir()) {
+            return Message::error_with_errno(
+                "cannot change a directory into a file or back".to_string(),
+                EINVAL,
+            );
+        }
+
+        let length = (stat.length != u64::MAX).then_some(stat.length);
+        let length = match (length, &state.contents) {
+            (Some(length), Contents::File(_)) => match usize::try_from(length) {
+                Ok(length) if length <= MAX_FILE_SIZE => Some(length),
+                _ => return file_too_large(),
+            },
+            (Some(length), Contents::Dir(_)) if length != 0 => return is_a_directory(),
+            _ => None,
+        };
 
-        for element in path_elements {
-            // use a block scope to ensure the lock is released before reassigning current
-            let next_node = {
-                let node = current.lock().unwrap();
-                match &*node {
-                    FsNode::Directory { children, .. } => children.get(element).cloned(),
-                    FsNode::File { .. } => None, // Cannot navigate into a file
+        let rename = !stat.name.is_empty() && stat.name != state.name;
+        if rename {
+            if !valid_name(&stat.name) {
+                return invalid_name();
+            }
+            match parent_state.as_ref().map(|parent| &parent.contents) {
+                Some(Contents::Dir(children)) if children.contains_key(&stat.name) => {
+                    return file_exists();
                 }
-            };
+                Some(Contents::Dir(_)) => {}
+                _ => {
+                    return Message::error_with_errno("cannot rename the root".to_string(), EBUSY);
+                }
+            }
+        }
 
-            // process the result after the lock is released
-            if let Some(child) = next_node {
-                current = child;
-            } else {
-                return None;
+        if let Some(mode) = mode {
+            state.mode = mode;
+        }
+        if let (Some(length), Contents::File(data)) = (length, &mut state.contents) {
+            data.resize(length, 0);
+            state.modified(&owner(session));
+        }
+        if stat.mtime != u32::MAX {
+            state.mtime = stat.mtime;
+        }
+        if rename {
+            if let Some(parent_state) = parent_state.as_mut() {
+                // a node that was removed through another fid only changes its own name
+                if let Contents::Dir(children) = &mut parent_state.contents {
+                    if children
+                        .get(&state.name)
+                        .is_some_and(|child| Arc::ptr_eq(child, node))
+                    {
+                        children.remove(&state.name);
+                        children.insert(stat.name.clone(), node.clone());
+                    }
+                }
+                parent_state.modified(&owner(session));
             }
+            state.name.clone_from(&stat.name);
         }
 
-        Some(current)
+        Message::Rwstat(Rwstat)
     }
+}
 
-    // create a directory entry list for Rread of a directory
-    fn create_dir_entries(&self, dir_node: &FsNode) -> Vec<u8> {
-        let mut result = Vec::new();
+/// Read the next whole entries of an opened directory that fit in `count` bytes
+fn read_dir(entry: &FidEntry, offset: u64, count: u32, dialect: Dialect) -> Message {
+    // only whole entries are returned, so offsets other than where the previous read stopped
+    // would land in the middle of one
+    let mut cursor = entry.cursor.lock().unwrap();
+    if offset == 0 {
+        let mut entries = VecDeque::new();
+        for child in entry.node.children() {
+            let mut record = Vec::new();
+            if let Err(e) = child.stat().encode_as(&mut record, dialect) {
+                return Message::error(format!("failed to encode stat: {e}"));
+            }
+            entries.push_back(record);
+        }
+        *cursor = DirCursor { offset: 0, entries };
+    } else if offset != cursor.offset {
+        return FidError::BadOffset.into();
+    }
 
-        if let FsNode::Directory { children, .. } = dir_node {
-            // create a consistent stat structure format based on 9P2000 spec
-            for (name, child) in children {
-                let child_node = child.lock().unwrap();
-                let stat = child_node.to_stat();
+    let mut data = Vec::new();
+    while let Some(record) = cursor.entries.front() {
+        if data.len() + record.len() > count as usize {
+            break;
+        }
+        data.extend_from_slice(record);
+        cursor.entries.pop_front();
+    }
 
-                // format correctly according to 9P spec:
-                // size[2] + type[2] + dev[4] + qid.type[1] + qid.vers[4] + qid.path[8] +
-                // mode[4] + atime[4] + mtime[4] + length[8] +
-                // name[s] + uid[s] + gid[s] + muid[s]
+    if data.is_empty() && !cursor.entries.is_empty() {
+        return Message::error_with_errno(
+            "read count too small for a directory entry".to_string(),
+            EINVAL,
+        );
+    }
 
-                // Start with calculating the size (excluding size field itself)
-                let strings_len = 2
-                    + stat.name.len()
-                    + 2
-                    + stat.uid.len()
-                    + 2
-                    + stat.gid.len()
-                    + 2
-                    + stat.muid.len();
-                let fixed_fields_len = 2 + 4 + 13 + 4 + 4 + 4 + 8; // All fixed-size fields
-                let stat_size = fixed_fields_len + strings_len;
+    cursor.offset += data.len() as u64;
+    Message::Rread(Rread { data: data.into() })
+}
 
-                // Now build the entry
-                let mut entry = Vec::with_capacity(stat_size + 2);
+/// Whether `mode` opens a file for writing
+fn writes(mode: FlagSet<OpenMode>) -> bool {
+    let access = mode.bits() & 3;
+    access == OpenMode::Write as u8 || access == OpenMode::ReadWrite as u8
+}
 
-                // Size field (2 bytes) - excluding itself
-                entry.extend_from_slice(&(stat_size as u16).to_le_bytes());
+/// The user changes made through `session` are recorded for
+fn owner<F>(session: &Session<F>) -> String {
+    session
+        .uname()
+        .filter(|uname| !uname.is_empty())
+        .unwrap_or_else(|| String::from("none"))
+}
 
-                // Type field (2 bytes)
-                entry.extend_from_slice(&(stat.qtype as u16).to_le_bytes());
+fn iounit<F>(session: &Session<F>) -> u32 {
+    session.msize().saturating_sub(P9_IOHDRSZ)
+}
 
-                // Dev field (4 bytes)
-                entry.extend_from_slice(&stat.dev.to_le_bytes());
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| u32::try_from(now.as_secs()).unwrap_or(u32::MAX))
+}
 
-                // Qid (13 bytes: 1+4+8)
-                entry.push(stat.qid.qtype);
-                entry.extend_from_slice(&stat.qid.version.to_le_bytes());
-                entry.extend_from_slice(&stat.qid.path.to_le_bytes());
+fn is_a_directory() -> Message {
+    Message::error_with_errno("is a directory".to_string(), EISDIR)
+}
 
-                // Mode (4 bytes)
-                entry.extend_from_slice(&stat.mode.to_le_bytes());
+fn file_exists() -> Message {
+    Message::error_with_errno("file exists".to_string(), EEXIST)
+}
 
-                // Atime (4 bytes)
-                entry.extend_from_slice(&stat.atime.to_le_bytes());
+fn file_too_large() -> Message {
+    Message::error_with_errno("file too large".to_string(), EFBIG)
+}
 
-                // Mtime (4 bytes)
-                entry.extend_from_slice(&stat.mtime.to_le_bytes());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stowage_proto::{consts::P9_NOFID, Decodable, Rerror};
+    use tokio_test::block_on;
+
+    const ROOT_FID: u32 = 0;
+
+    fn setup() -> (Handler, Session<FidEntry>) {
+        let handler = Handler::new();
+        let session = Session::new();
+        let attach = Tattach {
+            fid: ROOT_FID,
+            afid: P9_NOFID,
+            uname: String::new(),
+            aname: String::new(),
+            n_uname: P9_NONUNAME,
+        };
+        assert!(matches!(
+            block_on(handler.attach(&session, &attach)),
+            Message::Rattach(_)
+        ));
 
-                // Length (8 bytes)
-                entry.extend_from_slice(&stat.length.to_le_bytes());
+        (handler, session)
+    }
 
-                // Strings
-                for s in &[&stat.name, &stat.uid, &stat.gid, &stat.muid] {
-                    entry.extend_from_slice(&(s.len() as u16).to_le_bytes());
-                    entry.extend_from_slice(s.as_bytes());
-                }
+    fn walk(
+        session: &Session<FidEntry>,
+        handler: &Handler,
+        newfid: u32,
+        names: &[&str],
+    ) -> Message {
+        let twalk = Twalk {
+            fid: ROOT_FID,
+            newfid,
+            wnames: names.iter().map(ToString::to_string).collect(),
+        };
+        block_on(handler.walk(session, &twalk))
+    }
 
-                // Add to result
-                result.extend_from_slice(&entry);
-            }
+    /// Create `name` in the directory at `dir` through `fid`, leaving it open
+    fn create(
+        handler: &Handler,
+        session: &Session<FidEntry>,
+        fid: u32,
+        dir: &[&str],
+        name: &str,
+        perm: FlagSet<FileMode>,
+    ) -> Message {
+        assert!(matches!(
+            walk(session, handler, fid, dir),
+            Message::Rwalk(_)
+        ));
+        let tcreate = Tcreate {
+            fid,
+            name: name.to_string(),
+            perm,
+            mode: if perm.contains(FileMode::Dir) {
+                OpenMode::Read.into()
+            } else {
+                OpenMode::ReadWrite.into()
+            },
+            extension: String::new(),
+        };
+        block_on(handler.create(session, &tcreate))
+    }
+
+    fn read(handler: &Handler, session: &Session<FidEntry>, fid: u32, offset: u64) -> Message {
+        let tread = Tread {
+            fid,
+            offset,
+            count: 4096,
+        };
+        block_on(handler.read(session, &tread))
+    }
+
+    fn perm(bits: u32) -> FlagSet<FileMode> {
+        FlagSet::new_truncated(bits)
+    }
+
+    #[test]
+    fn written_data_reads_back() {
+        let (handler, session) = setup();
+        assert!(matches!(
+            create(&handler, &session, 1, &[], "file", perm(0o644)),
+            Message::Rcreate(_)
+        ));
+
+        for (offset, data) in [(0, &b"hello"[..]), (8, b"world")] {
+            let twrite = Twrite {
+                fid: 1,
+                offset,
+                data: data.to_vec().into(),
+            };
+            assert!(matches!(
+                block_on(handler.write(&session, &twrite)),
+                Message::Rwrite(Rwrite { count: 5 })
+            ));
         }
 
-        result
+        let Message::Rread(rread) = read(&handler, &session, 1, 0) else {
+            panic!("read failed");
+        };
+        assert_eq!(&rread.data[..], b"hello\0\0\0world");
+
+        // a fresh fid sees the same contents, and its length
+        assert!(matches!(
+            walk(&session, &handler, 2, &["file"]),
+            Message::Rwalk(_)
+        ));
+        let Message::Rstat(rstat) = block_on(handler.stat(&session, &Tstat { fid: 2 })) else {
+            panic!("stat failed");
+        };
+        assert_eq!(rstat.stat.length, 13);
+        assert_eq!(rstat.stat.name, "file");
     }
-}
 
-impl MessageHandler for Handler {
-    async fn handle_message(&self, message: Message) -> Message {
-        info!(?message);
-
-        todo!()
-        // match message {
-        //     Message::Tversion { tag, msize, .. } => Message::Rversion {
-        //         tag,
-        //         msize: msize.min(8192),
-        //         version: "9P2000.L".to_string(),
-        //     },
-
-        //     Message::Tauth { tag, .. } => {
-        //         // We don't require authentication
-        //         Message::Rlerror {
-        //             tag,
-        //             ecode: 2, // EOPNOTSUPP (Operation not supported)
-        //         }
-        //     }
-
-        //     Message::Tattach { tag, fid, .. } => {
-        //         // Create a new fid pointing to the root directory
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         fids.insert(
-        //             fid,
-        //             FidState {
-        //                 node: self.root.clone(),
-        //                 is_open: false,
-        //                 open_mode: None,
-        //                 path: Vec::new(),
-        //             },
-        //         );
-
-        //         Message::Rattach {
-        //             tag,
-        //             qid: self.root.lock().unwrap().qid(),
-        //         }
-        //     }
-
-        //     Message::Twalk {
-        //         tag,
-        //         fid,
-        //         newfid,
-        //         wnames,
-        //     } => {
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         // Check if source fid exists
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Source fid not found".to_string(),
-        //             };
-        //         }
-
-        //         // Special case: empty walk = clone the fid
-        //         if wnames.is_empty() {
-        //             let source_state = fids.get(&fid).unwrap().clone();
-        //             fids.insert(newfid, source_state);
-
-        //             return Message::Rwalk {
-        //                 tag,
-        //                 wqids: Vec::new(),
-        //             };
-        //         }
-
-        //         // Get the starting point and create a path copy
-        //         let source_state = fids.get(&fid).unwrap();
-        //         let mut current_path = source_state.path.clone();
-        //         let mut current_node = source_state.node.clone();
-        //         let mut wqids = Vec::new();
-
-        //         // Walk through each element
-        //         for wname in &wnames {
-        //             // This block scope ensures the lock is released before reassigning current_node
-        //             let next_node = {
-        //                 let node = current_node.lock().unwrap();
-        //                 match &*node {
-        //                     FsNode::Directory { children, .. } => {
-        //                         if let Some(child) = children.get(wname) {
-        //                             Some(child.clone())
-        //                         } else {
-        //                             None
-        //                         }
-        //                     }
-        //                     FsNode::File { .. } => {
-        //                         // Cannot walk through a file
-        //                         return Message::Rerror {
-        //                             tag,
-        //                             ename: "Cannot walk through a file".to_string(),
-        //                         };
-        //                     }
-        //                 }
-        //             };
-
-        //             // Process the result after the lock is released
-        //             if let Some(child) = next_node {
-        //                 current_path.push(wname.clone());
-        //                 wqids.push(child.lock().unwrap().qid());
-        //                 current_node = child; // No borrowing conflict now
-        //             } else {
-        //                 // Path element not found
-        //                 if wqids.is_empty() {
-        //                     return Message::Rlerror { tag, ecode: 2 };
-        //                 }
-        //                 break;
-        //             }
-        //         }
-
-        //         // Create the new fid if we walked the entire path
-        //         if wqids.len() == wnames.len() {
-        //             fids.insert(
-        //                 newfid,
-        //                 FidState {
-        //                     node: current_node,
-        //                     is_open: false,
-        //                     open_mode: None,
-        //                     path: current_path,
-        //                 },
-        //             );
-        //         }
-
-        //         Message::Rwalk { tag, wqids }
-        //     }
-
-        //     Message::Tlopen { tag, fid, flags } => {
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         // Check if fid exists
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rlerror {
-        //                 tag,
-        //                 ecode: 2, // ENOENT
-        //             };
-        //         }
-
-        //         let fid_state = fids.get_mut(&fid).unwrap();
-
-        //         if fid_state.is_open {
-        //             return Message::Rlerror {
-        //                 tag,
-        //                 ecode: 9, // EBADF
-        //             };
-        //         }
-
-        //         // Update the atime
-        //         fid_state.node.lock().unwrap().update_atime();
-
-        //         // Mark as open
-        //         fid_state.is_open = true;
-        //         fid_state.open_mode = Some(flags);
-
-        //         let node = fid_state.node.lock().unwrap();
-        //         let qid = node.qid();
-
-        //         // Check if this is a directory and open flags are compatible
-        //         match &*node {
-        //             FsNode::Directory { .. } => {
-        //                 // For directories, typically only O_RDONLY (0) is allowed
-        //                 if flags & 3 != 0 {
-        //                     // Check if not O_RDONLY
-        //                     return Message::Rlerror {
-        //                         tag,
-        //                         ecode: 21, // EISDIR
-        //                     };
-        //                 }
-        //             }
-        //             FsNode::File { .. } => {
-        //                 // Files can have any open mode
-        //             }
-        //         }
-
-        //         Message::Rlopen {
-        //             tag,
-        //             qid,
-        //             iounit: 8192, // Maximum recommended transfer size
-        //         }
-        //     }
-
-        //     Message::Tread {
-        //         tag,
-        //         fid,
-        //         offset,
-        //         count,
-        //     } => {
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         // Check if fid exists and is open
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not found".to_string(),
-        //             };
-        //         }
-
-        //         let fid_state = fids.get_mut(&fid).unwrap();
-
-        //         if !fid_state.is_open {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not open".to_string(),
-        //             };
-        //         }
-
-        //         let mut node = fid_state.node.lock().unwrap();
-        //         node.update_atime();
-
-        //         match &*node {
-        //             FsNode::File { content, .. } => {
-        //                 // Read from file
-        //                 let start = offset as usize;
-        //                 let end = (offset + count as u64).min(content.len() as u64) as usize;
-
-        //                 let data = if start >= content.len() {
-        //                     Vec::new() // End of file
-        //                 } else {
-        //                     content[start..end].to_vec()
-        //                 };
-
-        //                 Message::Rread { tag, data }
-        //             }
-        //             FsNode::Directory { .. } => {
-        //                 // For directories, we need to return directory entries
-        //                 let dir_entries = self.create_dir_entries(&node);
-
-        //                 // Apply offset and count
-        //                 let start = offset as usize;
-        //                 let end = (offset + count as u64).min(dir_entries.len() as u64) as usize;
-
-        //                 let data = if start >= dir_entries.len() {
-        //                     Vec::new() // End of directory
-        //                 } else {
-        //                     dir_entries[start..end].to_vec()
-        //                 };
-
-        //                 Message::Rread { tag, data }
-        //             }
-        //         }
-        //     }
-
-        //     Message::Twrite {
-        //         tag,
-        //         fid,
-        //         offset,
-        //         data,
-        //     } => {
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         // check if fid exists and is open for writing
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not found".to_string(),
-        //             };
-        //         }
-
-        //         let fid_state = fids.get_mut(&fid).unwrap();
-
-        //         if !fid_state.is_open {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not open".to_string(),
-        //             };
-        //         }
-
-        //         // check write permission (simplified)
-        //         let open_mode = fid_state.open_mode.unwrap_or(0);
-        //         if open_mode & 0x01 == 0 && open_mode & 0x02 == 0 {
-        //             // Not O_WRITE or O_RDWR
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Permission denied".to_string(),
-        //             };
-        //         }
-
-        //         let mut node = fid_state.node.lock().unwrap();
-
-        //         match &mut *node {
-        //             FsNode::File {
-        //                 ref mut content, ..
-        //             } => {
-        //                 // write to file
-        //                 let start = offset as usize;
-
-        //                 // ensure file is large enough
-        //                 if start + data.len() > content.len() {
-        //                     content.resize(start + data.len(), 0);
-        //                 }
-
-        //                 // write the data
-        //                 content[start..start + data.len()].copy_from_slice(&data);
-
-        //                 // update modification time
-        //                 node.update_mtime();
-
-        //                 Message::Rwrite {
-        //                     tag,
-        //                     count: data.len() as u32,
-        //                 }
-        //             }
-        //             FsNode::Directory { .. } => Message::Rerror {
-        //                 tag,
-        //                 ename: "Cannot write to directory".to_string(),
-        //             },
-        //         }
-        //     }
-
-        //     Message::Tclunk { tag, fid } => {
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         if fids.remove(&fid).is_some() {
-        //             Message::Rclunk { tag }
-        //         } else {
-        //             Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not found".to_string(),
-        //             }
-        //         }
-        //     }
-
-        //     Message::Tremove { tag, fid } => {
-        //         let mut fids = self.fids.lock().unwrap();
-
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not found".to_string(),
-        //             };
-        //         }
-
-        //         let fid_state = fids.get(&fid).unwrap().clone();
-        //         let path_elements = fid_state.path.clone();
-
-        //         // cannot remove root
-        //         if path_elements.is_empty() {
-        //             fids.remove(&fid);
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Cannot remove root directory".to_string(),
-        //             };
-        //         }
-
-        //         // find parent directory
-        //         let parent_path = &path_elements[0..path_elements.len() - 1];
-        //         let filename = path_elements.last().unwrap();
-
-        //         if let Some(parent_node) = self.find_node(parent_path) {
-        //             let mut parent = parent_node.lock().unwrap();
-
-        //             match &mut *parent {
-        //                 FsNode::Directory {
-        //                     ref mut children, ..
-        //                 } => {
-        //                     // remove the entry
-        //                     if children.remove(filename).is_some() {
-        //                         // update modification time
-        //                         parent.update_mtime();
-
-        //                         // remove the fid regardless of success
-        //                         fids.remove(&fid);
-
-        //                         return Message::Rremove { tag };
-        //                     } else {
-        //                         fids.remove(&fid);
-        //                         return Message::Rerror {
-        //                             tag,
-        //                             ename: "File not found".to_string(),
-        //                         };
-        //                     }
-        //                 }
-        //                 FsNode::File { .. } => {
-        //                     fids.remove(&fid);
-        //                     return Message::Rerror {
-        //                         tag,
-        //                         ename: "Parent is not a directory".to_string(),
-        //                     };
-        //                 }
-        //             }
-        //         } else {
-        //             fids.remove(&fid);
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Parent directory not found".to_string(),
-        //             };
-        //         }
-        //     }
-
-        //     Message::Tgetattr {
-        //         tag,
-        //         fid,
-        //         request_mask,
-        //     } => {
-        //         let fids = self.fids.lock().unwrap();
-
-        //         // Check if fid exists
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rlerror {
-        //                 tag,
-        //                 ecode: 2, // ENOENT
-        //             };
-        //         }
-
-        //         let fid_state = fids.get(&fid).unwrap();
-        //         let node = fid_state.node.lock().unwrap();
-
-        //         // Default values
-        //         let (mode, size, nlink) = match &*node {
-        //             FsNode::Directory { mode, children, .. } => {
-        //                 // For directories: appropriate mode with directory bit, size 0, link count based on children + 2 (. and ..)
-        //                 (*mode | 0x80000000, 0, (children.len() + 2) as u64)
-        //             }
-        //             FsNode::File { mode, content, .. } => {
-        //                 // For files: file mode, content size, link count 1
-        //                 (*mode, content.len() as u64, 1)
-        //             }
-        //         };
-
-        //         // TODO: filter based on request_mask
-        //         let valid = 0xFFFFFFFF; // all fields valid
-
-        //         Message::Rgetattr {
-        //             tag,
-        //             valid,
-        //             qid: node.qid(),
-        //             mode,
-        //             uid: 1000, // default user ID
-        //             gid: 1000, // default group ID
-        //             nlink,
-        //             rdev: 0, // not a device file
-        //             size,
-        //             blksize: 4096,                // default block size
-        //             blocks: (size + 4095) / 4096, // number of blocks used (rounded up)
-        //             atime_sec: node.atime_sec(),
-        //             atime_nsec: node.atime_nsec(),
-        //             mtime_sec: node.mtime_sec(),
-        //             mtime_nsec: node.mtime_nsec(),
-        //             ctime_sec: node.ctime_sec(),
-        //             ctime_nsec: node.ctime_nsec(),
-        //             btime_sec: node.creation_time_sec(),
-        //             btime_nsec: node.creation_time_nsec(),
-        //             gen: 0,          // generation number (not used)
-        //             data_version: 0, // data version (not used)
-        //         }
-        //     }
-
-        //     Message::Tstat { tag, fid } => {
-        //         let fids = self.fids.lock().unwrap();
-
-        //         // check if fid exists
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not found".to_string(),
-        //             };
-        //         }
-
-        //         let fid_state = fids.get(&fid).unwrap();
-        //         let node = fid_state.node.lock().unwrap();
-
-        //         Message::Rstat {
-        //             tag,
-        //             stat: node.to_stat(),
-        //         }
-        //     }
-
-        //     Message::Twstat { tag, fid, stat } => {
-        //         let fids = self.fids.lock().unwrap();
-
-        //         // check if fid exists
-        //         if !fids.contains_key(&fid) {
-        //             return Message::Rerror {
-        //                 tag,
-        //                 ename: "Fid not found".to_string(),
-        //             };
-        //         }
-
-        //         let fid_state = fids.get(&fid).unwrap();
-        //         let mut node = fid_state.node.lock().unwrap();
-
-        //         // for simplicity, we only allow changing mode and name
-        //         match &mut *node {
-        //             FsNode::File {
-        //                 ref mut name,
-        //                 ref mut mode,
-        //                 ..
-        //             } => {
-        //                 // update fields if they're not ~0 (which means don't change)
-        //                 if stat.mode != 0xFFFFFFFF {
-        //                     *mode = stat.mode & 0o777; // Only allow changing permission bits
-        //                 }
-
-        //                 if !stat.name.is_empty() {
-        //                     // changing name would require updating the parent directory too
-        //                     // for simplicity, we don't allow this
-        //                     // *name = stat.name.clone();
-        //                     return Message::Rerror {
-        //                         tag,
-        //                         ename: "Changing name not supported".to_string(),
-        //                     };
-        //                 }
-
-        //                 node.update_mtime();
-
-        //                 Message::Rwstat { tag }
-        //             }
-        //             FsNode::Directory {
-        //                 ref mut name,
-        //                 ref mut mode,
-        //                 ..
-        //             } => {
-        //                 // update fields if they're not ~0 (which means don't change)
-        //                 if stat.mode != 0xFFFFFFFF {
-        //                     *mode = stat.mode & 0o777; // Only allow changing permission bits
-        //                 }
-
-        //                 if !stat.name.is_empty() {
-        //                     // changing name would require updating the parent directory too
-        //                     // for simplicity, we don't allow this
-        //                     // *name = stat.name.clone();
-        //                     return Message::Rerror {
-        //                         tag,
-        //                         ename: "Changing name not supported".to_string(),
-        //                     };
-        //                 }
-
-        //                 node.update_mtime();
-
-        //                 Message::Rwstat { tag }
-        //             }
-        //         }
-        //     }
-
-        //     _ => Message::Rerror {
-        //         tag: message.get_tag(),
-        //         ename: "Operation not implemented".to_string(),
-        //     },
-        // }
+    #[test]
+    fn created_files_are_listed_and_can_be_removed() {
+        let (handler, session) = setup();
+        assert!(matches!(
+            create(
+                &handler,
+                &session,
+                1,
+                &[],
+                "dir",
+                FileMode::Dir | perm(0o755)
+            ),
+            Message::Rcreate(_)
+        ));
+        assert!(matches!(
+            create(&handler, &session, 2, &["dir"], "file", perm(0o644)),
+            Message::Rcreate(_)
+        ));
+
+        // directories must be empty to be removed
+        assert!(matches!(
+            walk(&session, &handler, 3, &["dir"]),
+            Message::Rwalk(_)
+        ));
+        assert!(matches!(
+            block_on(handler.remove(&session, &Tremove { fid: 3 })),
+            Message::Rerror(_)
+        ));
+        assert!(session.fids().get(3).is_err());
+
+        let Message::Rread(rread) = read(&handler, &session, 1, 0) else {
+            panic!("read failed");
+        };
+        let stat = Stat::decode(&mut rread.data.as_ref()).unwrap();
+        assert_eq!(stat.name, "file");
+        assert!(!stat.mode.contains(FileMode::Dir));
+
+        assert!(matches!(
+            block_on(handler.remove(&session, &Tremove { fid: 2 })),
+            Message::Rremove(_)
+        ));
+        assert!(matches!(
+            walk(&session, &handler, 3, &["dir", "file"]),
+            Message::Rwalk(Rwalk { wqids }) if wqids.len() == 1
+        ));
+        assert!(matches!(
+            read(&handler, &session, 1, 0),
+            Message::Rread(Rread { data }) if data.is_empty()
+        ));
+    }
+
+    #[test]
+    fn renamed_files_are_found_under_their_new_name() {
+        let (handler, session) = setup();
+        assert!(matches!(
+            create(&handler, &session, 1, &[], "old", perm(0o644)),
+            Message::Rcreate(_)
+        ));
+        assert!(matches!(
+            create(&handler, &session, 2, &[], "taken", perm(0o644)),
+            Message::Rcreate(_)
+        ));
+
+        let mut stat = Stat::new_dont_touch();
+        stat.name = "taken".to_string();
+        assert!(matches!(
+            block_on(handler.wstat(
+                &session,
+                &Twstat {
+                    fid: 1,
+                    stat: stat.clone()
+                }
+            )),
+            Message::Rerror(_)
+        ));
+
+        stat.name = "new".to_string();
+        assert!(matches!(
+            block_on(handler.wstat(&session, &Twstat { fid: 1, stat })),
+            Message::Rwstat(_)
+        ));
+        assert!(matches!(
+            walk(&session, &handler, 3, &["old"]),
+            Message::Rerror(_)
+        ));
+        assert!(matches!(
+            walk(&session, &handler, 3, &["new"]),
+            Message::Rwalk(_)
+        ));
+    }
+
+    #[test]
+    fn files_opened_for_reading_cannot_be_written() {
+        let (handler, session) = setup();
+        assert!(matches!(
+            create(&handler, &session, 1, &[], "file", perm(0o644)),
+            Message::Rcreate(_)
+        ));
+        assert!(matches!(
+            walk(&session, &handler, 2, &["file"]),
+            Message::Rwalk(_)
+        ));
+        let topen = Topen {
+            fid: 2,
+            mode: OpenMode::Read.into(),
+        };
+        assert!(matches!(
+            block_on(handler.open(&session, &topen)),
+            Message::Ropen(_)
+        ));
+
+        let twrite = Twrite {
+            fid: 2,
+            offset: 0,
+            data: b"data".to_vec().into(),
+        };
+        assert!(matches!(
+            block_on(handler.write(&session, &twrite)),
+            Message::Rerror(_)
+        ));
+    }
+
+    #[test]
+    fn files_cannot_grow_past_the_size_cap() {
+        let (handler, session) = setup();
+        assert!(matches!(
+            create(&handler, &session, 1, &[], "file", perm(0o644)),
+            Message::Rcreate(_)
+        ));
+
+        for offset in [u64::MAX, MAX_FILE_SIZE as u64] {
+            let twrite = Twrite {
+                fid: 1,
+                offset,
+                data: b"data".to_vec().into(),
+            };
+            assert!(matches!(
+                block_on(handler.write(&session, &twrite)),
+                Message::Rerror(Rerror { errno: EFBIG, .. })
+            ));
+        }
+
+        let mut stat = Stat::new_dont_touch();
+        stat.length = u64::MAX - 1;
+        assert!(matches!(
+            block_on(handler.wstat(&session, &Twstat { fid: 1, stat })),
+            Message::Rerror(Rerror { errno: EFBIG, .. })
+        ));
+
+        let Message::Rstat(rstat) = block_on(handler.stat(&session, &Tstat { fid: 1 })) else {
+            panic!("stat failed");
+        };
+        assert_eq!(rstat.stat.length, 0);
     }
 }
@@ -0,0 +1,384 @@
+//! Who the users attaching to a server are, for checking their permissions
+
+use nix::unistd::{Gid, Group, Uid, User};
+use std::{ffi::CString, fs, io, path::Path};
+
+/// Maps the names users attach with to the ids files are owned by, and back
+pub trait Users: Send + Sync {
+    /// The uid of the user called `name`
+    fn uid(&self, name: &str) -> Option<u32>;
+
+    /// The name of the user `uid`
+    fn uname(&self, uid: u32) -> Option<String>;
+
+    /// The gid of the group called `name`
+    fn gid(&self, name: &str) -> Option<u32>;
+
+    /// The name of the group `gid`
+    fn gname(&self, gid: u32) -> Option<String>;
+
+    /// The groups the user `uid` is a member of, its primary group first
+    fn groups(&self, uid: u32) -> Vec<u32>;
+}
+
+/// The accounts of the host, as the C library looks them up
+pub struct HostUsers;
+
+impl Users for HostUsers {
+    fn uid(&self, name: &str) -> Option<u32> {
+        let user = User::from_name(name).ok().flatten()?;
+        Some(user.uid.as_raw())
+    }
+
+    fn uname(&self, uid: u32) -> Option<String> {
+        let user = User::from_uid(Uid::from_raw(uid)).ok().flatten()?;
+        Some(user.name)
+    }
+
+    fn gid(&self, name: &str) -> Option<u32> {
+        let group = Group::from_name(name).ok().flatten()?;
+        Some(group.gid.as_raw())
+    }
+
+    fn gname(&self, gid: u32) -> Option<String> {
+        let group = Group::from_gid(Gid::from_raw(gid)).ok().flatten()?;
+        Some(group.name)
+    }
+
+    fn groups(&self, uid: u32) -> Vec<u32> {
+        let Ok(Some(user)) = User::from_uid(Uid::from_raw(uid)) else {
+            return Vec::new();
+        };
+        let mut groups = vec![user.gid.as_raw()];
+        let supplementary = CString::new(user.name)
+            .ok()
+            .and_then(|name| nix::unistd::getgrouplist(&name, user.gid).ok())
+            .unwrap_or_default();
+        for gid in supplementary.iter().map(|gid: &Gid| gid.as_raw()) {
+            if !groups.contains(&gid) {
+                groups.push(gid);
+            }
+        }
+        groups
+    }
+}
+
+/// An account in a users database read from files
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Account {
+    name: String,
+    id: u32,
+    /// the primary group of a user, unused for groups
+    gid: u32,
+    /// the names of the users in a group, unused for users
+    members: Vec<String>,
+}
+
+/// Users and groups in the format of `/etc/passwd` and `/etc/group`
+///
+/// Only the name, id and primary group of a user and the name, id and members of a group are
+/// read, so shadowed passwords make no difference.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Passwd {
+    users: Vec<Account>,
+    groups: Vec<Account>,
+}
+
+impl Passwd {
+    /// Read the users in `passwd` and the groups in `group`
+    ///
+    /// # Errors
+    /// - either file cannot be read or has a malformed line
+    pub fn read(passwd: &Path, group: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(passwd)?, &fs::read_to_string(group)?).map_err(
+            |(file, line)| {
+                let path = if file == PASSWD { passwd } else { group };
+                malformed(path, line)
+            },
+        )
+    }
+
+    /// Parse the contents of a passwd and a group file, returning the file and line number of
+    /// the first malformed line
+    fn parse(passwd: &str, group: &str) -> Result<Self, (&'static str, usize)> {
+        let mut users = Vec::new();
+        for (number, fields) in records(passwd) {
+            // name:password:uid:gid:gecos:home:shell
+            let account = match fields[..] {
+                [name, _, uid, gid, ..] => Some(Account {
+                    name: name.to_string(),
+                    id: uid.parse().map_err(|_| (PASSWD, number))?,
+                    gid: gid.parse().map_err(|_| (PASSWD, number))?,
+                    members: Vec::new(),
+                }),
+                _ => None,
+            };
+            users.push(account.ok_or((PASSWD, number))?);
+        }
+
+        let mut groups = Vec::new();
+        for (number, fields) in records(group) {
+            // name:password:gid:members
+            let account = match fields[..] {
+                [name, _, gid, members] => Some(Account {
+                    name: name.to_string(),
+                    id: gid.parse().map_err(|_| (GROUP, number))?,
+                    gid: 0,
+                    members: list(members),
+                }),
+                _ => None,
+            };
+            groups.push(account.ok_or((GROUP, number))?);
+        }
+
+        Ok(Self { users, groups })
+    }
+}
+
+const PASSWD: &str = "passwd";
+const GROUP: &str = "group";
+
+impl Users for Passwd {
+    fn uid(&self, name: &str) -> Option<u32> {
+        by_name(&self.users, name).map(|user| user.id)
+    }
+
+    fn uname(&self, uid: u32) -> Option<String> {
+        by_id(&self.users, uid).map(|user| user.name.clone())
+    }
+
+    fn gid(&self, name: &str) -> Option<u32> {
+        by_name(&self.groups, name).map(|group| group.id)
+    }
+
+    fn gname(&self, gid: u32) -> Option<String> {
+        by_id(&self.groups, gid).map(|group| group.name.clone())
+    }
+
+    fn groups(&self, uid: u32) -> Vec<u32> {
+        let Some(user) = by_id(&self.users, uid) else {
+            return Vec::new();
+        };
+        let mut groups = vec![user.gid];
+        for group in &self.groups {
+            if group.members.contains(&user.name) && !groups.contains(&group.id) {
+                groups.push(group.id);
+            }
+        }
+        groups
+    }
+}
+
+/// Users in the format of Plan 9's `/adm/users`
+///
+/// Each line reads `id:name:leader:members`, where members is a comma separated list of user
+/// names. Every user is also a group of the same id and name, which its leader and members
+/// belong to besides the user itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdmUsers {
+    users: Vec<Account>,
+}
+
+impl AdmUsers {
+    /// Read the users in `path`
+    ///
+    /// # Errors
+    /// - the file cannot be read or has a malformed line
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|line| malformed(path, line))
+    }
+
+    /// Parse the contents of a users file, returning the line number of the first malformed
+    /// line
+    fn parse(users: &str) -> Result<Self, usize> {
+        let mut accounts = Vec::new();
+        for (number, fields) in records(users) {
+            let account = match fields[..] {
+                [id, name, leader, members] => {
+                    let mut members = list(members);
+                    if !leader.is_empty() && !members.iter().any(|member| member == leader) {
+                        members.push(leader.to_string());
+                    }
+                    let id = id.parse().map_err(|_| number)?;
+                    Some(Account {
+                        name: name.to_string(),
+                        id,
+                        gid: id,
+                        members,
+                    })
+                }
+                _ => None,
+            };
+            accounts.push(account.ok_or(number)?);
+        }
+        Ok(Self { users: accounts })
+    }
+}
+
+impl Users for AdmUsers {
+    fn uid(&self, name: &str) -> Option<u32> {
+        by_name(&self.users, name).map(|user| user.id)
+    }
+
+    fn uname(&self, uid: u32) -> Option<String> {
+        by_id(&self.users, uid).map(|user| user.name.clone())
+    }
+
+    fn gid(&self, name: &str) -> Option<u32> {
+        self.uid(name)
+    }
+
+    fn gname(&self, gid: u32) -> Option<String> {
+        self.uname(gid)
+    }
+
+    fn groups(&self, uid: u32) -> Vec<u32> {
+        let Some(user) = by_id(&self.users, uid) else {
+            return Vec::new();
+        };
+        let mut groups = vec![user.id];
+        for group in &self.users {
+            if group.members.contains(&user.name) && !groups.contains(&group.id) {
+                groups.push(group.id);
+            }
+        }
+        groups
+    }
+}
+
+/// The fields of the lines of a colon separated file, numbered from 1, leaving out blank lines
+/// and comments
+fn records(contents: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| (index + 1, line.split(':').collect()))
+}
+
+/// The names in a comma separated list
+fn list(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn by_name<'a>(accounts: &'a [Account], name: &str) -> Option<&'a Account> {
+    accounts.iter().find(|account| account.name == name)
+}
+
+fn by_id(accounts: &[Account], id: u32) -> Option<&Account> {
+    accounts.iter().find(|account| account.id == id)
+}
+
+fn malformed(path: &Path, line: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{line}: malformed entry", path.display()),
+    )
+}
+
+/// Who a fid acts for, settled when it is attached
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    /// the primary group first
+    pub gids: Vec<u32>,
+}
+
+impl Credentials {
+    /// The credentials of the user `uid`, with the groups `users` knows it to be in
+    #[must_use]
+    pub fn of(users: &dyn Users, uid: u32) -> Self {
+        Self {
+            uid,
+            gids: users.groups(uid),
+        }
+    }
+
+    /// Whether the user may read, write or execute what has unix permissions `mode` and is
+    /// owned by `uid` and `gid`
+    ///
+    /// `want` holds the bits asked for, as in the `other` bits of a mode. As in Plan 9 the
+    /// classes add up: what is permitted to others is permitted to the owner too. The host's
+    /// superuser may do anything.
+    #[must_use]
+    pub fn permits(&self, mode: u32, uid: u32, gid: u32, want: u32) -> bool {
+        let mut granted = mode & 0o7;
+        if self.uid == uid {
+            granted |= (mode >> 6) & 0o7;
+        }
+        if self.gids.contains(&gid) {
+            granted |= (mode >> 3) & 0o7;
+        }
+        self.uid == 0 || granted & want == want
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permission_classes_add_up() {
+        let glenda = Credentials {
+            uid: 1000,
+            gids: vec![1000, 50],
+        };
+        // owner, group and others
+        assert!(glenda.permits(0o600, 1000, 0, 0o6));
+        assert!(!glenda.permits(0o600, 1001, 1000, 0o4));
+        assert!(glenda.permits(0o040, 1001, 50, 0o4));
+        assert!(!glenda.permits(0o040, 1001, 51, 0o4));
+        assert!(glenda.permits(0o004, 1001, 51, 0o4));
+        // others' bits count for the owner as well
+        assert!(glenda.permits(0o204, 1000, 0, 0o6));
+        assert!(!glenda.permits(0o400, 1000, 0, 0o6));
+
+        let root = Credentials {
+            uid: 0,
+            gids: vec![0],
+        };
+        assert!(root.permits(0o000, 1000, 1000, 0o7));
+    }
+
+    #[test]
+    fn passwd_and_group_files_name_users_and_their_groups() {
+        let users = Passwd::parse(
+            "root:x:0:0:root:/root:/bin/sh\n\nglenda:x:1000:1000::/home/glenda:/bin/rc\n",
+            "root:x:0:\nglenda:x:1000:\nsys:x:3:glenda,root\n",
+        )
+        .unwrap();
+        assert_eq!(users.uid("glenda"), Some(1000));
+        assert_eq!(users.uname(0).as_deref(), Some("root"));
+        assert_eq!(users.gid("sys"), Some(3));
+        assert_eq!(users.gname(1000).as_deref(), Some("glenda"));
+        assert_eq!(users.groups(1000), vec![1000, 3]);
+        assert_eq!(users.uid("nobody"), None);
+        assert!(users.groups(42).is_empty());
+
+        assert_eq!(
+            Passwd::parse("glenda:x:glenda:1000:::\n", ""),
+            Err((PASSWD, 1))
+        );
+        assert_eq!(Passwd::parse("", "root:x:0:\nsys:x:3\n"), Err((GROUP, 2)));
+    }
+
+    #[test]
+    fn adm_users_are_groups_of_their_leader_and_members() {
+        let users = AdmUsers::parse(
+            "# id:name:leader:members\n0:none::\n1:glenda:glenda:\n2:sys::glenda\n3:upas:glenda:\n",
+        )
+        .unwrap();
+        assert_eq!(users.uid("glenda"), Some(1));
+        assert_eq!(users.gid("sys"), Some(2));
+        assert_eq!(users.gname(3).as_deref(), Some("upas"));
+        assert_eq!(users.groups(1), vec![1, 2, 3]);
+        assert_eq!(users.groups(0), vec![0]);
+
+        assert_eq!(AdmUsers::parse("1:glenda:glenda\n"), Err(1));
+        assert_eq!(AdmUsers::parse("\n-1:adm:adm:\n"), Err(2));
+    }
+}
@@ -0,0 +1,72 @@
+//! The shared-secret challenge–response protocol spoken over an afid
+//!
+//! After Tauth the client reads a challenge of `CHALLENGE_LEN` random bytes from the afid and
+//! writes back the HMAC-SHA256, keyed with the secret, of the challenge followed by the user
+//! name and tree name it is going to attach with. Both names are prefixed with their length
+//! like 9P strings, so that no two pairs of names give the same input.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// How many bytes the server challenges with
+pub const CHALLENGE_LEN: usize = 32;
+
+/// How many bytes the client answers with
+pub const RESPONSE_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &[u8], challenge: &[u8], uname: &str, aname: &str) -> HmacSha256 {
+    // unwrap - HMAC takes keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(challenge);
+    for name in [uname, aname] {
+        let len = u16::try_from(name.len()).unwrap_or(u16::MAX);
+        mac.update(&len.to_le_bytes());
+        mac.update(name.as_bytes());
+    }
+    mac
+}
+
+/// The answer to `challenge` of someone knowing `secret`
+#[must_use]
+pub fn respond(secret: &[u8], challenge: &[u8], uname: &str, aname: &str) -> [u8; RESPONSE_LEN] {
+    mac(secret, challenge, uname, aname)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Whether `response` answers `challenge` with knowledge of `secret`, compared in constant time
+#[must_use]
+pub fn verify(secret: &[u8], challenge: &[u8], uname: &str, aname: &str, response: &[u8]) -> bool {
+    mac(secret, challenge, uname, aname)
+        .verify_slice(response)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_bound_to_secret_challenge_and_names() {
+        let challenge = [7; CHALLENGE_LEN];
+        let response = respond(b"secret", &challenge, "glenda", "");
+        assert!(verify(b"secret", &challenge, "glenda", "", &response));
+
+        assert!(!verify(b"guess", &challenge, "glenda", "", &response));
+        assert!(!verify(
+            b"secret",
+            &[8; CHALLENGE_LEN],
+            "glenda",
+            "",
+            &response
+        ));
+        assert!(!verify(b"secret", &challenge, "bootes", "", &response));
+        // moving a byte from one name to the other changes the answer
+        let response = respond(b"secret", &challenge, "ab", "c");
+        assert!(!verify(b"secret", &challenge, "a", "bc", &response));
+        assert!(!verify(b"secret", &challenge, "ab", "c", &response[1..]));
+    }
+}
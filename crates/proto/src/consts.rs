@@ -1 +1,75 @@
 pub const P9_NOFID: u32 = !0;
+pub const P9_NONUNAME: u32 = !0;
+
+/// msize used until Tversion negotiates one, and the most a server offers by default
+pub const P9_DEFAULT_MSIZE: u32 = 8192;
+/// Bytes of an Rread/Twrite that are not data, `msize - P9_IOHDRSZ` is the largest i/o count
+pub const P9_IOHDRSZ: u32 = 24;
+/// The most names a single Twalk may carry
+pub const P9_MAXWELEM: usize = 16;
+
+/// Linux errno values as carried by 9P2000.u `Rerror` and 9P2000.L `Rlerror`
+pub mod errno {
+    pub const EPERM: u32 = 1;
+    pub const ENOENT: u32 = 2;
+    pub const EIO: u32 = 5;
+    pub const EBADF: u32 = 9;
+    pub const EAGAIN: u32 = 11;
+    pub const ENOMEM: u32 = 12;
+    pub const EACCES: u32 = 13;
+    pub const EBUSY: u32 = 16;
+    pub const EEXIST: u32 = 17;
+    pub const EXDEV: u32 = 18;
+    pub const ENOTDIR: u32 = 20;
+    pub const EISDIR: u32 = 21;
+    pub const EINVAL: u32 = 22;
+    pub const EFBIG: u32 = 27;
+    pub const ENOSPC: u32 = 28;
+    pub const EROFS: u32 = 30;
+    pub const ENAMETOOLONG: u32 = 36;
+    pub const ENOSYS: u32 = 38;
+    pub const ENOTEMPTY: u32 = 39;
+    pub const ELOOP: u32 = 40;
+    pub const ENODATA: u32 = 61;
+    pub const EPROTO: u32 = 71;
+    pub const EMSGSIZE: u32 = 90;
+    pub const EOPNOTSUPP: u32 = 95;
+}
+
+// Tgetattr request_mask and Rgetattr valid bits
+pub const P9_GETATTR_MODE: u64 = 0x0000_0001;
+pub const P9_GETATTR_NLINK: u64 = 0x0000_0002;
+pub const P9_GETATTR_UID: u64 = 0x0000_0004;
+pub const P9_GETATTR_GID: u64 = 0x0000_0008;
+pub const P9_GETATTR_RDEV: u64 = 0x0000_0010;
+pub const P9_GETATTR_ATIME: u64 = 0x0000_0020;
+pub const P9_GETATTR_MTIME: u64 = 0x0000_0040;
+pub const P9_GETATTR_CTIME: u64 = 0x0000_0080;
+pub const P9_GETATTR_INO: u64 = 0x0000_0100;
+pub const P9_GETATTR_SIZE: u64 = 0x0000_0200;
+pub const P9_GETATTR_BLOCKS: u64 = 0x0000_0400;
+pub const P9_GETATTR_BTIME: u64 = 0x0000_0800;
+pub const P9_GETATTR_GEN: u64 = 0x0000_1000;
+pub const P9_GETATTR_DATA_VERSION: u64 = 0x0000_2000;
+pub const P9_GETATTR_BASIC: u64 = 0x0000_07ff;
+pub const P9_GETATTR_ALL: u64 = 0x0000_3fff;
+
+// Tsetattr valid bits
+pub const P9_SETATTR_MODE: u32 = 0x0000_0001;
+pub const P9_SETATTR_UID: u32 = 0x0000_0002;
+pub const P9_SETATTR_GID: u32 = 0x0000_0004;
+pub const P9_SETATTR_SIZE: u32 = 0x0000_0008;
+pub const P9_SETATTR_ATIME: u32 = 0x0000_0010;
+pub const P9_SETATTR_MTIME: u32 = 0x0000_0020;
+pub const P9_SETATTR_CTIME: u32 = 0x0000_0040;
+pub const P9_SETATTR_ATIME_SET: u32 = 0x0000_0080;
+pub const P9_SETATTR_MTIME_SET: u32 = 0x0000_0100;
+
+// Tlock type and Rlock status values
+pub const P9_LOCK_TYPE_RDLCK: u8 = 0;
+pub const P9_LOCK_TYPE_WRLCK: u8 = 1;
+pub const P9_LOCK_TYPE_UNLCK: u8 = 2;
+pub const P9_LOCK_SUCCESS: u8 = 0;
+pub const P9_LOCK_BLOCKED: u8 = 1;
+pub const P9_LOCK_ERROR: u8 = 2;
+pub const P9_LOCK_GRACE: u8 = 3;
@@ -0,0 +1,586 @@
+//! Plan 9 dial strings, naming where a 9P server is reached or served
+//!
+//! - `tcp!host!port` or `net!host!service`, the port defaulting to 564
+//! - `tls!host!port`, TCP with TLS on top, see `crate::tls`
+//! - `unix!path`
+//! - `virtio!tag`, which only the kernel can dial
+//! - `-` for standard input and output
+//!
+//! Hosts may be IPv6 addresses, bracketed or not, and `*` listens on every interface. A bare
+//! socket address such as `127.0.0.1:564` or `[::1]:564` is taken as TCP.
+
+use crate::tls::{ClientConfig, ServerConfig};
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, Join, ReadBuf, Stdin, Stdout},
+    net::{unix::UCred, TcpListener, TcpStream, UnixListener, UnixStream},
+};
+use tokio_rustls::{
+    rustls::pki_types::{CertificateDer, ServerName},
+    server::TlsStream as TlsServerStream,
+    TlsAcceptor, TlsConnector, TlsStream,
+};
+
+/// The port of a 9P server when a dial string does not give one
+pub const DEFAULT_PORT: u16 = 564;
+
+/// The service names a dial string may give instead of a port
+const SERVICES: &[(&str, u16)] = &[("9fs", DEFAULT_PORT), ("9p", DEFAULT_PORT)];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("empty dial string")]
+    Empty,
+
+    #[error("{0}: unknown network (expecting tcp, net, tls, unix, virtio, or -)")]
+    UnknownNetwork(String),
+
+    #[error("{0}: missing address")]
+    MissingAddress(String),
+
+    #[error("{0}: unknown service or invalid port")]
+    InvalidService(String),
+
+    #[error("{0}: too many fields")]
+    TooManyFields(String),
+}
+
+/// Where to reach or serve 9P
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DialString {
+    Tcp { host: String, port: u16 },
+    Tls { host: String, port: u16 },
+    Unix(PathBuf),
+    Virtio(String),
+    Stdio,
+}
+
+impl FromStr for DialString {
+    type Err = ParseError;
+
+    fn from_str(dial: &str) -> Result<Self, Self::Err> {
+        match dial {
+            "" => return Err(ParseError::Empty),
+            "-" => return Ok(DialString::Stdio),
+            _ => {}
+        }
+        if let Ok(addr) = dial.parse::<SocketAddr>() {
+            return Ok(DialString::Tcp {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            });
+        }
+
+        let (network, address) = dial.split_once('!').unwrap_or((dial, ""));
+        if address.is_empty() {
+            return match network {
+                "tcp" | "net" | "tls" | "unix" | "virtio" => {
+                    Err(ParseError::MissingAddress(dial.to_string()))
+                }
+                _ => Err(ParseError::UnknownNetwork(network.to_string())),
+            };
+        }
+
+        match network {
+            "tcp" | "net" => {
+                let (host, port) = parse_host(dial, address)?;
+                Ok(DialString::Tcp { host, port })
+            }
+            "tls" => {
+                let (host, port) = parse_host(dial, address)?;
+                Ok(DialString::Tls { host, port })
+            }
+            // a path may well contain a '!'
+            "unix" => Ok(DialString::Unix(PathBuf::from(address))),
+            "virtio" => Ok(DialString::Virtio(address.to_string())),
+            _ => Err(ParseError::UnknownNetwork(network.to_string())),
+        }
+    }
+}
+
+/// The host and port in the `host!service` address of `dial`
+fn parse_host(dial: &str, address: &str) -> Result<(String, u16), ParseError> {
+    let mut fields = address.split('!');
+    let host = fields.next().unwrap_or_default();
+    let port = fields.next().map_or(Ok(DEFAULT_PORT), parse_service)?;
+    if fields.next().is_some() {
+        return Err(ParseError::TooManyFields(dial.to_string()));
+    }
+    if host.is_empty() {
+        return Err(ParseError::MissingAddress(dial.to_string()));
+    }
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok((host.to_string(), port))
+}
+
+/// The port a service name or number stands for
+fn parse_service(service: &str) -> Result<u16, ParseError> {
+    SERVICES
+        .iter()
+        .find(|(name, _)| *name == service)
+        .map(|(_, port)| *port)
+        .or_else(|| service.parse().ok())
+        .ok_or_else(|| ParseError::InvalidService(service.to_string()))
+}
+
+impl fmt::Display for DialString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialString::Tcp { host, port } => write!(f, "tcp!{host}!{port}"),
+            DialString::Tls { host, port } => write!(f, "tls!{host}!{port}"),
+            DialString::Unix(path) => write!(f, "unix!{}", path.display()),
+            DialString::Virtio(tag) => write!(f, "virtio!{tag}"),
+            DialString::Stdio => write!(f, "-"),
+        }
+    }
+}
+
+impl DialString {
+    /// Connect to the server at this address
+    ///
+    /// `-` is the connection on standard input and output, as handed to a server started by
+    /// inetd or ssh.
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - the address is a virtio tag, or needs TLS and has to be dialed with `connect_tls`
+    pub async fn connect(&self) -> io::Result<Stream> {
+        match self {
+            DialString::Tcp { host, port } => Ok(Stream::Tcp(connect_tcp(host, *port).await?)),
+            DialString::Tls { .. } => Err(needs_tls(self)),
+            DialString::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path).await?)),
+            DialString::Virtio(_) => Err(virtio_unsupported()),
+            DialString::Stdio => Ok(Stream::Stdio(tokio::io::join(
+                tokio::io::stdin(),
+                tokio::io::stdout(),
+            ))),
+        }
+    }
+
+    /// Connect to the server at this `tls!` address, verifying it with `config`
+    ///
+    /// The server's certificate has to be issued for the host of the address.
+    ///
+    /// # Errors
+    /// - the connection cannot be established
+    /// - the server's certificate is not trusted
+    /// - the address is not a `tls!` one
+    pub async fn connect_tls(&self, config: Arc<ClientConfig>) -> io::Result<Stream> {
+        let DialString::Tls { host, port } = self else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{self}: not a tls address"),
+            ));
+        };
+        let name = ServerName::try_from(host.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{host}: {e}")))?;
+        let stream = connect_tcp(host, *port).await?;
+        let stream = TlsConnector::from(config).connect(name, stream).await?;
+        Ok(Stream::Tls(Box::new(stream.into())))
+    }
+
+    /// Listen for connections at this address
+    ///
+    /// A unix socket left behind by a server that is gone is replaced, one still being listened
+    /// on is not. `-` is a single connection that is already there, it is connected to rather
+    /// than listened on.
+    ///
+    /// # Errors
+    /// - the address cannot be bound
+    /// - the address is `-` or a virtio tag, or needs TLS and has to be listened on with
+    ///   `listen_tls`
+    pub async fn listen(&self) -> io::Result<Listener> {
+        match self {
+            DialString::Tcp { host, port } => Ok(Listener::Tcp(bind_tcp(host, *port).await?)),
+            DialString::Tls { .. } => Err(needs_tls(self)),
+            DialString::Unix(path) => {
+                let stale = std::fs::symlink_metadata(path).is_ok_and(|metadata| {
+                    std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())
+                });
+                if stale {
+                    if UnixStream::connect(path).await.is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{}: a server is already listening", path.display()),
+                        ));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+            DialString::Virtio(_) => Err(virtio_unsupported()),
+            DialString::Stdio => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot listen on -, connect to it instead",
+            )),
+        }
+    }
+
+    /// Listen for connections at this `tls!` address, serving TLS with `config`
+    ///
+    /// # Errors
+    /// - the address cannot be bound
+    /// - the address is not a `tls!` one
+    pub async fn listen_tls(&self, config: Arc<ServerConfig>) -> io::Result<Listener> {
+        let DialString::Tls { host, port } = self else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{self}: not a tls address"),
+            ));
+        };
+        Ok(Listener::Tls {
+            listener: bind_tcp(host, *port).await?,
+            acceptor: TlsAcceptor::from(config),
+        })
+    }
+}
+
+async fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect((host, port)).await?;
+    // pipelined requests are small writes that must not wait for each other's acks
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+async fn bind_tcp(host: &str, port: u16) -> io::Result<TcpListener> {
+    let host = if host == "*" { "0.0.0.0" } else { host };
+    TcpListener::bind((host, port)).await
+}
+
+fn needs_tls(dial: &DialString) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{dial}: needs a TLS configuration"),
+    )
+}
+
+fn virtio_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "virtio transports are only reachable by the kernel",
+    )
+}
+
+/// A connection made through a dial string
+pub enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Unix(UnixStream),
+    Stdio(Join<Stdin, Stdout>),
+}
+
+impl Stream {
+    /// The credentials of the process on the other end, known only for unix sockets
+    ///
+    /// # Errors
+    /// - the kernel does not report them
+    pub fn peer_cred(&self) -> io::Result<Option<UCred>> {
+        match self {
+            Stream::Unix(stream) => stream.peer_cred().map(Some),
+            Stream::Tcp(_) | Stream::Tls(_) | Stream::Stdio(_) => Ok(None),
+        }
+    }
+
+    /// The certificate the other end presented over TLS, if any
+    #[must_use]
+    pub fn peer_certificate(&self) -> Option<&CertificateDer<'static>> {
+        match self {
+            Stream::Tls(stream) => stream.get_ref().1.peer_certificates()?.first(),
+            Stream::Tcp(_) | Stream::Unix(_) | Stream::Stdio(_) => None,
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Stdio(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Stdio(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Stdio(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Stdio(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Where connections to a served dial string come in
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls {
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+    },
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Wait for the next connection, returning it along with who made it
+    ///
+    /// A TLS connection still has its handshake ahead of it, so that a slow client holds up
+    /// no one but itself.
+    ///
+    /// # Errors
+    /// - accepting the connection fails
+    pub async fn accept(&self) -> io::Result<(Incoming, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = accept_tcp(listener).await?;
+                Ok((Incoming::Ready(Stream::Tcp(stream)), addr))
+            }
+            Listener::Tls { listener, acceptor } => {
+                let (stream, addr) = accept_tcp(listener).await?;
+                let handshake = acceptor.accept(stream);
+                Ok((Incoming::Handshaking(Box::pin(handshake)), addr))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let peer = match listener.local_addr()?.as_pathname() {
+                    Some(path) => format!("unix!{}", path.display()),
+                    None => "unix".to_string(),
+                };
+                Ok((Incoming::Ready(Stream::Unix(stream)), peer))
+            }
+        }
+    }
+}
+
+impl Listener {
+    /// The TCP address being listened on, with the port the system picked for port 0
+    ///
+    /// # Errors
+    /// - the address cannot be had from the system
+    pub fn tcp_addr(&self) -> io::Result<Option<SocketAddr>> {
+        match self {
+            Listener::Tcp(listener) | Listener::Tls { listener, .. } => {
+                listener.local_addr().map(Some)
+            }
+            Listener::Unix(_) => Ok(None),
+        }
+    }
+}
+
+async fn accept_tcp(listener: &TcpListener) -> io::Result<(TcpStream, String)> {
+    let (stream, addr) = listener.accept().await?;
+    // replies to pipelined requests must not wait for each other's acks
+    if let Err(e) = stream.set_nodelay(true) {
+        tracing::warn!("failed to set TCP_NODELAY for {addr}: {e}");
+    }
+    Ok((stream, addr.to_string()))
+}
+
+type Handshake = Pin<Box<dyn Future<Output = io::Result<TlsServerStream<TcpStream>>> + Send>>;
+
+/// A connection taken by a `Listener`
+pub enum Incoming {
+    Ready(Stream),
+    /// a TLS handshake yet to finish
+    Handshaking(Handshake),
+}
+
+impl Incoming {
+    /// The connection, once it is ready to carry 9P
+    ///
+    /// # Errors
+    /// - the TLS handshake fails
+    pub async fn establish(self) -> io::Result<Stream> {
+        match self {
+            Incoming::Ready(stream) => Ok(stream),
+            Incoming::Handshaking(handshake) => Ok(Stream::Tls(Box::new(handshake.await?.into()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn tcp(host: &str, port: u16) -> DialString {
+        DialString::Tcp {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        let cases = [
+            ("tcp!example.com!5640", tcp("example.com", 5640)),
+            ("tcp!example.com", tcp("example.com", DEFAULT_PORT)),
+            ("net!example.com!9fs", tcp("example.com", 564)),
+            ("tcp!*!564", tcp("*", 564)),
+            ("tcp!::1!564", tcp("::1", 564)),
+            ("tcp![fe80::1]!9fs", tcp("fe80::1", 564)),
+            ("[::1]:3000", tcp("::1", 3000)),
+            ("127.0.0.1:3000", tcp("127.0.0.1", 3000)),
+            (
+                "tls!example.com!5640",
+                DialString::Tls {
+                    host: "example.com".to_string(),
+                    port: 5640,
+                },
+            ),
+            (
+                "unix!/run/a!b.sock",
+                DialString::Unix("/run/a!b.sock".into()),
+            ),
+            ("virtio!tag", DialString::Virtio("tag".to_string())),
+            ("-", DialString::Stdio),
+        ];
+        for (dial, expected) in cases {
+            let parsed: DialString = dial.parse().unwrap();
+            assert_eq!(parsed, expected, "{dial}");
+            assert_eq!(parsed.to_string().parse::<DialString>().unwrap(), parsed);
+        }
+        assert_eq!(tcp("::1", 564).to_string(), "tcp!::1!564");
+
+        assert_eq!("".parse::<DialString>(), Err(ParseError::Empty));
+        assert_eq!(
+            "udp!host!1".parse::<DialString>(),
+            Err(ParseError::UnknownNetwork("udp".to_string()))
+        );
+        assert_eq!(
+            "unix".parse::<DialString>(),
+            Err(ParseError::MissingAddress("unix".to_string()))
+        );
+        assert_eq!(
+            "tcp!host!gopher".parse::<DialString>(),
+            Err(ParseError::InvalidService("gopher".to_string()))
+        );
+        assert_eq!(
+            "tcp!host!1!2".parse::<DialString>(),
+            Err(ParseError::TooManyFields("tcp!host!1!2".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn connects_to_what_it_listens_on() {
+        let path = std::env::temp_dir().join(format!("stowage-dial-{}.sock", std::process::id()));
+        let dial = DialString::Unix(path.clone());
+        let listener = dial.listen().await.unwrap();
+        let mut client = dial.connect().await.unwrap();
+        let (incoming, _) = listener.accept().await.unwrap();
+        let mut server = incoming.establish().await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        // the peer is this process, which owns the socket it made
+        let owner = std::os::unix::fs::MetadataExt::uid(&std::fs::metadata(&path).unwrap());
+        assert_eq!(server.peer_cred().unwrap().unwrap().uid(), owner);
+        // the socket is in use
+        assert!(dial.listen().await.is_err());
+
+        // once the server is gone its socket is taken over
+        drop(listener);
+        drop(server);
+        dial.listen().await.unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn speaks_tls_with_self_signed_certificates() {
+        let dir = std::env::temp_dir().join(format!("stowage-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, pem: String| {
+            let path = dir.join(name);
+            std::fs::write(&path, pem).unwrap();
+            path
+        };
+
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let server_cert = write("server.pem", server.cert.pem());
+        let server_key = write("server.key", server.key_pair.serialize_pem());
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "glenda");
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let client = params.self_signed(&client_key).unwrap();
+        let client_cert = write("client.pem", client.pem());
+        let client_key = write("client.key", client_key.serialize_pem());
+
+        let config = tls::server_config(&server_cert, &server_key, Some(&client_cert)).unwrap();
+        let listener = "tls!127.0.0.1!0"
+            .parse::<DialString>()
+            .unwrap()
+            .listen_tls(config)
+            .await
+            .unwrap();
+        let dial = DialString::Tls {
+            host: "localhost".to_string(),
+            port: listener.tcp_addr().unwrap().unwrap().port(),
+        };
+        let handshake = |config| async {
+            let server = async { listener.accept().await.unwrap().0.establish().await };
+            tokio::join!(dial.connect_tls(config), server)
+        };
+
+        let config = tls::client_config(&server_cert, Some((&client_cert, &client_key))).unwrap();
+        let (client, server) = handshake(config).await;
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        let name = tls::common_name(server.peer_certificate().unwrap());
+        assert_eq!(name.as_deref(), Some("glenda"));
+
+        // a client without a certificate is turned away
+        let config = tls::client_config(&server_cert, None).unwrap();
+        assert!(handshake(config).await.1.is_err());
+        // and a server the client does not trust is hung up on
+        let config = tls::client_config(&client_cert, None).unwrap();
+        assert!(handshake(config).await.0.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,14 +1,24 @@
 use flagset::FlagSet;
 
-use crate::{FileMode, OpenMode, QidType};
+use crate::{consts::P9_NONUNAME, Dialect, FileMode, OpenMode, QidType};
 
 use super::{
-    Message, Qid, Rattach, Rauth, Rclunk, Rcreate, Rerror, Rflush, Ropen, Rread, Rremove, Rstat,
-    Rversion, Rwalk, Rwrite, Rwstat, Stat, TaggedMessage, Tattach, Tauth, Tclunk, Tcreate, Tflush,
-    Topen, Tread, Tremove, Tstat, Tversion, Twalk, Twrite, Twstat,
+    Message, Qid, Rattach, Rauth, Rclunk, Rcreate, Rerror, Rflush, Rfsync, Rgetattr, Rgetlock,
+    Rlcreate, Rlerror, Rlink, Rlock, Rlopen, Rmkdir, Rmknod, Ropen, Rread, Rreaddir, Rreadlink,
+    Rremove, Rrename, Rrenameat, Rsetattr, Rstat, Rstatfs, Rsymlink, Runlinkat, Rversion, Rwalk,
+    Rwrite, Rwstat, Rxattrcreate, Rxattrwalk, Stat, TaggedMessage, Tattach, Tauth, Tclunk, Tcreate,
+    Tflush, Tfsync, Tgetattr, Tgetlock, Tlcreate, Tlink, Tlock, Tlopen, Tmkdir, Tmknod, Topen,
+    Tread, Treaddir, Treadlink, Tremove, Trename, Trenameat, Tsetattr, Tstat, Tstatfs, Tsymlink,
+    Tunlinkat, Tversion, Twalk, Twrite, Twstat, Txattrcreate, Txattrwalk,
 };
 use std::fmt;
 
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl fmt::Display for Qid {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut chars = String::new();
@@ -230,7 +240,11 @@ impl fmt::Display for Rattach {
 
 impl fmt::Display for Rerror {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ename {}", self.ename)
+        write!(f, "ename {}", self.ename)?;
+        if self.errno != 0 {
+            write!(f, " errno {}", self.errno)?;
+        }
+        Ok(())
     }
 }
 
@@ -297,7 +311,11 @@ impl fmt::Display for Tcreate {
             self.name,
             format_perm(self.perm),
             format_mode(self.mode)
-        )
+        )?;
+        if !self.extension.is_empty() {
+            write!(f, " ext '{}'", self.extension)?;
+        }
+        Ok(())
     }
 }
 
@@ -390,6 +408,342 @@ impl fmt::Display for Rwstat {
     }
 }
 
+impl fmt::Display for Rlerror {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ecode {}", self.ecode)
+    }
+}
+
+impl fmt::Display for Tstatfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fid {}", self.fid)
+    }
+}
+
+impl fmt::Display for Rstatfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "type {} bsize {} blocks {} bfree {} bavail {} files {} ffree {} fsid {} namelen {}",
+            self.r#type,
+            self.bsize,
+            self.blocks,
+            self.bfree,
+            self.bavail,
+            self.files,
+            self.ffree,
+            self.fsid,
+            self.namelen
+        )
+    }
+}
+
+impl fmt::Display for Tlopen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fid {} flags {}", self.fid, self.flags)
+    }
+}
+
+impl fmt::Display for Rlopen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "qid ({}) iounit {}", self.qid, self.iounit)
+    }
+}
+
+impl fmt::Display for Tlcreate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} name {} flags {} mode {:o} gid {}",
+            self.fid, self.name, self.flags, self.mode, self.gid
+        )
+    }
+}
+
+impl fmt::Display for Rlcreate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "qid ({}) iounit {}", self.qid, self.iounit)
+    }
+}
+
+impl fmt::Display for Tsymlink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} name {} symtgt {} gid {}",
+            self.fid, self.name, self.symtgt, self.gid
+        )
+    }
+}
+
+impl fmt::Display for Rsymlink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "qid ({})", self.qid)
+    }
+}
+
+impl fmt::Display for Tmknod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dfid {} name {} mode {:o} major {} minor {} gid {}",
+            self.dfid, self.name, self.mode, self.major, self.minor, self.gid
+        )
+    }
+}
+
+impl fmt::Display for Rmknod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "qid ({})", self.qid)
+    }
+}
+
+impl fmt::Display for Trename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fid {} dfid {} name {}", self.fid, self.dfid, self.name)
+    }
+}
+
+impl fmt::Display for Rrename {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Treadlink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fid {}", self.fid)
+    }
+}
+
+impl fmt::Display for Rreadlink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "target {}", self.target)
+    }
+}
+
+impl fmt::Display for Tgetattr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fid {} request_mask {}", self.fid, self.request_mask)
+    }
+}
+
+impl fmt::Display for Rgetattr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "valid {} qid ({}) mode {:o} uid {} gid {} nlink {} rdev {} size {} blksize {} blocks {} atime_sec {} atime_nsec {} mtime_sec {} mtime_nsec {} ctime_sec {} ctime_nsec {} btime_sec {} btime_nsec {} gen {} data_version {}",
+            self.valid,
+            self.qid,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.nlink,
+            self.rdev,
+            self.size,
+            self.blksize,
+            self.blocks,
+            self.atime_sec,
+            self.atime_nsec,
+            self.mtime_sec,
+            self.mtime_nsec,
+            self.ctime_sec,
+            self.ctime_nsec,
+            self.btime_sec,
+            self.btime_nsec,
+            self.gen,
+            self.data_version
+        )
+    }
+}
+
+impl fmt::Display for Tsetattr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} valid {} mode {:o} uid {} gid {} size {} atime_sec {} atime_nsec {} mtime_sec {} mtime_nsec {}",
+            self.fid,
+            self.valid,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+            self.atime_sec,
+            self.atime_nsec,
+            self.mtime_sec,
+            self.mtime_nsec
+        )
+    }
+}
+
+impl fmt::Display for Rsetattr {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Txattrwalk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} newfid {} name {}",
+            self.fid, self.newfid, self.name
+        )
+    }
+}
+
+impl fmt::Display for Rxattrwalk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "size {}", self.size)
+    }
+}
+
+impl fmt::Display for Txattrcreate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} name {} attr_size {} flags {}",
+            self.fid, self.name, self.attr_size, self.flags
+        )
+    }
+}
+
+impl fmt::Display for Rxattrcreate {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Treaddir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} offset {} count {}",
+            self.fid, self.offset, self.count
+        )
+    }
+}
+
+impl fmt::Display for Rreaddir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "count {}", self.data.len())
+    }
+}
+
+impl fmt::Display for Tfsync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fid {} datasync {}", self.fid, self.datasync)
+    }
+}
+
+impl fmt::Display for Rfsync {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Tlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} type {} flags {} start {} length {} proc_id {} client_id {}",
+            self.fid,
+            self.r#type,
+            self.flags,
+            self.start,
+            self.length,
+            self.proc_id,
+            self.client_id
+        )
+    }
+}
+
+impl fmt::Display for Rlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "status {}", self.status)
+    }
+}
+
+impl fmt::Display for Tgetlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fid {} type {} start {} length {} proc_id {} client_id {}",
+            self.fid, self.r#type, self.start, self.length, self.proc_id, self.client_id
+        )
+    }
+}
+
+impl fmt::Display for Rgetlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "type {} start {} length {} proc_id {} client_id {}",
+            self.r#type, self.start, self.length, self.proc_id, self.client_id
+        )
+    }
+}
+
+impl fmt::Display for Tlink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dfid {} fid {} name {}", self.dfid, self.fid, self.name)
+    }
+}
+
+impl fmt::Display for Rlink {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Tmkdir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dfid {} name {} mode {:o} gid {}",
+            self.dfid, self.name, self.mode, self.gid
+        )
+    }
+}
+
+impl fmt::Display for Rmkdir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "qid ({})", self.qid)
+    }
+}
+
+impl fmt::Display for Trenameat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "olddirfid {} oldname {} newdirfid {} newname {}",
+            self.olddirfid, self.oldname, self.newdirfid, self.newname
+        )
+    }
+}
+
+impl fmt::Display for Rrenameat {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Tunlinkat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dirfid {} name {} flags {}",
+            self.dirfid, self.name, self.flags
+        )
+    }
+}
+
+impl fmt::Display for Runlinkat {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -420,6 +774,45 @@ impl fmt::Display for Message {
             Message::Rstat(msg) => write!(f, "Rstat {msg}"),
             Message::Twstat(msg) => write!(f, "Twstat {msg}"),
             Message::Rwstat(msg) => write!(f, "Rwstat {msg}"),
+            Message::Rlerror(msg) => write!(f, "Rlerror {msg}"),
+            Message::Tstatfs(msg) => write!(f, "Tstatfs {msg}"),
+            Message::Rstatfs(msg) => write!(f, "Rstatfs {msg}"),
+            Message::Tlopen(msg) => write!(f, "Tlopen {msg}"),
+            Message::Rlopen(msg) => write!(f, "Rlopen {msg}"),
+            Message::Tlcreate(msg) => write!(f, "Tlcreate {msg}"),
+            Message::Rlcreate(msg) => write!(f, "Rlcreate {msg}"),
+            Message::Tsymlink(msg) => write!(f, "Tsymlink {msg}"),
+            Message::Rsymlink(msg) => write!(f, "Rsymlink {msg}"),
+            Message::Tmknod(msg) => write!(f, "Tmknod {msg}"),
+            Message::Rmknod(msg) => write!(f, "Rmknod {msg}"),
+            Message::Trename(msg) => write!(f, "Trename {msg}"),
+            Message::Rrename(msg) => write!(f, "Rrename {msg}"),
+            Message::Treadlink(msg) => write!(f, "Treadlink {msg}"),
+            Message::Rreadlink(msg) => write!(f, "Rreadlink {msg}"),
+            Message::Tgetattr(msg) => write!(f, "Tgetattr {msg}"),
+            Message::Rgetattr(msg) => write!(f, "Rgetattr {msg}"),
+            Message::Tsetattr(msg) => write!(f, "Tsetattr {msg}"),
+            Message::Rsetattr(msg) => write!(f, "Rsetattr {msg}"),
+            Message::Txattrwalk(msg) => write!(f, "Txattrwalk {msg}"),
+            Message::Rxattrwalk(msg) => write!(f, "Rxattrwalk {msg}"),
+            Message::Txattrcreate(msg) => write!(f, "Txattrcreate {msg}"),
+            Message::Rxattrcreate(msg) => write!(f, "Rxattrcreate {msg}"),
+            Message::Treaddir(msg) => write!(f, "Treaddir {msg}"),
+            Message::Rreaddir(msg) => write!(f, "Rreaddir {msg}"),
+            Message::Tfsync(msg) => write!(f, "Tfsync {msg}"),
+            Message::Rfsync(msg) => write!(f, "Rfsync {msg}"),
+            Message::Tlock(msg) => write!(f, "Tlock {msg}"),
+            Message::Rlock(msg) => write!(f, "Rlock {msg}"),
+            Message::Tgetlock(msg) => write!(f, "Tgetlock {msg}"),
+            Message::Rgetlock(msg) => write!(f, "Rgetlock {msg}"),
+            Message::Tlink(msg) => write!(f, "Tlink {msg}"),
+            Message::Rlink(msg) => write!(f, "Rlink {msg}"),
+            Message::Tmkdir(msg) => write!(f, "Tmkdir {msg}"),
+            Message::Rmkdir(msg) => write!(f, "Rmkdir {msg}"),
+            Message::Trenameat(msg) => write!(f, "Trenameat {msg}"),
+            Message::Rrenameat(msg) => write!(f, "Rrenameat {msg}"),
+            Message::Tunlinkat(msg) => write!(f, "Tunlinkat {msg}"),
+            Message::Runlinkat(msg) => write!(f, "Runlinkat {msg}"),
         }
     }
 }
@@ -501,6 +894,24 @@ impl fmt::Display for Stat {
             length_str,
             type_str,
             dev_str
-        )
+        )?;
+
+        // 9P2000.u fields, only present when decoded from that dialect
+        if !self.extension.is_empty()
+            || self.n_uid != P9_NONUNAME
+            || self.n_gid != P9_NONUNAME
+            || self.n_muid != P9_NONUNAME
+        {
+            write!(
+                f,
+                " ext '{}' nuid {} ngid {} nmuid {}",
+                self.extension,
+                format_fid(self.n_uid),
+                format_fid(self.n_gid),
+                format_fid(self.n_muid)
+            )?;
+        }
+
+        Ok(())
     }
 }
@@ -1,3 +1,4 @@
+use crate::consts::{P9_DEFAULT_MSIZE, P9_NONUNAME};
 use crate::error::{Error, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{Bytes, BytesMut};
@@ -6,10 +7,13 @@ use flagset::{flags, FlagSet};
 use std::io::Cursor;
 use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
 
+pub mod auth;
 pub mod consts;
+pub mod dial;
 pub mod error;
 mod ext;
 mod fmt;
+pub mod tls;
 
 pub trait Encodable {
     /// Encode self to writer and return the number of bytes written
@@ -58,6 +62,82 @@ pub enum MessageType {
     Rstat = 125,
     Twstat = 126,
     Rwstat = 127,
+
+    // 9P2000.L
+    Rlerror = 7,
+    Tstatfs = 8,
+    Rstatfs = 9,
+    Tlopen = 12,
+    Rlopen = 13,
+    Tlcreate = 14,
+    Rlcreate = 15,
+    Tsymlink = 16,
+    Rsymlink = 17,
+    Tmknod = 18,
+    Rmknod = 19,
+    Trename = 20,
+    Rrename = 21,
+    Treadlink = 22,
+    Rreadlink = 23,
+    Tgetattr = 24,
+    Rgetattr = 25,
+    Tsetattr = 26,
+    Rsetattr = 27,
+    Txattrwalk = 30,
+    Rxattrwalk = 31,
+    Txattrcreate = 32,
+    Rxattrcreate = 33,
+    Treaddir = 40,
+    Rreaddir = 41,
+    Tfsync = 50,
+    Rfsync = 51,
+    Tlock = 52,
+    Rlock = 53,
+    Tgetlock = 54,
+    Rgetlock = 55,
+    Tlink = 70,
+    Rlink = 71,
+    Tmkdir = 72,
+    Rmkdir = 73,
+    Trenameat = 74,
+    Rrenameat = 75,
+    Tunlinkat = 76,
+    Runlinkat = 77,
+}
+
+/// Protocol dialect agreed on by Tversion, which decides how the dialect specific
+/// fields of `Stat`, `Rerror`, `Tcreate`, `Tauth` and `Tattach` are encoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    /// 9P2000
+    #[default]
+    Base,
+    /// 9P2000.u
+    Unix,
+    /// 9P2000.L
+    Linux,
+}
+
+impl Dialect {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Dialect::Base => "9P2000",
+            Dialect::Unix => "9P2000.u",
+            Dialect::Linux => "9P2000.L",
+        }
+    }
+
+    /// Parse an exact version string as sent in Tversion/Rversion
+    #[must_use]
+    pub fn from_version(version: &str) -> Option<Self> {
+        match version {
+            "9P2000" => Some(Dialect::Base),
+            "9P2000.u" => Some(Dialect::Unix),
+            "9P2000.L" => Some(Dialect::Linux),
+            _ => None,
+        }
+    }
 }
 
 impl MessageType {
@@ -92,6 +172,45 @@ impl MessageType {
             125 => Ok(MessageType::Rstat),
             126 => Ok(MessageType::Twstat),
             127 => Ok(MessageType::Rwstat),
+            7 => Ok(MessageType::Rlerror),
+            8 => Ok(MessageType::Tstatfs),
+            9 => Ok(MessageType::Rstatfs),
+            12 => Ok(MessageType::Tlopen),
+            13 => Ok(MessageType::Rlopen),
+            14 => Ok(MessageType::Tlcreate),
+            15 => Ok(MessageType::Rlcreate),
+            16 => Ok(MessageType::Tsymlink),
+            17 => Ok(MessageType::Rsymlink),
+            18 => Ok(MessageType::Tmknod),
+            19 => Ok(MessageType::Rmknod),
+            20 => Ok(MessageType::Trename),
+            21 => Ok(MessageType::Rrename),
+            22 => Ok(MessageType::Treadlink),
+            23 => Ok(MessageType::Rreadlink),
+            24 => Ok(MessageType::Tgetattr),
+            25 => Ok(MessageType::Rgetattr),
+            26 => Ok(MessageType::Tsetattr),
+            27 => Ok(MessageType::Rsetattr),
+            30 => Ok(MessageType::Txattrwalk),
+            31 => Ok(MessageType::Rxattrwalk),
+            32 => Ok(MessageType::Txattrcreate),
+            33 => Ok(MessageType::Rxattrcreate),
+            40 => Ok(MessageType::Treaddir),
+            41 => Ok(MessageType::Rreaddir),
+            50 => Ok(MessageType::Tfsync),
+            51 => Ok(MessageType::Rfsync),
+            52 => Ok(MessageType::Tlock),
+            53 => Ok(MessageType::Rlock),
+            54 => Ok(MessageType::Tgetlock),
+            55 => Ok(MessageType::Rgetlock),
+            70 => Ok(MessageType::Tlink),
+            71 => Ok(MessageType::Rlink),
+            72 => Ok(MessageType::Tmkdir),
+            73 => Ok(MessageType::Rmkdir),
+            74 => Ok(MessageType::Trenameat),
+            75 => Ok(MessageType::Rrenameat),
+            76 => Ok(MessageType::Tunlinkat),
+            77 => Ok(MessageType::Runlinkat),
             _ => Err(Error::InvalidMessageType(value)),
         }
     }
@@ -112,6 +231,15 @@ flags! {
         Auth = 0x0800_0000,
 
         Temporary = 0x0400_0000,
+
+        // 9P2000.u extensions
+        Symlink = 0x0200_0000,
+        Device = 0x0080_0000,
+        NamedPipe = 0x0020_0000,
+        Socket = 0x0010_0000,
+        SetUid = 0x0008_0000,
+        SetGid = 0x0004_0000,
+
         OwnerRead = 0x0000_0100,
         OwnerWrite = 0x0000_0080,
         OwnerExec = 0x0000_0040,
@@ -142,6 +270,7 @@ flags! {
         Mount = 0x10,
         Auth = 0x08,
         Tmp = 0x04,
+        Symlink = 0x02, // 9P2000.u
         File = 0x00,
         DontTouch = !0,
     }
@@ -243,12 +372,59 @@ pub enum Message {
     Rstat(Rstat),
     Twstat(Twstat),
     Rwstat(Rwstat),
+
+    // 9P2000.L
+    Rlerror(Rlerror),
+    Tstatfs(Tstatfs),
+    Rstatfs(Rstatfs),
+    Tlopen(Tlopen),
+    Rlopen(Rlopen),
+    Tlcreate(Tlcreate),
+    Rlcreate(Rlcreate),
+    Tsymlink(Tsymlink),
+    Rsymlink(Rsymlink),
+    Tmknod(Tmknod),
+    Rmknod(Rmknod),
+    Trename(Trename),
+    Rrename(Rrename),
+    Treadlink(Treadlink),
+    Rreadlink(Rreadlink),
+    Tgetattr(Tgetattr),
+    Rgetattr(Rgetattr),
+    Tsetattr(Tsetattr),
+    Rsetattr(Rsetattr),
+    Txattrwalk(Txattrwalk),
+    Rxattrwalk(Rxattrwalk),
+    Txattrcreate(Txattrcreate),
+    Rxattrcreate(Rxattrcreate),
+    Treaddir(Treaddir),
+    Rreaddir(Rreaddir),
+    Tfsync(Tfsync),
+    Rfsync(Rfsync),
+    Tlock(Tlock),
+    Rlock(Rlock),
+    Tgetlock(Tgetlock),
+    Rgetlock(Rgetlock),
+    Tlink(Tlink),
+    Rlink(Rlink),
+    Tmkdir(Tmkdir),
+    Rmkdir(Rmkdir),
+    Trenameat(Trenameat),
+    Rrenameat(Rrenameat),
+    Tunlinkat(Tunlinkat),
+    Runlinkat(Runlinkat),
 }
 
 impl Message {
     #[must_use]
     pub fn error(ename: String) -> Message {
-        Message::Rerror(Rerror { ename })
+        Message::Rerror(Rerror { ename, errno: 0 })
+    }
+
+    /// Build an `Rerror` that also carries a unix errno for 9P2000.u clients
+    #[must_use]
+    pub fn error_with_errno(ename: String, errno: u32) -> Message {
+        Message::Rerror(Rerror { ename, errno })
     }
 
     pub fn message_type(&self) -> MessageType {
@@ -280,6 +456,45 @@ impl Message {
             Message::Rstat(_) => MessageType::Rstat,
             Message::Twstat(_) => MessageType::Twstat,
             Message::Rwstat(_) => MessageType::Rwstat,
+            Message::Rlerror(_) => MessageType::Rlerror,
+            Message::Tstatfs(_) => MessageType::Tstatfs,
+            Message::Rstatfs(_) => MessageType::Rstatfs,
+            Message::Tlopen(_) => MessageType::Tlopen,
+            Message::Rlopen(_) => MessageType::Rlopen,
+            Message::Tlcreate(_) => MessageType::Tlcreate,
+            Message::Rlcreate(_) => MessageType::Rlcreate,
+            Message::Tsymlink(_) => MessageType::Tsymlink,
+            Message::Rsymlink(_) => MessageType::Rsymlink,
+            Message::Tmknod(_) => MessageType::Tmknod,
+            Message::Rmknod(_) => MessageType::Rmknod,
+            Message::Trename(_) => MessageType::Trename,
+            Message::Rrename(_) => MessageType::Rrename,
+            Message::Treadlink(_) => MessageType::Treadlink,
+            Message::Rreadlink(_) => MessageType::Rreadlink,
+            Message::Tgetattr(_) => MessageType::Tgetattr,
+            Message::Rgetattr(_) => MessageType::Rgetattr,
+            Message::Tsetattr(_) => MessageType::Tsetattr,
+            Message::Rsetattr(_) => MessageType::Rsetattr,
+            Message::Txattrwalk(_) => MessageType::Txattrwalk,
+            Message::Rxattrwalk(_) => MessageType::Rxattrwalk,
+            Message::Txattrcreate(_) => MessageType::Txattrcreate,
+            Message::Rxattrcreate(_) => MessageType::Rxattrcreate,
+            Message::Treaddir(_) => MessageType::Treaddir,
+            Message::Rreaddir(_) => MessageType::Rreaddir,
+            Message::Tfsync(_) => MessageType::Tfsync,
+            Message::Rfsync(_) => MessageType::Rfsync,
+            Message::Tlock(_) => MessageType::Tlock,
+            Message::Rlock(_) => MessageType::Rlock,
+            Message::Tgetlock(_) => MessageType::Tgetlock,
+            Message::Rgetlock(_) => MessageType::Rgetlock,
+            Message::Tlink(_) => MessageType::Tlink,
+            Message::Rlink(_) => MessageType::Rlink,
+            Message::Tmkdir(_) => MessageType::Tmkdir,
+            Message::Rmkdir(_) => MessageType::Rmkdir,
+            Message::Trenameat(_) => MessageType::Trenameat,
+            Message::Rrenameat(_) => MessageType::Rrenameat,
+            Message::Tunlinkat(_) => MessageType::Tunlinkat,
+            Message::Runlinkat(_) => MessageType::Runlinkat,
         }
     }
 
@@ -305,6 +520,8 @@ pub struct Tauth {
     pub afid: u32,
     pub uname: String,
     pub aname: String,
+    /// 9P2000.u and 9P2000.L only: numeric user id, `P9_NONUNAME` when unknown
+    pub n_uname: u32,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -318,6 +535,8 @@ pub struct Tattach {
     pub afid: u32,
     pub uname: String,
     pub aname: String,
+    /// 9P2000.u and 9P2000.L only: numeric user id, `P9_NONUNAME` when unknown
+    pub n_uname: u32,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -328,6 +547,8 @@ pub struct Rattach {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Rerror {
     pub ename: String,
+    /// 9P2000.u only, zero when unknown
+    pub errno: u32,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -368,6 +589,8 @@ pub struct Tcreate {
     pub name: String,
     pub perm: FlagSet<FileMode>,
     pub mode: FlagSet<OpenMode>,
+    /// 9P2000.u only: symlink target or device description for special files
+    pub extension: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -435,6 +658,274 @@ pub struct Twstat {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Rwstat;
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rlerror {
+    pub ecode: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tstatfs {
+    pub fid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rstatfs {
+    pub r#type: u32,
+    pub bsize: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: u64,
+    pub namelen: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlopen {
+    pub fid: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rlopen {
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlcreate {
+    pub fid: u32,
+    pub name: String,
+    pub flags: u32,
+    pub mode: u32,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rlcreate {
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tsymlink {
+    pub fid: u32,
+    pub name: String,
+    pub symtgt: String,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rsymlink {
+    pub qid: Qid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tmknod {
+    pub dfid: u32,
+    pub name: String,
+    pub mode: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmknod {
+    pub qid: Qid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trename {
+    pub fid: u32,
+    pub dfid: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rrename;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Treadlink {
+    pub fid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rreadlink {
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tgetattr {
+    pub fid: u32,
+    pub request_mask: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rgetattr {
+    pub valid: u64,
+    pub qid: Qid,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+    pub ctime_sec: u64,
+    pub ctime_nsec: u64,
+    pub btime_sec: u64,
+    pub btime_nsec: u64,
+    pub gen: u64,
+    pub data_version: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tsetattr {
+    pub fid: u32,
+    pub valid: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rsetattr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Txattrwalk {
+    pub fid: u32,
+    pub newfid: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rxattrwalk {
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Txattrcreate {
+    pub fid: u32,
+    pub name: String,
+    pub attr_size: u64,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rxattrcreate;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Treaddir {
+    pub fid: u32,
+    pub offset: u64,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rreaddir {
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tfsync {
+    pub fid: u32,
+    pub datasync: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rfsync;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlock {
+    pub fid: u32,
+    pub r#type: u8,
+    pub flags: u32,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    pub client_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rlock {
+    pub status: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tgetlock {
+    pub fid: u32,
+    pub r#type: u8,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    pub client_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rgetlock {
+    pub r#type: u8,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    pub client_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlink {
+    pub dfid: u32,
+    pub fid: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rlink;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tmkdir {
+    pub dfid: u32,
+    pub name: String,
+    pub mode: u32,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmkdir {
+    pub qid: Qid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trenameat {
+    pub olddirfid: u32,
+    pub oldname: String,
+    pub newdirfid: u32,
+    pub newname: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rrenameat;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunlinkat {
+    pub dirfid: u32,
+    pub name: String,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Runlinkat;
+
 impl Encodable for u8 {
     fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
         w.write_u8(*self)?;
@@ -677,26 +1168,49 @@ impl Decodable for Rversion {
     }
 }
 
-impl Encodable for Tauth {
-    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+impl Tauth {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         let mut bytes_written = 0;
         bytes_written += self.afid.encode(w)?;
         bytes_written += self.uname.encode(w)?;
         bytes_written += self.aname.encode(w)?;
+        if dialect != Dialect::Base {
+            bytes_written += self.n_uname.encode(w)?;
+        }
         Ok(bytes_written)
     }
-}
 
-impl Decodable for Tauth {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid `Tauth` for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         Ok(Tauth {
             afid: u32::decode(r)?,
             uname: String::decode(r)?,
             aname: String::decode(r)?,
+            n_uname: match dialect {
+                Dialect::Base => P9_NONUNAME,
+                Dialect::Unix | Dialect::Linux => u32::decode(r)?,
+            },
         })
     }
 }
 
+impl Encodable for Tauth {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for Tauth {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
+    }
+}
+
 impl Encodable for Rauth {
     fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
         self.aqid.encode(w)
@@ -711,28 +1225,51 @@ impl Decodable for Rauth {
     }
 }
 
-impl Encodable for Tattach {
-    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+impl Tattach {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         let mut bytes_written = 0;
         bytes_written += self.fid.encode(w)?;
         bytes_written += self.afid.encode(w)?;
         bytes_written += self.uname.encode(w)?;
         bytes_written += self.aname.encode(w)?;
+        if dialect != Dialect::Base {
+            bytes_written += self.n_uname.encode(w)?;
+        }
         Ok(bytes_written)
     }
-}
 
-impl Decodable for Tattach {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid `Tattach` for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         Ok(Tattach {
             fid: u32::decode(r)?,
             afid: u32::decode(r)?,
             uname: String::decode(r)?,
             aname: String::decode(r)?,
+            n_uname: match dialect {
+                Dialect::Base => P9_NONUNAME,
+                Dialect::Unix | Dialect::Linux => u32::decode(r)?,
+            },
         })
     }
 }
 
+impl Encodable for Tattach {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for Tattach {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
+    }
+}
+
 impl Encodable for Rattach {
     fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
         self.qid.encode(w)
@@ -747,17 +1284,40 @@ impl Decodable for Rattach {
     }
 }
 
+impl Rerror {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
+        let mut bytes_written = self.ename.encode(w)?;
+        if dialect == Dialect::Unix {
+            bytes_written += self.errno.encode(w)?;
+        }
+        Ok(bytes_written)
+    }
+
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid `Rerror` for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
+        let ename = String::decode(r)?;
+        let errno = match dialect {
+            Dialect::Base | Dialect::Linux => 0,
+            Dialect::Unix => u32::decode(r)?,
+        };
+        Ok(Rerror { ename, errno })
+    }
+}
+
 impl Encodable for Rerror {
     fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
-        self.ename.encode(w)
+        self.encode_as(w, Dialect::Base)
     }
 }
 
 impl Decodable for Rerror {
     fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
-        Ok(Rerror {
-            ename: String::decode(r)?,
-        })
+        Self::decode_as(r, Dialect::Base)
     }
 }
 
@@ -857,28 +1417,51 @@ impl Decodable for Ropen {
     }
 }
 
-impl Encodable for Tcreate {
-    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+impl Tcreate {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         let mut bytes_written = 0;
         bytes_written += self.fid.encode(w)?;
         bytes_written += self.name.encode(w)?;
         bytes_written += self.perm.encode(w)?;
         bytes_written += self.mode.encode(w)?;
+        if dialect == Dialect::Unix {
+            bytes_written += self.extension.encode(w)?;
+        }
         Ok(bytes_written)
     }
-}
 
-impl Decodable for Tcreate {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid `Tcreate` for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         Ok(Tcreate {
             fid: u32::decode(r)?,
             name: String::decode(r)?,
             perm: FlagSet::<FileMode>::decode(r)?,
             mode: FlagSet::<OpenMode>::decode(r)?,
+            extension: match dialect {
+                Dialect::Base | Dialect::Linux => String::new(),
+                Dialect::Unix => String::decode(r)?,
+            },
         })
     }
 }
 
+impl Encodable for Tcreate {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for Tcreate {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
+    }
+}
+
 impl Encodable for Rcreate {
     fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
         let mut bytes_written = 0;
@@ -1031,66 +1614,865 @@ impl Decodable for Tstat {
     }
 }
 
-impl Encodable for Rstat {
-    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+impl Rstat {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         // Calculate the stat size and encode it
         let mut temp_buf = Vec::new();
         let mut temp_writer = Cursor::new(&mut temp_buf);
-        let stat_size = self.stat.encode(&mut temp_writer)?;
+        let stat_size = self.stat.encode_as(&mut temp_writer, dialect)?;
 
         let stat_size = u16::try_from(stat_size).map_err(|_| Error::StringTooLong(stat_size))?;
 
         let mut bytes_written = stat_size.encode(w)?;
-        bytes_written += self.stat.encode(w)?;
+        bytes_written += self.stat.encode_as(w, dialect)?;
 
         Ok(bytes_written)
     }
-}
 
-impl Decodable for Rstat {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid `Rstat` for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         let _stat_size = u16::decode(r)?;
-        let stat = Stat::decode(r)?;
+        let stat = Stat::decode_as(r, dialect)?;
         Ok(Rstat { stat })
     }
 }
 
-impl Encodable for Twstat {
+impl Encodable for Rstat {
     fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for Rstat {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
+    }
+}
+
+impl Twstat {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         let mut bytes_written = 0;
         bytes_written += self.fid.encode(w)?;
 
         let mut temp_buf = Vec::new();
         let mut temp_writer = Cursor::new(&mut temp_buf);
-        let stat_size = self.stat.encode(&mut temp_writer)?;
+        let stat_size = self.stat.encode_as(&mut temp_writer, dialect)?;
 
         let stat_size = u16::try_from(stat_size).map_err(|_| Error::StringTooLong(stat_size))?;
 
         bytes_written += stat_size.encode(w)?;
-        bytes_written += self.stat.encode(w)?;
+        bytes_written += self.stat.encode_as(w, dialect)?;
 
         Ok(bytes_written)
     }
-}
 
-impl Decodable for Twstat {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid `Twstat` for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         let fid = u32::decode(r)?;
         let _stat_size = u16::decode(r)?;
-        let stat = Stat::decode(r)?;
+        let stat = Stat::decode_as(r, dialect)?;
         Ok(Twstat { fid, stat })
     }
 }
 
-impl Encodable for Rwstat {
-    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+impl Encodable for Twstat {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for Twstat {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
+    }
+}
+
+impl Encodable for Rwstat {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Rwstat {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Rwstat)
+    }
+}
+
+impl Encodable for Rlerror {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.ecode.encode(w)
+    }
+}
+
+impl Decodable for Rlerror {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rlerror {
+            ecode: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tstatfs {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.fid.encode(w)
+    }
+}
+
+impl Decodable for Tstatfs {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tstatfs {
+            fid: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rstatfs {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.r#type.encode(w)?;
+        bytes_written += self.bsize.encode(w)?;
+        bytes_written += self.blocks.encode(w)?;
+        bytes_written += self.bfree.encode(w)?;
+        bytes_written += self.bavail.encode(w)?;
+        bytes_written += self.files.encode(w)?;
+        bytes_written += self.ffree.encode(w)?;
+        bytes_written += self.fsid.encode(w)?;
+        bytes_written += self.namelen.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Rstatfs {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rstatfs {
+            r#type: u32::decode(r)?,
+            bsize: u32::decode(r)?,
+            blocks: u64::decode(r)?,
+            bfree: u64::decode(r)?,
+            bavail: u64::decode(r)?,
+            files: u64::decode(r)?,
+            ffree: u64::decode(r)?,
+            fsid: u64::decode(r)?,
+            namelen: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tlopen {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.flags.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tlopen {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tlopen {
+            fid: u32::decode(r)?,
+            flags: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rlopen {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.qid.encode(w)?;
+        bytes_written += self.iounit.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Rlopen {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rlopen {
+            qid: Qid::decode(r)?,
+            iounit: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tlcreate {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        bytes_written += self.flags.encode(w)?;
+        bytes_written += self.mode.encode(w)?;
+        bytes_written += self.gid.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tlcreate {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tlcreate {
+            fid: u32::decode(r)?,
+            name: String::decode(r)?,
+            flags: u32::decode(r)?,
+            mode: u32::decode(r)?,
+            gid: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rlcreate {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.qid.encode(w)?;
+        bytes_written += self.iounit.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Rlcreate {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rlcreate {
+            qid: Qid::decode(r)?,
+            iounit: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tsymlink {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        bytes_written += self.symtgt.encode(w)?;
+        bytes_written += self.gid.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tsymlink {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tsymlink {
+            fid: u32::decode(r)?,
+            name: String::decode(r)?,
+            symtgt: String::decode(r)?,
+            gid: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rsymlink {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.qid.encode(w)
+    }
+}
+
+impl Decodable for Rsymlink {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rsymlink {
+            qid: Qid::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tmknod {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.dfid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        bytes_written += self.mode.encode(w)?;
+        bytes_written += self.major.encode(w)?;
+        bytes_written += self.minor.encode(w)?;
+        bytes_written += self.gid.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tmknod {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tmknod {
+            dfid: u32::decode(r)?,
+            name: String::decode(r)?,
+            mode: u32::decode(r)?,
+            major: u32::decode(r)?,
+            minor: u32::decode(r)?,
+            gid: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rmknod {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.qid.encode(w)
+    }
+}
+
+impl Decodable for Rmknod {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rmknod {
+            qid: Qid::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Trename {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.dfid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Trename {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Trename {
+            fid: u32::decode(r)?,
+            dfid: u32::decode(r)?,
+            name: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rrename {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Rrename {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Rrename)
+    }
+}
+
+impl Encodable for Treadlink {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.fid.encode(w)
+    }
+}
+
+impl Decodable for Treadlink {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Treadlink {
+            fid: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rreadlink {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.target.encode(w)
+    }
+}
+
+impl Decodable for Rreadlink {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rreadlink {
+            target: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tgetattr {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.request_mask.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tgetattr {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tgetattr {
+            fid: u32::decode(r)?,
+            request_mask: u64::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rgetattr {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.valid.encode(w)?;
+        bytes_written += self.qid.encode(w)?;
+        bytes_written += self.mode.encode(w)?;
+        bytes_written += self.uid.encode(w)?;
+        bytes_written += self.gid.encode(w)?;
+        bytes_written += self.nlink.encode(w)?;
+        bytes_written += self.rdev.encode(w)?;
+        bytes_written += self.size.encode(w)?;
+        bytes_written += self.blksize.encode(w)?;
+        bytes_written += self.blocks.encode(w)?;
+        bytes_written += self.atime_sec.encode(w)?;
+        bytes_written += self.atime_nsec.encode(w)?;
+        bytes_written += self.mtime_sec.encode(w)?;
+        bytes_written += self.mtime_nsec.encode(w)?;
+        bytes_written += self.ctime_sec.encode(w)?;
+        bytes_written += self.ctime_nsec.encode(w)?;
+        bytes_written += self.btime_sec.encode(w)?;
+        bytes_written += self.btime_nsec.encode(w)?;
+        bytes_written += self.gen.encode(w)?;
+        bytes_written += self.data_version.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Rgetattr {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rgetattr {
+            valid: u64::decode(r)?,
+            qid: Qid::decode(r)?,
+            mode: u32::decode(r)?,
+            uid: u32::decode(r)?,
+            gid: u32::decode(r)?,
+            nlink: u64::decode(r)?,
+            rdev: u64::decode(r)?,
+            size: u64::decode(r)?,
+            blksize: u64::decode(r)?,
+            blocks: u64::decode(r)?,
+            atime_sec: u64::decode(r)?,
+            atime_nsec: u64::decode(r)?,
+            mtime_sec: u64::decode(r)?,
+            mtime_nsec: u64::decode(r)?,
+            ctime_sec: u64::decode(r)?,
+            ctime_nsec: u64::decode(r)?,
+            btime_sec: u64::decode(r)?,
+            btime_nsec: u64::decode(r)?,
+            gen: u64::decode(r)?,
+            data_version: u64::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tsetattr {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.valid.encode(w)?;
+        bytes_written += self.mode.encode(w)?;
+        bytes_written += self.uid.encode(w)?;
+        bytes_written += self.gid.encode(w)?;
+        bytes_written += self.size.encode(w)?;
+        bytes_written += self.atime_sec.encode(w)?;
+        bytes_written += self.atime_nsec.encode(w)?;
+        bytes_written += self.mtime_sec.encode(w)?;
+        bytes_written += self.mtime_nsec.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tsetattr {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tsetattr {
+            fid: u32::decode(r)?,
+            valid: u32::decode(r)?,
+            mode: u32::decode(r)?,
+            uid: u32::decode(r)?,
+            gid: u32::decode(r)?,
+            size: u64::decode(r)?,
+            atime_sec: u64::decode(r)?,
+            atime_nsec: u64::decode(r)?,
+            mtime_sec: u64::decode(r)?,
+            mtime_nsec: u64::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rsetattr {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Rsetattr {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Rsetattr)
+    }
+}
+
+impl Encodable for Txattrwalk {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.newfid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Txattrwalk {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Txattrwalk {
+            fid: u32::decode(r)?,
+            newfid: u32::decode(r)?,
+            name: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rxattrwalk {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.size.encode(w)
+    }
+}
+
+impl Decodable for Rxattrwalk {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rxattrwalk {
+            size: u64::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Txattrcreate {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        bytes_written += self.attr_size.encode(w)?;
+        bytes_written += self.flags.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Txattrcreate {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Txattrcreate {
+            fid: u32::decode(r)?,
+            name: String::decode(r)?,
+            attr_size: u64::decode(r)?,
+            flags: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rxattrcreate {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Rxattrcreate {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Rxattrcreate)
+    }
+}
+
+impl Encodable for Treaddir {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.offset.encode(w)?;
+        bytes_written += self.count.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Treaddir {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Treaddir {
+            fid: u32::decode(r)?,
+            offset: u64::decode(r)?,
+            count: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rreaddir {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.data.encode(w)
+    }
+}
+
+impl Decodable for Rreaddir {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rreaddir {
+            data: Bytes::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tfsync {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.datasync.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tfsync {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tfsync {
+            fid: u32::decode(r)?,
+            datasync: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rfsync {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
         Ok(0)
     }
 }
 
-impl Decodable for Rwstat {
+impl Decodable for Rfsync {
     fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
-        Ok(Rwstat)
+        Ok(Rfsync)
+    }
+}
+
+impl Encodable for Tlock {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.r#type.encode(w)?;
+        bytes_written += self.flags.encode(w)?;
+        bytes_written += self.start.encode(w)?;
+        bytes_written += self.length.encode(w)?;
+        bytes_written += self.proc_id.encode(w)?;
+        bytes_written += self.client_id.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tlock {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tlock {
+            fid: u32::decode(r)?,
+            r#type: u8::decode(r)?,
+            flags: u32::decode(r)?,
+            start: u64::decode(r)?,
+            length: u64::decode(r)?,
+            proc_id: u32::decode(r)?,
+            client_id: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rlock {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.status.encode(w)
+    }
+}
+
+impl Decodable for Rlock {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rlock {
+            status: u8::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tgetlock {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.r#type.encode(w)?;
+        bytes_written += self.start.encode(w)?;
+        bytes_written += self.length.encode(w)?;
+        bytes_written += self.proc_id.encode(w)?;
+        bytes_written += self.client_id.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tgetlock {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tgetlock {
+            fid: u32::decode(r)?,
+            r#type: u8::decode(r)?,
+            start: u64::decode(r)?,
+            length: u64::decode(r)?,
+            proc_id: u32::decode(r)?,
+            client_id: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rgetlock {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.r#type.encode(w)?;
+        bytes_written += self.start.encode(w)?;
+        bytes_written += self.length.encode(w)?;
+        bytes_written += self.proc_id.encode(w)?;
+        bytes_written += self.client_id.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Rgetlock {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rgetlock {
+            r#type: u8::decode(r)?,
+            start: u64::decode(r)?,
+            length: u64::decode(r)?,
+            proc_id: u32::decode(r)?,
+            client_id: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Tlink {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.dfid.encode(w)?;
+        bytes_written += self.fid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tlink {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tlink {
+            dfid: u32::decode(r)?,
+            fid: u32::decode(r)?,
+            name: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rlink {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Rlink {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Rlink)
+    }
+}
+
+impl Encodable for Tmkdir {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.dfid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        bytes_written += self.mode.encode(w)?;
+        bytes_written += self.gid.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tmkdir {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tmkdir {
+            dfid: u32::decode(r)?,
+            name: String::decode(r)?,
+            mode: u32::decode(r)?,
+            gid: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rmkdir {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.qid.encode(w)
+    }
+}
+
+impl Decodable for Rmkdir {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Rmkdir {
+            qid: Qid::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Trenameat {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.olddirfid.encode(w)?;
+        bytes_written += self.oldname.encode(w)?;
+        bytes_written += self.newdirfid.encode(w)?;
+        bytes_written += self.newname.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Trenameat {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Trenameat {
+            olddirfid: u32::decode(r)?,
+            oldname: String::decode(r)?,
+            newdirfid: u32::decode(r)?,
+            newname: String::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Rrenameat {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Rrenameat {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Rrenameat)
+    }
+}
+
+impl Encodable for Tunlinkat {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        let mut bytes_written = 0;
+        bytes_written += self.dirfid.encode(w)?;
+        bytes_written += self.name.encode(w)?;
+        bytes_written += self.flags.encode(w)?;
+        Ok(bytes_written)
+    }
+}
+
+impl Decodable for Tunlinkat {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Ok(Tunlinkat {
+            dirfid: u32::decode(r)?,
+            name: String::decode(r)?,
+            flags: u32::decode(r)?,
+        })
+    }
+}
+
+impl Encodable for Runlinkat {
+    fn encode<W: WriteBytesExt>(&self, _w: &mut W) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Decodable for Runlinkat {
+    fn decode<R: ReadBytesExt>(_r: &mut R) -> Result<Self> {
+        Ok(Runlinkat)
+    }
+}
+
+impl Message {
+    /// Encode the message body using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
+        match self {
+            Message::Tauth(msg) => msg.encode_as(w, dialect),
+            Message::Tattach(msg) => msg.encode_as(w, dialect),
+            Message::Rerror(msg) => msg.encode_as(w, dialect),
+            Message::Tcreate(msg) => msg.encode_as(w, dialect),
+            Message::Rstat(msg) => msg.encode_as(w, dialect),
+            Message::Twstat(msg) => msg.encode_as(w, dialect),
+            _ => self.encode(w),
+        }
     }
 }
 
@@ -1124,6 +2506,45 @@ impl Encodable for Message {
             Message::Rstat(msg) => msg.encode(w),
             Message::Twstat(msg) => msg.encode(w),
             Message::Rwstat(msg) => msg.encode(w),
+            Message::Rlerror(msg) => msg.encode(w),
+            Message::Tstatfs(msg) => msg.encode(w),
+            Message::Rstatfs(msg) => msg.encode(w),
+            Message::Tlopen(msg) => msg.encode(w),
+            Message::Rlopen(msg) => msg.encode(w),
+            Message::Tlcreate(msg) => msg.encode(w),
+            Message::Rlcreate(msg) => msg.encode(w),
+            Message::Tsymlink(msg) => msg.encode(w),
+            Message::Rsymlink(msg) => msg.encode(w),
+            Message::Tmknod(msg) => msg.encode(w),
+            Message::Rmknod(msg) => msg.encode(w),
+            Message::Trename(msg) => msg.encode(w),
+            Message::Rrename(msg) => msg.encode(w),
+            Message::Treadlink(msg) => msg.encode(w),
+            Message::Rreadlink(msg) => msg.encode(w),
+            Message::Tgetattr(msg) => msg.encode(w),
+            Message::Rgetattr(msg) => msg.encode(w),
+            Message::Tsetattr(msg) => msg.encode(w),
+            Message::Rsetattr(msg) => msg.encode(w),
+            Message::Txattrwalk(msg) => msg.encode(w),
+            Message::Rxattrwalk(msg) => msg.encode(w),
+            Message::Txattrcreate(msg) => msg.encode(w),
+            Message::Rxattrcreate(msg) => msg.encode(w),
+            Message::Treaddir(msg) => msg.encode(w),
+            Message::Rreaddir(msg) => msg.encode(w),
+            Message::Tfsync(msg) => msg.encode(w),
+            Message::Rfsync(msg) => msg.encode(w),
+            Message::Tlock(msg) => msg.encode(w),
+            Message::Rlock(msg) => msg.encode(w),
+            Message::Tgetlock(msg) => msg.encode(w),
+            Message::Rgetlock(msg) => msg.encode(w),
+            Message::Tlink(msg) => msg.encode(w),
+            Message::Rlink(msg) => msg.encode(w),
+            Message::Tmkdir(msg) => msg.encode(w),
+            Message::Rmkdir(msg) => msg.encode(w),
+            Message::Trenameat(msg) => msg.encode(w),
+            Message::Rrenameat(msg) => msg.encode(w),
+            Message::Tunlinkat(msg) => msg.encode(w),
+            Message::Runlinkat(msg) => msg.encode(w),
         }
     }
 }
@@ -1136,38 +2557,42 @@ impl Decodable for Message {
     }
 }
 
-impl Encodable for TaggedMessage {
-    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+impl TaggedMessage {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         let mut bytes_written = 0;
 
         bytes_written += self.message.message_type().to_u8().encode(w)?;
         bytes_written += self.tag.encode(w)?;
-        bytes_written += self.message.encode(w)?;
+        bytes_written += self.message.encode_as(w, dialect)?;
 
         Ok(bytes_written)
     }
-}
 
-impl Decodable for TaggedMessage {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid message for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         let message_type = MessageType::from_u8(u8::decode(r)?)?;
         let tag = u16::decode(r)?;
 
         let message = match message_type {
             MessageType::Tversion => Message::Tversion(Tversion::decode(r)?),
             MessageType::Rversion => Message::Rversion(Rversion::decode(r)?),
-            MessageType::Tauth => Message::Tauth(Tauth::decode(r)?),
+            MessageType::Tauth => Message::Tauth(Tauth::decode_as(r, dialect)?),
             MessageType::Rauth => Message::Rauth(Rauth::decode(r)?),
-            MessageType::Tattach => Message::Tattach(Tattach::decode(r)?),
+            MessageType::Tattach => Message::Tattach(Tattach::decode_as(r, dialect)?),
             MessageType::Rattach => Message::Rattach(Rattach::decode(r)?),
-            MessageType::Rerror => Message::Rerror(Rerror::decode(r)?),
+            MessageType::Rerror => Message::Rerror(Rerror::decode_as(r, dialect)?),
             MessageType::Tflush => Message::Tflush(Tflush::decode(r)?),
             MessageType::Rflush => Message::Rflush(Rflush::decode(r)?),
             MessageType::Twalk => Message::Twalk(Twalk::decode(r)?),
             MessageType::Rwalk => Message::Rwalk(Rwalk::decode(r)?),
             MessageType::Topen => Message::Topen(Topen::decode(r)?),
             MessageType::Ropen => Message::Ropen(Ropen::decode(r)?),
-            MessageType::Tcreate => Message::Tcreate(Tcreate::decode(r)?),
+            MessageType::Tcreate => Message::Tcreate(Tcreate::decode_as(r, dialect)?),
             MessageType::Rcreate => Message::Rcreate(Rcreate::decode(r)?),
             MessageType::Tread => Message::Tread(Tread::decode(r)?),
             MessageType::Rread => Message::Rread(Rread::decode(r)?),
@@ -1178,29 +2603,129 @@ impl Decodable for TaggedMessage {
             MessageType::Tremove => Message::Tremove(Tremove::decode(r)?),
             MessageType::Rremove => Message::Rremove(Rremove::decode(r)?),
             MessageType::Tstat => Message::Tstat(Tstat::decode(r)?),
-            MessageType::Rstat => Message::Rstat(Rstat::decode(r)?),
-            MessageType::Twstat => Message::Twstat(Twstat::decode(r)?),
+            MessageType::Rstat => Message::Rstat(Rstat::decode_as(r, dialect)?),
+            MessageType::Twstat => Message::Twstat(Twstat::decode_as(r, dialect)?),
             MessageType::Rwstat => Message::Rwstat(Rwstat::decode(r)?),
+            MessageType::Rlerror => Message::Rlerror(Rlerror::decode(r)?),
+            MessageType::Tstatfs => Message::Tstatfs(Tstatfs::decode(r)?),
+            MessageType::Rstatfs => Message::Rstatfs(Rstatfs::decode(r)?),
+            MessageType::Tlopen => Message::Tlopen(Tlopen::decode(r)?),
+            MessageType::Rlopen => Message::Rlopen(Rlopen::decode(r)?),
+            MessageType::Tlcreate => Message::Tlcreate(Tlcreate::decode(r)?),
+            MessageType::Rlcreate => Message::Rlcreate(Rlcreate::decode(r)?),
+            MessageType::Tsymlink => Message::Tsymlink(Tsymlink::decode(r)?),
+            MessageType::Rsymlink => Message::Rsymlink(Rsymlink::decode(r)?),
+            MessageType::Tmknod => Message::Tmknod(Tmknod::decode(r)?),
+            MessageType::Rmknod => Message::Rmknod(Rmknod::decode(r)?),
+            MessageType::Trename => Message::Trename(Trename::decode(r)?),
+            MessageType::Rrename => Message::Rrename(Rrename::decode(r)?),
+            MessageType::Treadlink => Message::Treadlink(Treadlink::decode(r)?),
+            MessageType::Rreadlink => Message::Rreadlink(Rreadlink::decode(r)?),
+            MessageType::Tgetattr => Message::Tgetattr(Tgetattr::decode(r)?),
+            MessageType::Rgetattr => Message::Rgetattr(Rgetattr::decode(r)?),
+            MessageType::Tsetattr => Message::Tsetattr(Tsetattr::decode(r)?),
+            MessageType::Rsetattr => Message::Rsetattr(Rsetattr::decode(r)?),
+            MessageType::Txattrwalk => Message::Txattrwalk(Txattrwalk::decode(r)?),
+            MessageType::Rxattrwalk => Message::Rxattrwalk(Rxattrwalk::decode(r)?),
+            MessageType::Txattrcreate => Message::Txattrcreate(Txattrcreate::decode(r)?),
+            MessageType::Rxattrcreate => Message::Rxattrcreate(Rxattrcreate::decode(r)?),
+            MessageType::Treaddir => Message::Treaddir(Treaddir::decode(r)?),
+            MessageType::Rreaddir => Message::Rreaddir(Rreaddir::decode(r)?),
+            MessageType::Tfsync => Message::Tfsync(Tfsync::decode(r)?),
+            MessageType::Rfsync => Message::Rfsync(Rfsync::decode(r)?),
+            MessageType::Tlock => Message::Tlock(Tlock::decode(r)?),
+            MessageType::Rlock => Message::Rlock(Rlock::decode(r)?),
+            MessageType::Tgetlock => Message::Tgetlock(Tgetlock::decode(r)?),
+            MessageType::Rgetlock => Message::Rgetlock(Rgetlock::decode(r)?),
+            MessageType::Tlink => Message::Tlink(Tlink::decode(r)?),
+            MessageType::Rlink => Message::Rlink(Rlink::decode(r)?),
+            MessageType::Tmkdir => Message::Tmkdir(Tmkdir::decode(r)?),
+            MessageType::Rmkdir => Message::Rmkdir(Rmkdir::decode(r)?),
+            MessageType::Trenameat => Message::Trenameat(Trenameat::decode(r)?),
+            MessageType::Rrenameat => Message::Rrenameat(Rrenameat::decode(r)?),
+            MessageType::Tunlinkat => Message::Tunlinkat(Tunlinkat::decode(r)?),
+            MessageType::Runlinkat => Message::Runlinkat(Runlinkat::decode(r)?),
         };
 
         Ok(TaggedMessage { tag, message })
     }
 }
 
+impl Encodable for TaggedMessage {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for TaggedMessage {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
+    }
+}
+
 pub struct MessageCodec {
     length_codec: LengthDelimitedCodec,
+    dialect: Dialect,
+    msize: u32,
 }
 
 impl MessageCodec {
     #[must_use]
     pub fn new() -> Self {
+        Self::with_msize(P9_DEFAULT_MSIZE)
+    }
+
+    /// Create a codec that refuses frames larger than `msize` bytes, size field included
+    #[must_use]
+    pub fn with_msize(msize: u32) -> Self {
         Self {
             length_codec: LengthDelimitedCodec::builder()
                 .little_endian()
                 .length_field_length(4)
                 .length_adjustment(-4) // don't include length field in payload
+                .max_frame_length(Self::max_frame_length(msize))
                 .new_codec(),
+            dialect: Dialect::Base,
+            msize,
+        }
+    }
+
+    #[must_use]
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Switch the encoding used for subsequent messages, typically after Rversion
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    #[must_use]
+    pub fn msize(&self) -> u32 {
+        self.msize
+    }
+
+    /// Change the largest frame accepted in either direction, typically after Rversion
+    pub fn set_msize(&mut self, msize: u32) {
+        self.msize = msize;
+        self.length_codec
+            .set_max_frame_length(Self::max_frame_length(msize));
+    }
+
+    /// The limit for the length codec, which checks the size field before it is adjusted, so
+    /// the size field counts itself just like msize does
+    fn max_frame_length(msize: u32) -> usize {
+        msize as usize
+    }
+
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size > self.msize as usize {
+            return Err(Error::Protocol(format!(
+                "message of {size} bytes exceeds msize {}",
+                self.msize
+            )));
         }
+        Ok(())
     }
 }
 
@@ -1215,9 +2740,18 @@ impl Decoder for MessageCodec {
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
-        if let Some(frame) = self.length_codec.decode(src).map_err(Error::Io)? {
+        // the length codec refuses oversized frames before buffering them
+        let frame = self.length_codec.decode(src).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::InvalidData {
+                Error::Protocol(format!("message exceeds msize {}", self.msize))
+            } else {
+                Error::Io(e)
+            }
+        })?;
+
+        if let Some(frame) = frame {
             let mut cursor = Cursor::new(frame.as_ref());
-            let message = TaggedMessage::decode(&mut cursor)?;
+            let message = TaggedMessage::decode_as(&mut cursor, self.dialect)?;
             Ok(Some(message))
         } else {
             Ok(None)
@@ -1230,7 +2764,8 @@ impl Encoder<TaggedMessage> for MessageCodec {
 
     fn encode(&mut self, item: TaggedMessage, dst: &mut BytesMut) -> Result<()> {
         let mut payload = BytesMut::new();
-        item.encode(&mut payload.write_adapter())?;
+        item.encode_as(&mut payload.write_adapter(), self.dialect)?;
+        self.check_size(payload.len() + 4)?;
         self.length_codec
             .encode(payload.freeze(), dst)
             .map_err(Error::Io)?;
@@ -1252,6 +2787,12 @@ pub struct Stat {
     pub uid: String,
     pub gid: String,
     pub muid: String,
+    /// 9P2000.u only: symlink target or device description for special files
+    pub extension: String,
+    /// 9P2000.u only: numeric ids, `P9_NONUNAME` when unknown
+    pub n_uid: u32,
+    pub n_gid: u32,
+    pub n_muid: u32,
 }
 
 impl Stat {
@@ -1275,6 +2816,10 @@ impl Stat {
             uid: String::new(),
             gid: String::new(),
             muid: String::new(),
+            extension: String::new(),
+            n_uid: u32::MAX,
+            n_gid: u32::MAX,
+            n_muid: u32::MAX,
         }
     }
 
@@ -1300,8 +2845,12 @@ impl Stat {
     }
 }
 
-impl Encodable for Stat {
-    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+impl Stat {
+    /// Encode using the layout of the given dialect
+    /// # Errors
+    /// - failure writing to `w`
+    /// - the encoded stat is larger than a 16 bit size allows
+    pub fn encode_as<W: WriteBytesExt>(&self, w: &mut W, dialect: Dialect) -> Result<usize> {
         // using a temporary buffer to calculate the size
         let mut temp_buf = Vec::new();
         let mut temp_writer = Cursor::new(&mut temp_buf);
@@ -1318,6 +2867,12 @@ impl Encodable for Stat {
         self.uid.encode(&mut temp_writer)?;
         self.gid.encode(&mut temp_writer)?;
         self.muid.encode(&mut temp_writer)?;
+        if dialect == Dialect::Unix {
+            self.extension.encode(&mut temp_writer)?;
+            self.n_uid.encode(&mut temp_writer)?;
+            self.n_gid.encode(&mut temp_writer)?;
+            self.n_muid.encode(&mut temp_writer)?;
+        }
 
         let total_size =
             u16::try_from(temp_buf.len()).map_err(|_| Error::StringTooLong(temp_buf.len()))?;
@@ -1328,10 +2883,11 @@ impl Encodable for Stat {
 
         Ok(bytes_written)
     }
-}
 
-impl Decodable for Stat {
-    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+    /// Decode using the layout of the given dialect
+    /// # Errors
+    /// - the data is not a valid stat for `dialect`
+    pub fn decode_as<R: ReadBytesExt>(r: &mut R, dialect: Dialect) -> Result<Self> {
         let stat_size = u16::decode(r)? as usize;
 
         let mut stat_data = vec![0u8; stat_size];
@@ -1352,7 +2908,7 @@ impl Decodable for Stat {
         let gid = String::decode(&mut stat_cursor)?;
         let muid = String::decode(&mut stat_cursor)?;
 
-        Ok(Stat {
+        let mut stat = Stat {
             r#type,
             dev,
             qid,
@@ -1364,9 +2920,34 @@ impl Decodable for Stat {
             uid,
             gid,
             muid,
-        })
+            extension: String::new(),
+            n_uid: P9_NONUNAME,
+            n_gid: P9_NONUNAME,
+            n_muid: P9_NONUNAME,
+        };
+
+        if dialect == Dialect::Unix {
+            stat.extension = String::decode(&mut stat_cursor)?;
+            stat.n_uid = u32::decode(&mut stat_cursor)?;
+            stat.n_gid = u32::decode(&mut stat_cursor)?;
+            stat.n_muid = u32::decode(&mut stat_cursor)?;
+        }
+
+        Ok(stat)
+    }
+}
+
+impl Encodable for Stat {
+    fn encode<W: WriteBytesExt>(&self, w: &mut W) -> Result<usize> {
+        self.encode_as(w, Dialect::Base)
+    }
+}
+
+impl Decodable for Stat {
+    fn decode<R: ReadBytesExt>(r: &mut R) -> Result<Self> {
+        Self::decode_as(r, Dialect::Base)
     }
 }
 
-// #[cfg(test)]
-// mod tests;
+#[cfg(test)]
+mod tests;
@@ -1,5 +1,3 @@
-use crate::consts::P9_NOFID;
-
 use super::*;
 
 const DATA_LS_CLIENT: &[u8] = include_bytes!("./testdata/ls-client.9p");
@@ -291,11 +289,14 @@ mod comprehensive_tests {
         match &server_messages[1].message {
             Message::Rattach(rattach) => {
                 println!(
-                    "Actual qid: typ={}, vers={}, path={}",
+                    "Actual qid: typ={:?}, vers={}, path={}",
                     rattach.qid.qtype, rattach.qid.version, rattach.qid.path
                 );
                 // Directory QID should have typ with QTDIR bit set
-                assert_ne!(rattach.qid.qtype & 0x80, 0, "Should be a directory");
+                assert!(
+                    rattach.qid.qtype.contains(QidType::Dir),
+                    "Should be a directory"
+                );
             }
             _ => panic!("Expected Rattach as second response"),
         }
@@ -306,7 +307,7 @@ mod comprehensive_tests {
     #[test]
     fn test_file_creation_sequence() -> Result<()> {
         let client_messages = extract_all_messages(DATA_COMPREHENSIVE_CLIENT)?;
-        let server_messages = extract_all_messages(DATA_COMPREHENSIVE_SERVER)?;
+        let _server_messages = extract_all_messages(DATA_COMPREHENSIVE_SERVER)?;
 
         // Find Tcreate messages for file creation
         let create_messages: Vec<_> = client_messages
@@ -323,28 +324,28 @@ mod comprehensive_tests {
         );
 
         // Debug: Print actual values from first create
-        let (tag, tcreate) = &create_messages[0];
+        let (_tag, tcreate) = &create_messages[0];
         println!(
-            "Create message: name={}, perm={} (0o{:o}), mode={}",
-            tcreate.name, tcreate.perm, tcreate.perm, tcreate.mode
+            "Create message: name={}, perm={:?} (0o{:o}), mode={:?}",
+            tcreate.name,
+            tcreate.perm,
+            tcreate.perm.bits(),
+            tcreate.mode
         );
 
         // Verify structure - adjust expectations based on actual values
         assert!(tcreate.name.contains("txt") || tcreate.name.contains("file"));
 
         // Check if perm includes file type bits (0o100000)
-        let has_file_type = tcreate.perm & 0o170_000 != 0;
+        let perm = tcreate.perm.bits();
+        let has_file_type = perm & 0o170_000 != 0;
         if has_file_type {
             // Full mode with file type
-            assert_eq!(tcreate.perm & 0o777, 0o644, "Permission bits should be 644");
-            assert_eq!(
-                tcreate.perm & 0o170_000,
-                0o100_000,
-                "Should be regular file"
-            );
+            assert_eq!(perm & 0o777, 0o644, "Permission bits should be 644");
+            assert_eq!(perm & 0o170_000, 0o100_000, "Should be regular file");
         } else {
             // Just permission bits
-            assert_eq!(tcreate.perm, 0o644);
+            assert_eq!(perm, 0o644);
         }
 
         Ok(())
@@ -363,13 +364,16 @@ mod comprehensive_tests {
         if let Some(msg) = newfile_create {
             if let Message::Tcreate(tcreate) = &msg.message {
                 println!(
-                    "Touch create: name={}, perm={} (0o{:o}), mode={}",
-                    tcreate.name, tcreate.perm, tcreate.perm, tcreate.mode
+                    "Touch create: name={}, perm={:?} (0o{:o}), mode={:?}",
+                    tcreate.name,
+                    tcreate.perm,
+                    tcreate.perm.bits(),
+                    tcreate.mode
                 );
 
                 assert_eq!(tcreate.name, "newfile.txt");
                 // Check permissions (with or without file type bits)
-                let perm_bits = tcreate.perm & 0o777;
+                let perm_bits = tcreate.perm.bits() & 0o777;
                 assert_eq!(perm_bits, 0o644);
             }
         } else {
@@ -400,7 +404,19 @@ mod comprehensive_tests {
             })
             .collect();
 
-        if !write_messages.is_empty() {
+        if write_messages.is_empty() {
+            println!("No write operations found - debugging available operations:");
+            for msg in &client_messages {
+                match &msg.message {
+                    Message::Tcreate(t) => println!("  Tcreate: {}", t.name),
+                    Message::Topen(t) => println!("  Topen: fid={}, mode={:?}", t.fid, t.mode),
+                    Message::Twrite(t) => {
+                        println!("  Twrite: offset={}, len={}", t.offset, t.data.len());
+                    }
+                    _ => {}
+                }
+            }
+        } else {
             let (tag, twrite) = &write_messages[0];
             println!(
                 "Write operation: offset={}, count={}, data={:?}",
@@ -423,18 +439,6 @@ mod comprehensive_tests {
                     assert_eq!(rwrite.count as usize, twrite.data.len());
                 }
             }
-        } else {
-            println!("No write operations found - debugging available operations:");
-            for msg in &client_messages {
-                match &msg.message {
-                    Message::Tcreate(t) => println!("  Tcreate: {}", t.name),
-                    Message::Topen(t) => println!("  Topen: fid={}, mode={}", t.fid, t.mode),
-                    Message::Twrite(t) => {
-                        println!("  Twrite: offset={}, len={}", t.offset, t.data.len())
-                    }
-                    _ => {}
-                }
-            }
         }
 
         Ok(())
@@ -499,95 +503,266 @@ mod comprehensive_tests {
 
 #[cfg(test)]
 mod encoding_verification_tests {
-    use crate::consts::P9_NOFID;
+    use crate::consts::{P9_NOFID, P9_NONUNAME};
 
     use super::*;
     use bytes::BytesMut;
 
-    fn build_expected_client_messages() -> Vec<TaggedMessage> {
-        // Based on u9fs.log, build the exact sequence of T-messages
+    /// The first messages of the comprehensive client capture
+    fn expected_client_messages() -> Vec<TaggedMessage> {
+        let write_trunc = OpenMode::Write | OpenMode::Trunc;
+        let file_perm = FlagSet::new_truncated(0o644);
         vec![
-            // >>> Tversion tag 65535 msize 8192 version 9P2000
             TaggedMessage::new(
                 65535,
                 Message::Tversion(Tversion {
-                    msize: 8192,
+                    msize: 131_096,
                     version: "9P2000".to_string(),
                 }),
             ),
-            // >>> Tattach tag 1 fid 1 afid -1 uname user aname
             TaggedMessage::new(
-                1,
+                0,
                 Message::Tattach(Tattach {
-                    fid: 1,
+                    fid: 0,
                     afid: P9_NOFID,
-                    uname: "user".to_string(), // Adjust to your actual username
-                    aname: "".to_string(),
+                    uname: "justin".to_string(),
+                    aname: String::new(),
+                    n_uname: P9_NONUNAME,
                 }),
             ),
-            // >>> Twalk tag 2 fid 1 newfid 1 nwname 0
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 0 })),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 0 })),
+            TaggedMessage::new(0, twalk(0, 1, &[])),
+            TaggedMessage::new(0, topen(1, OpenMode::Read.into())),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 0 })),
             TaggedMessage::new(
-                2,
-                Message::Twalk(Twalk {
+                0,
+                Message::Tread(Tread {
                     fid: 1,
-                    newfid: 1,
-                    wnames: vec![],
+                    offset: 0,
+                    count: 8192,
                 }),
             ),
-            // >>> Tcreate tag 3 fid 1 name test.txt perm 100644 mode 1
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
+            TaggedMessage::new(0, twalk(0, 1, &["test.txt"])),
+            TaggedMessage::new(0, twalk(0, 1, &[])),
+            TaggedMessage::new(0, tcreate(1, "test.txt", file_perm, write_trunc)),
+            TaggedMessage::new(0, twalk(0, 2, &["test.txt"])),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
+            TaggedMessage::new(0, twrite(1, 0, "Hello, 9P world!\n")),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
+            TaggedMessage::new(0, twalk(0, 1, &["test.txt"])),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 1 })),
+            TaggedMessage::new(0, twalk(1, 2, &[])),
+            TaggedMessage::new(0, topen(2, OpenMode::Write.into())),
+            TaggedMessage::new(0, twrite(2, 17, "Second line\n")),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
+            TaggedMessage::new(0, twalk(0, 1, &["subdir"])),
+            TaggedMessage::new(0, twalk(0, 1, &[])),
             TaggedMessage::new(
-                3,
-                Message::Tcreate(Tcreate {
-                    fid: 1,
-                    name: "test.txt".to_string(),
-                    perm: 0o100_644, // note: includes file type bits
-                    mode: 1,         // write-only
-                }),
+                0,
+                tcreate(
+                    1,
+                    "subdir",
+                    FileMode::Dir | FlagSet::new_truncated(0o755),
+                    OpenMode::Read.into(),
+                ),
             ),
-            // >>> Twrite tag 4 fid 1 offset 0 count 17 data (17 bytes)
+            TaggedMessage::new(0, twalk(0, 2, &["subdir"])),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
+            TaggedMessage::new(0, twalk(0, 1, &["subdir"])),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 1 })),
+            TaggedMessage::new(0, twalk(1, 2, &["nested.txt"])),
+            TaggedMessage::new(0, twalk(1, 2, &[])),
+            TaggedMessage::new(0, tcreate(2, "nested.txt", file_perm, write_trunc)),
+            TaggedMessage::new(0, twalk(1, 3, &["nested.txt"])),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 3 })),
+            TaggedMessage::new(0, twrite(2, 0, "Nested content\n")),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 3 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
+            TaggedMessage::new(0, twalk(0, 1, &["newfile.txt"])),
+            TaggedMessage::new(0, twalk(0, 1, &[])),
             TaggedMessage::new(
-                4,
-                Message::Twrite(Twrite {
-                    fid: 1,
-                    offset: 0,
-                    data: b"Hello, 9P world!\n".to_vec().into(),
+                0,
+                tcreate(1, "newfile.txt", file_perm, OpenMode::Write.into()),
+            ),
+            TaggedMessage::new(0, twalk(0, 2, &["newfile.txt"])),
+            TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
+            TaggedMessage::new(
+                0,
+                Message::Twstat(Twstat {
+                    fid: 2,
+                    stat: touch_stat(),
                 }),
             ),
-            // >>> Tclunk tag 5 fid 1
-            TaggedMessage::new(5, Message::Tclunk(Tclunk { fid: 1 })),
-            // Add more messages based on the complete log...
-            // This is just the beginning of the sequence
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
+            TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
         ]
     }
 
-    fn build_expected_server_messages() -> Vec<TaggedMessage> {
-        // Based on u9fs.log, build the exact sequence of R-messages
+    /// The first messages of the comprehensive server capture
+    fn expected_server_messages() -> Vec<TaggedMessage> {
+        let root_qid = Qid {
+            qtype: QidType::Dir.into(),
+            version: 1_748_236_823,
+            path: 0x001d_e954,
+        };
         vec![
-            // <<< Rversion tag 65535 msize 8168 version 9P2000
             TaggedMessage::new(
                 65535,
                 Message::Rversion(Rversion {
-                    msize: 8168,
+                    msize: 8216,
                     version: "9P2000".to_string(),
                 }),
             ),
-            // <<< Rattach tag 1 qid (0000000000000000 0 d41d8cd98f00b204e9800998ecf8427e)
             TaggedMessage::new(
-                1,
+                0,
                 Message::Rattach(Rattach {
+                    qid: root_qid.clone(),
+                }),
+            ),
+            TaggedMessage::new(0, Message::Rstat(Rstat { stat: root_stat() })),
+            TaggedMessage::new(0, Message::Rstat(Rstat { stat: root_stat() })),
+            TaggedMessage::new(0, Message::Rwalk(Rwalk { wqids: vec![] })),
+            TaggedMessage::new(
+                0,
+                Message::Ropen(Ropen {
+                    qid: root_qid,
+                    iounit: 0,
+                }),
+            ),
+            TaggedMessage::new(0, Message::Rstat(Rstat { stat: root_stat() })),
+            TaggedMessage::new(0, Message::Rread(Rread { data: Bytes::new() })),
+            TaggedMessage::new(0, Message::Rclunk(Rclunk)),
+            TaggedMessage::new(
+                0,
+                Message::Rerror(Rerror {
+                    ename: "No such file or directory".to_string(),
+                    errno: 0,
+                }),
+            ),
+            TaggedMessage::new(0, Message::Rwalk(Rwalk { wqids: vec![] })),
+            TaggedMessage::new(
+                0,
+                Message::Rcreate(Rcreate {
                     qid: Qid {
-                        qtype: 0x80, // Directory
-                        version: 0,
-                        path: 0, // Root directory
+                        qtype: FlagSet::default(),
+                        version: 1_748_238_273,
+                        path: 0x003a_89fe,
                     },
+                    iounit: 0,
                 }),
             ),
-            // Add more R-messages...
         ]
     }
 
+    fn twalk(fid: u32, newfid: u32, wnames: &[&str]) -> Message {
+        Message::Twalk(Twalk {
+            fid,
+            newfid,
+            wnames: wnames.iter().map(ToString::to_string).collect(),
+        })
+    }
+
+    fn topen(fid: u32, mode: FlagSet<OpenMode>) -> Message {
+        Message::Topen(Topen { fid, mode })
+    }
+
+    fn tcreate(fid: u32, name: &str, perm: FlagSet<FileMode>, mode: FlagSet<OpenMode>) -> Message {
+        Message::Tcreate(Tcreate {
+            fid,
+            name: name.to_string(),
+            perm,
+            mode,
+            extension: String::new(),
+        })
+    }
+
+    fn twrite(fid: u32, offset: u64, data: &'static str) -> Message {
+        Message::Twrite(Twrite {
+            fid,
+            offset,
+            data: Bytes::from(data),
+        })
+    }
+
+    /// The wstat `touch` sends, changing nothing but the times
+    fn touch_stat() -> Stat {
+        let mut stat = Stat::new_dont_touch();
+        stat.atime = 1_748_238_273;
+        stat.mtime = 1_748_238_273;
+        stat
+    }
+
+    /// The stat of the exported directory
+    fn root_stat() -> Stat {
+        Stat {
+            r#type: 0,
+            dev: 0,
+            qid: Qid {
+                qtype: QidType::Dir.into(),
+                version: 1_748_236_823,
+                path: 0x001d_e954,
+            },
+            mode: FileMode::Dir | FlagSet::new_truncated(0o755),
+            atime: 1_748_236_823,
+            mtime: 1_748_236_823,
+            length: 0,
+            name: String::new(),
+            uid: "justin".to_string(),
+            gid: "users".to_string(),
+            muid: String::new(),
+            extension: String::new(),
+            n_uid: P9_NONUNAME,
+            n_gid: P9_NONUNAME,
+            n_muid: P9_NONUNAME,
+        }
+    }
+
+    /// The frames of a capture, each one message with its size
+    fn frames(mut data: &[u8]) -> Vec<&[u8]> {
+        let mut frames = Vec::new();
+        while data.len() >= 4 {
+            let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let (frame, rest) = data.split_at(size);
+            frames.push(frame);
+            data = rest;
+        }
+        frames
+    }
+
+    /// Encode each of `expected` and compare it with the frame at the same place in `capture`
+    ///
+    /// The lists only cover the start of the captures, the messages after them are not checked.
+    fn assert_encoded_as_captured(expected: &[TaggedMessage], capture: &[u8]) -> Result<()> {
+        let frames = frames(capture);
+        assert!(expected.len() <= frames.len());
+
+        let mut codec = MessageCodec::new();
+        for (i, (message, frame)) in expected.iter().zip(frames).enumerate() {
+            let mut encoded = BytesMut::new();
+            codec.encode(message.clone(), &mut encoded)?;
+            if encoded.as_ref() != frame {
+                let actual = codec.decode(&mut BytesMut::from(frame))?;
+                println!("\nMessage {i} differs:");
+                println!("Expected: {message:?}");
+                println!("Actual:   {actual:?}");
+                print_byte_comparison(encoded.as_ref(), frame, &format!("Message {i}"));
+                panic!("message {i} is not encoded as captured");
+            }
+        }
+
+        Ok(())
+    }
+
     fn print_byte_comparison(actual: &[u8], expected: &[u8], message_name: &str) {
-        println!("\n=== ENCODING MISMATCH: {} ===", message_name);
+        println!("\n=== ENCODING MISMATCH: {message_name} ===");
         println!(
             "Expected length: {}, Actual length: {}",
             expected.len(),
@@ -610,19 +785,16 @@ mod encoding_verification_tests {
 
             match (actual_byte, expected_byte) {
                 (Some(a), Some(e)) if a == e => {
-                    println!("  {:3}: 0x{:02x} 0x{:02x} ✓", i, a, e);
+                    println!("  {i:3}: 0x{a:02x} 0x{e:02x} ✓");
                 }
                 (Some(a), Some(e)) => {
-                    println!(
-                        "  {:3}: 0x{:02x} 0x{:02x} ✗ (got {}, expected {})",
-                        i, a, e, a, e
-                    );
+                    println!("  {i:3}: 0x{a:02x} 0x{e:02x} ✗ (got {a}, expected {e})");
                 }
                 (Some(a), None) => {
-                    println!("  {:3}: 0x{:02x} ---- ✗ (extra byte)", i, a);
+                    println!("  {i:3}: 0x{a:02x} ---- ✗ (extra byte)");
                 }
                 (None, Some(e)) => {
-                    println!("  {:3}: ---- 0x{:02x} ✗ (missing byte)", i, e);
+                    println!("  {i:3}: ---- 0x{e:02x} ✗ (missing byte)");
                 }
                 (None, None) => unreachable!(),
             }
@@ -635,7 +807,7 @@ mod encoding_verification_tests {
             if i > 0 && i % 16 == 0 {
                 print!("\n  ");
             }
-            print!("{:02x} ", byte);
+            print!("{byte:02x} ");
         }
         println!();
 
@@ -645,139 +817,19 @@ mod encoding_verification_tests {
             if i > 0 && i % 16 == 0 {
                 print!("\n  ");
             }
-            print!("{:02x} ", byte);
+            print!("{byte:02x} ");
         }
         println!("\n");
     }
 
     #[test]
     fn test_client_message_encoding() -> Result<()> {
-        let expected_messages = build_expected_client_messages();
-        let mut codec = MessageCodec::new();
-        let mut encoded = BytesMut::new();
-
-        // Encode our constructed messages
-        for msg in &expected_messages {
-            codec.encode(msg.clone(), &mut encoded)?;
-        }
-
-        // Compare with actual captured data
-        let actual_data = DATA_COMPREHENSIVE_CLIENT;
-
-        if encoded.as_ref() != actual_data {
-            println!("CLIENT MESSAGE ENCODING MISMATCH!");
-
-            // Try to decode the actual data to see what we should be producing
-            let mut actual_codec = MessageCodec::new();
-            let mut actual_buf = BytesMut::from(actual_data);
-            let mut decoded_actual = Vec::new();
-
-            while !actual_buf.is_empty() {
-                match actual_codec.decode(&mut actual_buf)? {
-                    Some(msg) => decoded_actual.push(msg),
-                    None => break,
-                }
-            }
-
-            println!(
-                "Expected {} messages, actual data has {} messages",
-                expected_messages.len(),
-                decoded_actual.len()
-            );
-
-            // Compare message by message
-            for (i, (expected, actual)) in expected_messages
-                .iter()
-                .zip(decoded_actual.iter())
-                .enumerate()
-            {
-                if expected != actual {
-                    println!("\nMessage {} differs:", i);
-                    println!("Expected: {:?}", expected);
-                    println!("Actual:   {:?}", actual);
-
-                    // Encode each message individually for comparison
-                    let mut expected_bytes = BytesMut::new();
-                    let mut actual_bytes = BytesMut::new();
-
-                    codec.encode(expected.clone(), &mut expected_bytes)?;
-                    codec.encode(actual.clone(), &mut actual_bytes)?;
-
-                    print_byte_comparison(
-                        expected_bytes.as_ref(),
-                        actual_bytes.as_ref(),
-                        &format!("Message {}", i),
-                    );
-
-                    // Stop at first difference for now
-                    break;
-                }
-            }
-
-            panic!("Encoding mismatch detected");
-        }
-
-        Ok(())
+        assert_encoded_as_captured(&expected_client_messages(), DATA_COMPREHENSIVE_CLIENT)
     }
 
     #[test]
     fn test_server_message_encoding() -> Result<()> {
-        let expected_messages = build_expected_server_messages();
-        let mut codec = MessageCodec::new();
-        let mut encoded = BytesMut::new();
-
-        for msg in &expected_messages {
-            codec.encode(msg.clone(), &mut encoded)?;
-        }
-
-        let actual_data = DATA_COMPREHENSIVE_SERVER;
-
-        if encoded.as_ref() != actual_data {
-            println!("SERVER MESSAGE ENCODING MISMATCH!");
-
-            // Decode actual data for comparison
-            let mut actual_codec = MessageCodec::new();
-            let mut actual_buf = BytesMut::from(actual_data);
-            let mut decoded_actual = Vec::new();
-
-            while !actual_buf.is_empty() {
-                match actual_codec.decode(&mut actual_buf)? {
-                    Some(msg) => decoded_actual.push(msg),
-                    None => break,
-                }
-            }
-
-            // Compare message by message
-            for (i, (expected, actual)) in expected_messages
-                .iter()
-                .zip(decoded_actual.iter())
-                .enumerate()
-            {
-                if expected != actual {
-                    println!("\nServer Message {} differs:", i);
-                    println!("Expected: {:?}", expected);
-                    println!("Actual:   {:?}", actual);
-
-                    let mut expected_bytes = BytesMut::new();
-                    let mut actual_bytes = BytesMut::new();
-
-                    codec.encode(expected.clone(), &mut expected_bytes)?;
-                    codec.encode(actual.clone(), &mut actual_bytes)?;
-
-                    print_byte_comparison(
-                        expected_bytes.as_ref(),
-                        actual_bytes.as_ref(),
-                        &format!("Server Message {}", i),
-                    );
-
-                    break;
-                }
-            }
-
-            panic!("Server encoding mismatch detected");
-        }
-
-        Ok(())
+        assert_encoded_as_captured(&expected_server_messages(), DATA_COMPREHENSIVE_SERVER)
     }
 
     #[test]
@@ -817,326 +869,388 @@ mod encoding_verification_tests {
     }
 }
 
-fn expected_client_messages() -> Vec<TaggedMessage> {
-    use bytes::Bytes;
+mod linux_round_trips {
+    use super::*;
+    use crate::consts::P9_NONUNAME;
+    use bytes::BytesMut;
 
-    vec![
-        TaggedMessage::new(
-            65535,
-            Message::Tversion(Tversion {
-                msize: 131_096,
-                version: "9P2000".to_string(),
-            }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Tattach(Tattach {
-                fid: 0,
-                afid: P9_NOFID,
-                uname: "justin".to_string(),
+    fn qid(path: u64) -> Qid {
+        Qid {
+            qtype: QidType::File.into(),
+            version: 7,
+            path,
+        }
+    }
+
+    /// Each message must decode to itself after being encoded as 9P2000.L, using up its frame
+    fn assert_round_trips(messages: Vec<Message>) {
+        let mut codec = MessageCodec::new();
+        codec.set_dialect(Dialect::Linux);
+        for (tag, message) in messages.into_iter().enumerate() {
+            let tagged = TaggedMessage::new(u16::try_from(tag).unwrap(), message);
+            let mut buf = BytesMut::new();
+            codec.encode(tagged.clone(), &mut buf).unwrap();
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(tagged));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn lerror() {
+        assert_round_trips(vec![Message::Rlerror(Rlerror { ecode: 2 })]);
+    }
+
+    #[test]
+    fn attach_carries_n_uname() {
+        assert_round_trips(vec![
+            Message::Tauth(Tauth {
+                afid: 3,
+                uname: "glenda".to_string(),
                 aname: String::new(),
+                n_uname: 1000,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 0 })),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 0 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec![],
-            }),
-        ),
-        TaggedMessage::new(0, Message::Topen(Topen { fid: 1, mode: 0 })),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 0 })),
-        TaggedMessage::new(
-            0,
-            Message::Tread(Tread {
-                fid: 1,
-                offset: 0,
-                count: 8192,
-            }),
-        ),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec!["test.txt".to_string()],
-            }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec![],
-            }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Tcreate(Tcreate {
+            Message::Tattach(Tattach {
                 fid: 1,
-                name: "test.txt".to_string(),
-                perm: 0o644,
-                mode: 17,
+                afid: 3,
+                uname: String::new(),
+                aname: "/srv".to_string(),
+                n_uname: P9_NONUNAME,
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 2,
-                wnames: vec!["test.txt".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn statfs() {
+        assert_round_trips(vec![
+            Message::Tstatfs(Tstatfs { fid: 1 }),
+            Message::Rstatfs(Rstatfs {
+                r#type: 0x0102_1994,
+                bsize: 4096,
+                blocks: 1 << 40,
+                bfree: 12,
+                bavail: 11,
+                files: 1 << 33,
+                ffree: 5,
+                fsid: u64::MAX,
+                namelen: 255,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
-        TaggedMessage::new(
-            0,
-            Message::Twrite(Twrite {
+        ]);
+    }
+
+    #[test]
+    fn lopen() {
+        assert_round_trips(vec![
+            Message::Tlopen(Tlopen {
                 fid: 1,
-                offset: 0,
-                data: Bytes::from("Hello, 9P world!\n"),
+                flags: 0o1_000_002,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec!["test.txt".to_string()],
+            Message::Rlopen(Rlopen {
+                qid: qid(9),
+                iounit: 8168,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 1 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
+        ]);
+    }
+
+    #[test]
+    fn lcreate() {
+        assert_round_trips(vec![
+            Message::Tlcreate(Tlcreate {
                 fid: 1,
-                newfid: 2,
-                wnames: vec![],
+                name: "new".to_string(),
+                flags: 0o102,
+                mode: 0o644,
+                gid: 100,
             }),
-        ),
-        TaggedMessage::new(0, Message::Topen(Topen { fid: 2, mode: 1 })),
-        TaggedMessage::new(
-            0,
-            Message::Twrite(Twrite {
-                fid: 2,
-                offset: 17,
-                data: Bytes::from("Second line\n"),
-            }),
-        ),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec!["subdir".to_string()],
-            }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec![],
+            Message::Rlcreate(Rlcreate {
+                qid: qid(10),
+                iounit: 0,
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Tcreate(Tcreate {
+        ]);
+    }
+
+    #[test]
+    fn symlink() {
+        assert_round_trips(vec![
+            Message::Tsymlink(Tsymlink {
                 fid: 1,
-                name: "subdir".to_string(),
-                perm: 0o755 | 0x8000_0000, // DMDIR
-                mode: 0,
-            }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 2,
-                wnames: vec!["subdir".to_string()],
+                name: "link".to_string(),
+                symtgt: "../target".to_string(),
+                gid: 100,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec!["subdir".to_string()],
+            Message::Rsymlink(Rsymlink { qid: qid(11) }),
+        ]);
+    }
+
+    #[test]
+    fn mknod() {
+        assert_round_trips(vec![
+            Message::Tmknod(Tmknod {
+                dfid: 1,
+                name: "null".to_string(),
+                mode: 0o20_666,
+                major: 1,
+                minor: 3,
+                gid: 0,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 1 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 1,
-                newfid: 2,
-                wnames: vec!["nested.txt".to_string()],
+            Message::Rmknod(Rmknod { qid: qid(12) }),
+        ]);
+    }
+
+    #[test]
+    fn rename() {
+        assert_round_trips(vec![
+            Message::Trename(Trename {
+                fid: 2,
+                dfid: 1,
+                name: "renamed".to_string(),
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 1,
-                newfid: 2,
-                wnames: vec![],
+            Message::Rrename(Rrename),
+        ]);
+    }
+
+    #[test]
+    fn readlink() {
+        assert_round_trips(vec![
+            Message::Treadlink(Treadlink { fid: 2 }),
+            Message::Rreadlink(Rreadlink {
+                target: "/etc/hostname".to_string(),
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Tcreate(Tcreate {
+        ]);
+    }
+
+    #[test]
+    fn getattr() {
+        assert_round_trips(vec![
+            Message::Tgetattr(Tgetattr {
                 fid: 2,
-                name: "nested.txt".to_string(),
-                perm: 0o644,
-                mode: 17,
+                request_mask: 0x3fff,
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 1,
-                newfid: 3,
-                wnames: vec!["nested.txt".to_string()],
+            Message::Rgetattr(Rgetattr {
+                valid: 0x07ff,
+                qid: qid(13),
+                mode: 0o100_644,
+                uid: 1000,
+                gid: 100,
+                nlink: 1,
+                rdev: 0,
+                size: 1 << 35,
+                blksize: 4096,
+                blocks: 8,
+                atime_sec: 1,
+                atime_nsec: 2,
+                mtime_sec: 3,
+                mtime_nsec: 4,
+                ctime_sec: 5,
+                ctime_nsec: 6,
+                btime_sec: 7,
+                btime_nsec: 8,
+                gen: 9,
+                data_version: 10,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 3 })),
-        TaggedMessage::new(
-            0,
-            Message::Twrite(Twrite {
+        ]);
+    }
+
+    #[test]
+    fn setattr() {
+        assert_round_trips(vec![
+            Message::Tsetattr(Tsetattr {
                 fid: 2,
-                offset: 0,
-                data: Bytes::from("Nested content\n"),
+                valid: 0x1ff,
+                mode: 0o600,
+                uid: 1000,
+                gid: 100,
+                size: 42,
+                atime_sec: 1,
+                atime_nsec: 2,
+                mtime_sec: 3,
+                mtime_nsec: 4,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 3 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec!["newfile.txt".to_string()],
+            Message::Rsetattr(Rsetattr),
+        ]);
+    }
+
+    #[test]
+    fn xattrwalk() {
+        assert_round_trips(vec![
+            Message::Txattrwalk(Txattrwalk {
+                fid: 2,
+                newfid: 3,
+                name: "user.comment".to_string(),
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 1,
-                wnames: vec![],
+            Message::Rxattrwalk(Rxattrwalk { size: 17 }),
+        ]);
+    }
+
+    #[test]
+    fn xattrcreate() {
+        assert_round_trips(vec![
+            Message::Txattrcreate(Txattrcreate {
+                fid: 3,
+                name: "user.comment".to_string(),
+                attr_size: 17,
+                flags: 1,
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Tcreate(Tcreate {
+            Message::Rxattrcreate(Rxattrcreate),
+        ]);
+    }
+
+    #[test]
+    fn readdir() {
+        assert_round_trips(vec![
+            Message::Treaddir(Treaddir {
                 fid: 1,
-                name: "newfile.txt".to_string(),
-                perm: 0o644,
-                mode: 1,
+                offset: 1 << 40,
+                count: 8168,
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Twalk(Twalk {
-                fid: 0,
-                newfid: 2,
-                wnames: vec!["newfile.txt".to_string()],
+            Message::Rreaddir(Rreaddir {
+                data: Bytes::from_static(b"\x00entries\xff"),
             }),
-        ),
-        TaggedMessage::new(0, Message::Tstat(Tstat { fid: 2 })),
-        TaggedMessage::new(
-            0,
-            Message::Twstat(Twstat {
+        ]);
+    }
+
+    #[test]
+    fn fsync() {
+        assert_round_trips(vec![
+            Message::Tfsync(Tfsync {
                 fid: 2,
-                stat: create_twstat_bytes(),
+                datasync: 1,
             }),
-        ),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 1 })),
-        TaggedMessage::new(0, Message::Tclunk(Tclunk { fid: 2 })),
-        // ... continuing with the rest of the messages following the same pattern
-        // (truncated for brevity, but you would continue parsing all messages from the log)
-    ]
-}
-
-fn expected_server_messages() -> Vec<TaggedMessage> {
-    use bytes::Bytes;
+            Message::Rfsync(Rfsync),
+        ]);
+    }
 
-    vec![
-        TaggedMessage::new(
-            65535,
-            Message::Rversion(Rversion {
-                msize: 8216,
-                version: "9P2000".to_string(),
+    #[test]
+    fn lock() {
+        assert_round_trips(vec![
+            Message::Tlock(Tlock {
+                fid: 2,
+                r#type: 1,
+                flags: 2,
+                start: 0,
+                length: u64::MAX,
+                proc_id: 4242,
+                client_id: "host".to_string(),
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Rattach(Rattach {
-                qid: Qid::from_log_format(0x001d_e954, 1_748_236_823, 'd'),
+            Message::Rlock(Rlock { status: 1 }),
+        ]);
+    }
+
+    #[test]
+    fn getlock() {
+        assert_round_trips(vec![
+            Message::Tgetlock(Tgetlock {
+                fid: 2,
+                r#type: 0,
+                start: 10,
+                length: 20,
+                proc_id: 4242,
+                client_id: "host".to_string(),
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Rstat(Rstat {
-                stat: create_root_stat_bytes(),
+            Message::Rgetlock(Rgetlock {
+                r#type: 2,
+                start: 10,
+                length: 20,
+                proc_id: 4343,
+                client_id: "other".to_string(),
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Rstat(Rstat {
-                stat: create_root_stat_bytes(),
+        ]);
+    }
+
+    #[test]
+    fn link() {
+        assert_round_trips(vec![
+            Message::Tlink(Tlink {
+                dfid: 1,
+                fid: 2,
+                name: "hardlink".to_string(),
             }),
-        ),
-        TaggedMessage::new(0, Message::Rwalk(Rwalk { wqids: vec![] })),
-        TaggedMessage::new(
-            0,
-            Message::Ropen(Ropen {
-                qid: Qid::from_log_format(0x001d_e954, 1_748_236_823, 'd'),
-                iounit: 0,
+            Message::Rlink(Rlink),
+        ]);
+    }
+
+    #[test]
+    fn mkdir() {
+        assert_round_trips(vec![
+            Message::Tmkdir(Tmkdir {
+                dfid: 1,
+                name: "dir".to_string(),
+                mode: 0o755,
+                gid: 100,
             }),
-        ),
-        TaggedMessage::new(
-            0,
-            Message::Rstat(Rstat {
-                stat: create_root_stat_bytes(),
+            Message::Rmkdir(Rmkdir { qid: qid(14) }),
+        ]);
+    }
+
+    #[test]
+    fn renameat() {
+        assert_round_trips(vec![
+            Message::Trenameat(Trenameat {
+                olddirfid: 1,
+                oldname: "old".to_string(),
+                newdirfid: 4,
+                newname: "new".to_string(),
             }),
-        ),
-        TaggedMessage::new(0, Message::Rread(Rread { data: Bytes::new() })),
-        TaggedMessage::new(0, Message::Rclunk(Rclunk)),
-        TaggedMessage::new(
-            0,
-            Message::Rerror(Rerror {
-                ename: "No such file or directory".to_string(),
+            Message::Rrenameat(Rrenameat),
+        ]);
+    }
+
+    #[test]
+    fn unlinkat() {
+        assert_round_trips(vec![
+            Message::Tunlinkat(Tunlinkat {
+                dirfid: 1,
+                name: "dir".to_string(),
+                flags: 0x200,
             }),
-        ),
-        TaggedMessage::new(0, Message::Rwalk(Rwalk { wqids: vec![] })),
+            Message::Runlinkat(Runlinkat),
+        ]);
+    }
+}
+
+mod msize_limits {
+    use super::*;
+    use bytes::BytesMut;
+
+    /// A Twrite whose frame is `size` bytes long, size field included
+    fn twrite_of(size: usize) -> TaggedMessage {
+        // size[4] type[1] tag[2] fid[4] offset[8] count[4]
+        let data = vec![0; size - 23];
         TaggedMessage::new(
-            0,
-            Message::Rcreate(Rcreate {
-                qid: Qid::from_log_format(0x003a89fe, 1748238273, ' '),
-                iounit: 0,
+            1,
+            Message::Twrite(Twrite {
+                fid: 1,
+                offset: 0,
+                data: data.into(),
             }),
-        ),
-        // TODO: finish these
-    ]
-}
+        )
+    }
 
-fn create_twstat_bytes() -> Stat {
-    todo!();
-}
+    #[test]
+    fn frames_of_exactly_msize_are_accepted() {
+        let mut buf = BytesMut::new();
+        MessageCodec::with_msize(64)
+            .encode(twrite_of(64), &mut buf)
+            .unwrap();
+        assert_eq!(buf.len(), 64);
+        let decoded = MessageCodec::with_msize(64).decode(&mut buf).unwrap();
+        assert_eq!(decoded, Some(twrite_of(64)));
+    }
 
-// Helper function to create root directory stat bytes
-fn create_root_stat_bytes() -> Stat {
-    todo!()
+    #[test]
+    fn frames_over_msize_are_refused() {
+        let mut buf = BytesMut::new();
+        assert!(MessageCodec::with_msize(64)
+            .encode(twrite_of(65), &mut buf)
+            .is_err());
+
+        MessageCodec::new().encode(twrite_of(65), &mut buf).unwrap();
+        assert!(matches!(
+            MessageCodec::with_msize(64).decode(&mut buf),
+            Err(Error::Protocol(_))
+        ));
+
+        // the size field alone is enough to refuse a frame, before the rest of it arrives
+        let mut header = BytesMut::from(&[0xff, 0xff, 0x00, 0x00][..]);
+        assert!(MessageCodec::with_msize(64).decode(&mut header).is_err());
+    }
 }
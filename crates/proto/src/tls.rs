@@ -0,0 +1,116 @@
+//! TLS for 9P over TCP, with rustls
+//!
+//! Certificates and keys are read from PEM files. A server may require clients to present a
+//! certificate issued by a CA of its choosing, the common name of which then names the client.
+//!
+//! Self-signed certificates do, each one being its own CA, as long as they are not marked as
+//! CAs: rustls refuses to see a CA certificate used by a server or client. With openssl:
+//!
+//! ```text
+//! openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+//!     -keyout server.key -out server.pem -subj /CN=localhost \
+//!     -addext subjectAltName=DNS:localhost -addext basicConstraints=critical,CA:FALSE
+//! ```
+
+use std::{fs::File, io, path::Path, sync::Arc};
+use tokio_rustls::rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore,
+};
+
+pub use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {e}", path.display()),
+    )
+}
+
+fn certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_reader_iter(File::open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(path, e))?;
+    if certificates.is_empty() {
+        return Err(invalid(path, "no certificates"));
+    }
+    Ok(certificates)
+}
+
+fn private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_reader(File::open(path)?).map_err(|e| invalid(path, e))
+}
+
+fn roots(path: &Path) -> io::Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates(path)? {
+        roots.add(certificate).map_err(|e| invalid(path, e))?;
+    }
+    Ok(Arc::new(roots))
+}
+
+/// Serve with the certificate chain in `cert` and its key in `key`
+///
+/// With `client_ca` every client has to present a certificate issued by one of the CAs in it.
+///
+/// # Errors
+/// - a file cannot be read or holds no usable certificate or key
+/// - the key does not go with the certificate
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> io::Result<Arc<ServerConfig>> {
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+    let builder = match client_ca {
+        Some(path) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots(path)?, provider())
+                .build()
+                .map_err(|e| invalid(path, e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certificates(cert)?, private_key(key)?)
+        .map_err(|e| invalid(cert, e))?;
+    Ok(Arc::new(config))
+}
+
+/// Trust servers with a certificate issued by one of the CAs in `ca`
+///
+/// A self-signed server certificate is its own CA. `identity` is the certificate chain and key
+/// presented to servers that ask for one.
+///
+/// # Errors
+/// - a file cannot be read or holds no usable certificate or key
+/// - the key does not go with the certificate
+pub fn client_config(ca: &Path, identity: Option<(&Path, &Path)>) -> io::Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots(ca)?);
+    let config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+            .map_err(|e| invalid(cert, e))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// The common name in the subject of `certificate`, which names a client
+#[must_use]
+pub fn common_name(certificate: &CertificateDer<'_>) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    let name = certificate.subject().iter_common_name().next()?;
+    name.as_str().ok().map(ToString::to_string)
+}
@@ -0,0 +1,253 @@
+use crate::{FidError, Session};
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use stowage_proto::{
+    auth::{self, CHALLENGE_LEN, RESPONSE_LEN},
+    consts::{errno::EACCES, P9_NOFID},
+    Message, Qid, QidType, Rauth, Rclunk, Rread, Rremove, Rwrite, Tattach, Tauth,
+};
+
+/// Decides who may attach, through a conversation over the afid of a Tauth
+///
+/// Once a server has an authenticator every Tattach must name an afid whose conversation
+/// proved the same user name and tree the attach asks for.
+pub trait Authenticator: Send + Sync {
+    /// Start authenticating `uname` for the tree `aname`
+    ///
+    /// # Errors
+    /// - `uname` cannot authenticate at all, the message is sent to the client
+    fn start(&self, uname: &str, aname: &str) -> Result<Box<dyn Conversation>, String>;
+}
+
+/// One run of an authentication protocol, the client reads and writes it through the afid
+pub trait Conversation: Send {
+    /// What the server says next, at most `count` bytes of it
+    ///
+    /// # Errors
+    /// - the protocol expects the client to write, the message is sent to the client
+    fn read(&mut self, count: u32) -> Result<Bytes, String>;
+
+    /// Take what the client says, returning how much of `data` was consumed
+    ///
+    /// # Errors
+    /// - the client said something wrong, the message is sent to the client
+    fn write(&mut self, data: &[u8]) -> Result<u32, String>;
+
+    /// Whether the protocol finished, proving the identity it was started for
+    fn authenticated(&self) -> bool;
+}
+
+/// An afid and the conversation behind it
+pub(crate) struct AuthFid {
+    uname: String,
+    n_uname: u32,
+    aname: String,
+    conversation: Mutex<Box<dyn Conversation>>,
+}
+
+impl AuthFid {
+    fn conversation(&self) -> std::sync::MutexGuard<'_, Box<dyn Conversation>> {
+        self.conversation.lock().unwrap()
+    }
+}
+
+/// Answer `message` if it is about authentication, leaving everything else to the handler
+///
+/// Besides reads, writes and clunks of afids this refuses attaches that were not
+/// authenticated, and binding a fid that is in use as an afid.
+pub(crate) fn intercept<F>(
+    authenticator: &dyn Authenticator,
+    session: &Session<F>,
+    message: &Message,
+) -> Option<Message> {
+    let auth_fid = |fid: u32| session.auths().get(&fid).cloned();
+
+    match message {
+        Message::Tauth(tauth) => Some(start(authenticator, session, tauth)),
+        Message::Tattach(tattach) if session.auths().contains_key(&tattach.fid) => {
+            Some(FidError::DuplicateFid.into())
+        }
+        Message::Tattach(tattach) => refusal(session, tattach),
+        Message::Twalk(twalk) if session.auths().contains_key(&twalk.newfid) => {
+            Some(FidError::DuplicateFid.into())
+        }
+        Message::Tread(tread) => {
+            let auth = auth_fid(tread.fid)?;
+            let reply = match auth.conversation().read(tread.count) {
+                Ok(data) => Message::Rread(Rread { data }),
+                Err(ename) => Message::error_with_errno(ename, EACCES),
+            };
+            Some(reply)
+        }
+        Message::Twrite(twrite) => {
+            let auth = auth_fid(twrite.fid)?;
+            let reply = match auth.conversation().write(&twrite.data) {
+                Ok(count) => Message::Rwrite(Rwrite { count }),
+                Err(ename) => Message::error_with_errno(ename, EACCES),
+            };
+            Some(reply)
+        }
+        Message::Tclunk(tclunk) => session
+            .auths()
+            .remove(&tclunk.fid)
+            .map(|_| Message::Rclunk(Rclunk)),
+        Message::Tremove(tremove) => session
+            .auths()
+            .remove(&tremove.fid)
+            .map(|_| Message::Rremove(Rremove)),
+        _ => None,
+    }
+}
+
+fn start<F>(authenticator: &dyn Authenticator, session: &Session<F>, tauth: &Tauth) -> Message {
+    if tauth.afid == P9_NOFID
+        || session.fids().get(tauth.afid).is_ok()
+        || session.auths().contains_key(&tauth.afid)
+    {
+        return FidError::DuplicateFid.into();
+    }
+
+    let conversation = match authenticator.start(&tauth.uname, &tauth.aname) {
+        Ok(conversation) => conversation,
+        Err(ename) => return Message::error_with_errno(ename, EACCES),
+    };
+    let auth = AuthFid {
+        uname: tauth.uname.clone(),
+        n_uname: tauth.n_uname,
+        aname: tauth.aname.clone(),
+        conversation: Mutex::new(conversation),
+    };
+    session.auths().insert(tauth.afid, Arc::new(auth));
+
+    Message::Rauth(Rauth {
+        aqid: Qid {
+            qtype: QidType::Auth.into(),
+            version: 0,
+            path: u64::from(tauth.afid),
+        },
+    })
+}
+
+/// Why `tattach` is refused, if its afid did not prove who it attaches as
+fn refusal<F>(session: &Session<F>, tattach: &Tattach) -> Option<Message> {
+    let denied = |ename: &str| Some(Message::error_with_errno(ename.to_string(), EACCES));
+    if tattach.afid == P9_NOFID {
+        return denied("authentication required");
+    }
+
+    let Some(auth) = session.auths().get(&tattach.afid).cloned() else {
+        return Some(FidError::UnknownFid.into());
+    };
+    if auth.uname != tattach.uname || auth.n_uname != tattach.n_uname {
+        return denied("authenticated as another user");
+    }
+    if auth.aname != tattach.aname {
+        return denied("authenticated for another tree");
+    }
+    if !auth.conversation().authenticated() {
+        return denied("authentication failed");
+    }
+
+    None
+}
+
+/// Challenge–response with a secret the server shares with its clients
+///
+/// The protocol is the one of `stowage_proto::auth`: the client reads a random challenge from
+/// the afid and writes back an HMAC over it and the names it attaches with, keyed with the
+/// secret.
+pub struct SharedSecret {
+    secret: Arc<[u8]>,
+}
+
+impl SharedSecret {
+    #[must_use]
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into().into(),
+        }
+    }
+}
+
+impl Authenticator for SharedSecret {
+    fn start(&self, uname: &str, aname: &str) -> Result<Box<dyn Conversation>, String> {
+        let mut nonce = [0; CHALLENGE_LEN];
+        getrandom::fill(&mut nonce).map_err(|e| format!("no challenge: {e}"))?;
+
+        Ok(Box::new(Challenge {
+            secret: self.secret.clone(),
+            uname: uname.to_string(),
+            aname: aname.to_string(),
+            nonce,
+            sent: 0,
+            response: Vec::with_capacity(RESPONSE_LEN),
+            state: State::Challenging,
+        }))
+    }
+}
+
+/// Where a shared-secret conversation is at
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// the challenge is being read and answered
+    Challenging,
+    Authenticated,
+    Failed,
+}
+
+struct Challenge {
+    secret: Arc<[u8]>,
+    uname: String,
+    aname: String,
+    /// the random bytes challenged with
+    nonce: [u8; CHALLENGE_LEN],
+    /// how much of the challenge was read
+    sent: usize,
+    /// what was written of the response so far
+    response: Vec<u8>,
+    state: State,
+}
+
+impl Conversation for Challenge {
+    fn read(&mut self, count: u32) -> Result<Bytes, String> {
+        let end = CHALLENGE_LEN.min(self.sent + count as usize);
+        let data = Bytes::copy_from_slice(&self.nonce[self.sent..end]);
+        self.sent = end;
+        Ok(data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<u32, String> {
+        match self.state {
+            State::Challenging if self.sent < CHALLENGE_LEN => {
+                return Err("read the challenge first".to_string())
+            }
+            State::Challenging => {}
+            State::Authenticated => return Err("already authenticated".to_string()),
+            State::Failed => return Err("authentication failed".to_string()),
+        }
+
+        let count = data.len().min(RESPONSE_LEN - self.response.len());
+        self.response.extend_from_slice(&data[..count]);
+        if self.response.len() == RESPONSE_LEN {
+            let verified = auth::verify(
+                &self.secret,
+                &self.nonce,
+                &self.uname,
+                &self.aname,
+                &self.response,
+            );
+            if !verified {
+                self.state = State::Failed;
+                return Err("authentication failed".to_string());
+            }
+            self.state = State::Authenticated;
+        }
+
+        // unwrap - at most RESPONSE_LEN
+        Ok(u32::try_from(count).unwrap())
+    }
+
+    fn authenticated(&self) -> bool {
+        self.state == State::Authenticated
+    }
+}
@@ -0,0 +1,320 @@
+use flagset::FlagSet;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use stowage_proto::{
+    consts::errno::{EBADF, EBUSY, EINVAL, ENOENT, ENOTDIR, EPROTO},
+    Message, OpenMode,
+};
+
+/// Ways a request can misuse a fid, worded like Plan 9's lib9p
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FidError {
+    #[error("unknown fid")]
+    UnknownFid,
+
+    #[error("duplicate fid")]
+    DuplicateFid,
+
+    #[error("cannot clone open fid")]
+    CloneOpenFid,
+
+    #[error("9P protocol botch")]
+    Botch,
+
+    #[error("walk in non-directory")]
+    WalkNonDirectory,
+
+    #[error("file not found")]
+    NotFound,
+
+    #[error("bad offset")]
+    BadOffset,
+}
+
+impl FidError {
+    /// The errno reported to 9P2000.u and 9P2000.L clients
+    #[must_use]
+    pub fn errno(self) -> u32 {
+        match self {
+            Self::UnknownFid | Self::DuplicateFid => EBADF,
+            Self::CloneOpenFid => EBUSY,
+            Self::Botch => EPROTO,
+            Self::WalkNonDirectory => ENOTDIR,
+            Self::NotFound => ENOENT,
+            Self::BadOffset => EINVAL,
+        }
+    }
+}
+
+impl From<FidError> for Message {
+    fn from(error: FidError) -> Self {
+        Message::error_with_errno(error.to_string(), error.errno())
+    }
+}
+
+struct Entry<T> {
+    value: Arc<T>,
+    mode: Option<FlagSet<OpenMode>>,
+}
+
+/// The fids of a session and whatever a handler keeps for each of them
+///
+/// The table enforces the rules 9P places on fids so handlers only deal with their own state:
+/// a newfid must be unused unless it is the fid being walked, a walk only binds newfid once
+/// every name was walked, open fids can neither be walked nor opened again, and clunk always
+/// frees the fid.
+pub struct FidTable<T> {
+    entries: Mutex<HashMap<u32, Entry<T>>>,
+}
+
+impl<T> FidTable<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<u32, Entry<T>>> {
+        self.entries.lock().unwrap()
+    }
+
+    /// Bind a fresh fid, as done by Tattach and Tauth
+    ///
+    /// # Errors
+    /// - `fid` is already in use
+    pub fn attach(&self, fid: u32, value: T) -> Result<Arc<T>, FidError> {
+        let mut entries = self.entries();
+        if entries.contains_key(&fid) {
+            return Err(FidError::DuplicateFid);
+        }
+
+        let value = Arc::new(value);
+        entries.insert(
+            fid,
+            Entry {
+                value: value.clone(),
+                mode: None,
+            },
+        );
+        Ok(value)
+    }
+
+    /// # Errors
+    /// - `fid` is not in use
+    pub fn get(&self, fid: u32) -> Result<Arc<T>, FidError> {
+        self.entries()
+            .get(&fid)
+            .map(|entry| entry.value.clone())
+            .ok_or(FidError::UnknownFid)
+    }
+
+    /// The value of an opened fid along with the mode it was opened with
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    /// - `fid` has not been opened
+    pub fn get_open(&self, fid: u32) -> Result<(Arc<T>, FlagSet<OpenMode>), FidError> {
+        let entries = self.entries();
+        let entry = entries.get(&fid).ok_or(FidError::UnknownFid)?;
+        let mode = entry.mode.ok_or(FidError::Botch)?;
+        Ok((entry.value.clone(), mode))
+    }
+
+    /// Check that `fid` may be walked into `newfid` and return where the walk starts
+    ///
+    /// Nothing is bound yet, call `bind` once every name has been walked.
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    /// - `fid` has been opened
+    /// - `newfid` is in use and differs from `fid`
+    pub fn walk(&self, fid: u32, newfid: u32) -> Result<Arc<T>, FidError> {
+        let entries = self.entries();
+        let entry = entries.get(&fid).ok_or(FidError::UnknownFid)?;
+        if entry.mode.is_some() {
+            return Err(FidError::CloneOpenFid);
+        }
+        if newfid != fid && entries.contains_key(&newfid) {
+            return Err(FidError::DuplicateFid);
+        }
+        Ok(entry.value.clone())
+    }
+
+    /// Bind `newfid` to the result of a complete walk from `fid`
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    /// - `newfid` is in use and differs from `fid`
+    pub fn bind(&self, fid: u32, newfid: u32, value: T) -> Result<Arc<T>, FidError> {
+        let mut entries = self.entries();
+        if !entries.contains_key(&fid) {
+            return Err(FidError::UnknownFid);
+        }
+        if newfid != fid && entries.contains_key(&newfid) {
+            return Err(FidError::DuplicateFid);
+        }
+
+        let value = Arc::new(value);
+        entries.insert(
+            newfid,
+            Entry {
+                value: value.clone(),
+                mode: None,
+            },
+        );
+        Ok(value)
+    }
+
+    /// Check that `fid` may be opened or created in, returning its current value
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    /// - `fid` has already been opened
+    pub fn opening(&self, fid: u32) -> Result<Arc<T>, FidError> {
+        let entries = self.entries();
+        let entry = entries.get(&fid).ok_or(FidError::UnknownFid)?;
+        if entry.mode.is_some() {
+            return Err(FidError::Botch);
+        }
+        Ok(entry.value.clone())
+    }
+
+    /// Mark `fid` as open, replacing its value with the opened file
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    /// - `fid` has already been opened
+    pub fn opened(&self, fid: u32, mode: FlagSet<OpenMode>, value: T) -> Result<Arc<T>, FidError> {
+        let mut entries = self.entries();
+        let entry = entries.get_mut(&fid).ok_or(FidError::UnknownFid)?;
+        if entry.mode.is_some() {
+            return Err(FidError::Botch);
+        }
+
+        entry.value = Arc::new(value);
+        entry.mode = Some(mode);
+        Ok(entry.value.clone())
+    }
+
+    /// Replace the value of `fid`, keeping its open mode
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    pub fn replace(&self, fid: u32, value: T) -> Result<Arc<T>, FidError> {
+        let mut entries = self.entries();
+        let entry = entries.get_mut(&fid).ok_or(FidError::UnknownFid)?;
+        entry.value = Arc::new(value);
+        Ok(entry.value.clone())
+    }
+
+    /// Free `fid`, returning its value so the handler can release what it holds
+    ///
+    /// # Errors
+    /// - `fid` is not in use
+    pub fn clunk(&self, fid: u32) -> Result<Arc<T>, FidError> {
+        self.entries()
+            .remove(&fid)
+            .map(|entry| entry.value)
+            .ok_or(FidError::UnknownFid)
+    }
+
+    /// Every fid currently in use
+    #[must_use]
+    pub fn ids(&self) -> Vec<u32> {
+        self.entries().keys().copied().collect()
+    }
+
+    /// Free every fid
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+}
+
+impl<T> Default for FidTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table holding the root as fid 0, with each value naming the path of its fid
+    fn table() -> FidTable<&'static str> {
+        let fids = FidTable::new();
+        fids.attach(0, "/").unwrap();
+        fids
+    }
+
+    #[test]
+    fn newfids_in_use_are_refused() {
+        let fids = table();
+        assert_eq!(fids.attach(0, "/").unwrap_err(), FidError::DuplicateFid);
+
+        fids.bind(0, 1, "/a").unwrap();
+        assert_eq!(fids.walk(0, 1).unwrap_err(), FidError::DuplicateFid);
+        assert_eq!(fids.bind(0, 1, "/b").unwrap_err(), FidError::DuplicateFid);
+        assert_eq!(*fids.get(1).unwrap(), "/a");
+
+        // walking a fid onto itself is how a client moves it
+        assert_eq!(*fids.walk(1, 1).unwrap(), "/a");
+        fids.bind(1, 1, "/a/b").unwrap();
+        assert_eq!(*fids.get(1).unwrap(), "/a/b");
+    }
+
+    #[test]
+    fn partial_walks_bind_nothing() {
+        let fids = table();
+        // a walk that stops short never gets to bind, so newfid stays free
+        assert_eq!(*fids.walk(0, 1).unwrap(), "/");
+        assert_eq!(fids.get(1).unwrap_err(), FidError::UnknownFid);
+        assert_eq!(fids.ids(), vec![0]);
+
+        assert_eq!(fids.walk(2, 3).unwrap_err(), FidError::UnknownFid);
+        assert_eq!(fids.bind(2, 3, "/x").unwrap_err(), FidError::UnknownFid);
+        assert_eq!(fids.get(3).unwrap_err(), FidError::UnknownFid);
+    }
+
+    #[test]
+    fn open_fids_cannot_be_walked_or_opened_again() {
+        let fids = table();
+        fids.bind(0, 1, "/file").unwrap();
+        assert_eq!(fids.get_open(1).unwrap_err(), FidError::Botch);
+
+        fids.opening(1).unwrap();
+        fids.opened(1, OpenMode::Read.into(), "/file (open)")
+            .unwrap();
+        let (value, mode) = fids.get_open(1).unwrap();
+        assert_eq!((*value, mode), ("/file (open)", OpenMode::Read.into()));
+
+        assert_eq!(fids.walk(1, 2).unwrap_err(), FidError::CloneOpenFid);
+        assert_eq!(fids.walk(1, 1).unwrap_err(), FidError::CloneOpenFid);
+        assert_eq!(fids.opening(1).unwrap_err(), FidError::Botch);
+        assert_eq!(
+            fids.opened(1, OpenMode::Write.into(), "/file").unwrap_err(),
+            FidError::Botch
+        );
+
+        // replacing the value, as a rename does, keeps it open
+        fids.replace(1, "/renamed").unwrap();
+        assert_eq!(fids.get_open(1).unwrap().1, FlagSet::from(OpenMode::Read));
+    }
+
+    #[test]
+    fn clunk_frees_the_fid() {
+        let fids = table();
+        fids.bind(0, 1, "/file").unwrap();
+        fids.opened(1, OpenMode::Write.into(), "/file").unwrap();
+
+        // whatever the handler makes of the value, the fid is gone
+        assert_eq!(*fids.clunk(1).unwrap(), "/file");
+        assert_eq!(fids.get(1).unwrap_err(), FidError::UnknownFid);
+        assert_eq!(fids.clunk(1).unwrap_err(), FidError::UnknownFid);
+
+        // and may be used again
+        fids.bind(0, 1, "/other").unwrap();
+        assert_eq!(fids.get_open(1).unwrap_err(), FidError::Botch);
+    }
+}
@@ -1,145 +1,389 @@
-use futures::{SinkExt, StreamExt};
-use std::sync::Arc;
+use futures::{
+    future::{AbortHandle, Abortable},
+    stream::FuturesUnordered,
+    SinkExt, StreamExt,
+};
+use std::{collections::HashMap, sync::Arc};
 use stowage_proto::{
-    Message, MessageCodec, Rerror, Rversion, Tattach, Tauth, Tclunk, Tcreate, Tflush, Topen, Tread,
-    Tremove, Tstat, Tversion, Twalk, Twrite, Twstat,
+    consts::{
+        errno::{EIO, EOPNOTSUPP},
+        P9_DEFAULT_MSIZE, P9_IOHDRSZ, P9_MAXWELEM,
+    },
+    Dialect, Message, MessageCodec, Rflush, Rlerror, Rversion, TaggedMessage, Tattach, Tauth,
+    Tclunk, Tcreate, Tfsync, Tgetattr, Tgetlock, Tlcreate, Tlink, Tlock, Tlopen, Tmkdir, Tmknod,
+    Topen, Tread, Treaddir, Treadlink, Tremove, Trename, Trenameat, Tsetattr, Tstat, Tstatfs,
+    Tsymlink, Tunlinkat, Tversion, Twalk, Twrite, Twstat, Txattrcreate, Txattrwalk,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Framed;
 
-pub trait MessageHandler {
-    fn version(&self, message: &Tversion) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rversion(Rversion {
-                msize: message.msize.min(8192),
-                version: match message.version.as_ref() {
-                    "9P2000" => message.version.to_string(),
-                    _ => "unknown".to_string(),
-                },
-            })
+pub use auth::{Authenticator, Conversation, SharedSecret};
+pub use fid::{FidError, FidTable};
+pub use peer::{Peer, PeerPolicy};
+pub use session::Session;
+
+mod auth;
+mod fid;
+mod peer;
+mod session;
+
+/// Answer a Tversion with the best dialect out of `dialects` that the client asked for
+///
+/// An exact match on the client's version string wins, otherwise any "9P2000" variant falls
+/// back to the base protocol as the spec allows.
+#[must_use]
+pub fn negotiate_version(message: &Tversion, dialects: &[Dialect]) -> Rversion {
+    let version = match Dialect::from_version(&message.version) {
+        Some(dialect) if dialects.contains(&dialect) => dialect.as_str(),
+        _ if message.version.starts_with("9P2000") && dialects.contains(&Dialect::Base) => {
+            Dialect::Base.as_str()
         }
+        _ => "unknown",
+    };
+
+    Rversion {
+        msize: message.msize.min(P9_DEFAULT_MSIZE),
+        version: version.to_string(),
     }
+}
 
-    fn auth(&self, _msg: &Tauth) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+/// A request being handled
+struct InFlight {
+    handle: AbortHandle,
+    /// whether a Tflush may cancel it
+    abortable: bool,
+    /// the tags of the Tflushes waiting for it to be answered
+    flushes: Vec<u16>,
+}
+
+/// Whether `message` can be cancelled midway without leaving anything changed
+///
+/// Handlers may have handed the work to another thread that carries on regardless, so only
+/// requests that change nothing are abandoned when flushed.
+fn abortable(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::Tread(_)
+            | Message::Tstat(_)
+            | Message::Tstatfs(_)
+            | Message::Treadlink(_)
+            | Message::Tgetattr(_)
+            | Message::Treaddir(_)
+            | Message::Tgetlock(_)
+    )
+}
+
+fn not_supported_linux() -> Message {
+    Message::Rlerror(Rlerror { ecode: EOPNOTSUPP })
+}
+
+/// A filesystem served over 9P
+///
+/// Handlers are shared by every connection and their futures are `Send`, so a server can run
+/// each connection on its own task whatever the handler is.
+pub trait MessageHandler: Send + Sync {
+    /// Whatever the handler keeps for each fid of a session
+    type Fid: Send + Sync;
+
+    /// The protocol dialects this handler is able to speak
+    fn dialects(&self) -> &[Dialect] {
+        &[Dialect::Base]
     }
 
-    fn attach(&self, __msg: &Tattach) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn version(
+        &self,
+        _session: &Session<Self::Fid>,
+        message: &Tversion,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::Rversion(negotiate_version(message, self.dialects())) }
     }
 
-    fn flush(&self, __msg: &Tflush) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn auth(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tauth,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn walk(&self, _msg: &Twalk) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn attach(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tattach,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn open(&self, _msg: &Topen) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn walk(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Twalk,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn create(&self, _msg: &Tcreate) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn open(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Topen,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn read(&self, _msg: &Tread) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn create(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tcreate,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn write(&self, _msg: &Twrite) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn read(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tread,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn clunk(&self, _msg: &Tclunk) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn write(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Twrite,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn remove(&self, _msg: &Tremove) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn clunk(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tclunk,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn stat(&self, _msg: &Tstat) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn remove(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tremove,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
     }
 
-    fn wstat(&self, _msg: &Twstat) -> impl std::future::Future<Output = Message> {
-        async {
-            Message::Rerror(Rerror {
-                ename: "Operation not supported".to_string(),
-            })
-        }
+    fn stat(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tstat,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
+    }
+
+    fn wstat(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Twstat,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { Message::error("Operation not supported".to_string()) }
+    }
+
+    // 9P2000.L requests, answered with EOPNOTSUPP unless overridden
+
+    fn statfs(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tstatfs,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn lopen(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tlopen,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn lcreate(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tlcreate,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn symlink(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tsymlink,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn mknod(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tmknod,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn rename(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Trename,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn readlink(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Treadlink,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn getattr(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tgetattr,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn setattr(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tsetattr,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn xattrwalk(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Txattrwalk,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn xattrcreate(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Txattrcreate,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn readdir(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Treaddir,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn fsync(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tfsync,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn lock(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tlock,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn getlock(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tgetlock,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn link(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tlink,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn mkdir(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tmkdir,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn renameat(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Trenameat,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
+    }
+
+    fn unlinkat(
+        &self,
+        _session: &Session<Self::Fid>,
+        _msg: &Tunlinkat,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        async { not_supported_linux() }
     }
 
     /// Dispatcher method that routes messages to specific handlers
-    fn handle_message(&self, message: &Message) -> impl std::future::Future<Output = Message> {
-        println!("message: {message:?}");
+    fn handle_message(
+        &self,
+        session: &Session<Self::Fid>,
+        message: &Message,
+    ) -> impl std::future::Future<Output = Message> + Send {
+        tracing::debug!("message: {message:?}");
         async {
             let m = message.clone();
             match m {
-                Message::Tversion(msg) => self.version(&msg).await,
-                Message::Tauth(msg) => self.auth(&msg).await,
-                Message::Tattach(msg) => self.attach(&msg).await,
-                Message::Tflush(msg) => self.flush(&msg).await,
-                Message::Twalk(msg) => self.walk(&msg).await,
-                Message::Topen(msg) => self.open(&msg).await,
-                Message::Tcreate(msg) => self.create(&msg).await,
-                Message::Tread(msg) => self.read(&msg).await,
-                Message::Twrite(msg) => self.write(&msg).await,
-                Message::Tclunk(msg) => self.clunk(&msg).await,
-                Message::Tremove(msg) => self.remove(&msg).await,
-                Message::Tstat(msg) => self.stat(&msg).await,
-                Message::Twstat(msg) => self.wstat(&msg).await,
+                Message::Tversion(msg) => self.version(session, &msg).await,
+                Message::Tauth(msg) => self.auth(session, &msg).await,
+                Message::Tattach(msg) => self.attach(session, &msg).await,
+                Message::Twalk(msg) => self.walk(session, &msg).await,
+                Message::Topen(msg) => self.open(session, &msg).await,
+                Message::Tcreate(msg) => self.create(session, &msg).await,
+                Message::Tread(msg) => self.read(session, &msg).await,
+                Message::Twrite(msg) => self.write(session, &msg).await,
+                Message::Tclunk(msg) => self.clunk(session, &msg).await,
+                Message::Tremove(msg) => self.remove(session, &msg).await,
+                Message::Tstat(msg) => self.stat(session, &msg).await,
+                Message::Twstat(msg) => self.wstat(session, &msg).await,
+                Message::Tstatfs(msg) => self.statfs(session, &msg).await,
+                Message::Tlopen(msg) => self.lopen(session, &msg).await,
+                Message::Tlcreate(msg) => self.lcreate(session, &msg).await,
+                Message::Tsymlink(msg) => self.symlink(session, &msg).await,
+                Message::Tmknod(msg) => self.mknod(session, &msg).await,
+                Message::Trename(msg) => self.rename(session, &msg).await,
+                Message::Treadlink(msg) => self.readlink(session, &msg).await,
+                Message::Tgetattr(msg) => self.getattr(session, &msg).await,
+                Message::Tsetattr(msg) => self.setattr(session, &msg).await,
+                Message::Txattrwalk(msg) => self.xattrwalk(session, &msg).await,
+                Message::Txattrcreate(msg) => self.xattrcreate(session, &msg).await,
+                Message::Treaddir(msg) => self.readdir(session, &msg).await,
+                Message::Tfsync(msg) => self.fsync(session, &msg).await,
+                Message::Tlock(msg) => self.lock(session, &msg).await,
+                Message::Tgetlock(msg) => self.getlock(session, &msg).await,
+                Message::Tlink(msg) => self.link(session, &msg).await,
+                Message::Tmkdir(msg) => self.mkdir(session, &msg).await,
+                Message::Trenameat(msg) => self.renameat(session, &msg).await,
+                Message::Tunlinkat(msg) => self.unlinkat(session, &msg).await,
 
                 // reply messages should not be received by the handler
-                _ => Message::Rerror(Rerror {
-                    ename: "Unexpected message type".to_string(),
-                }),
+                _ => Message::error("Unexpected message type".to_string()),
             }
         }
     }
@@ -152,6 +396,9 @@ where
 {
     connection: Framed<T, MessageCodec>,
     handler: Arc<F>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    peer: Option<Peer>,
+    peer_policy: PeerPolicy,
 }
 
 impl<T, H> Plan9<T, H>
@@ -166,25 +413,410 @@ where
         Self {
             connection,
             handler,
+            authenticator: None,
+            peer: None,
+            peer_policy: PeerPolicy::default(),
         }
     }
 
+    /// Require every attach to be authenticated by `authenticator`
+    ///
+    /// Tauth and the afids it makes are then handled by the service, the handler never sees
+    /// them.
+    #[must_use]
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Tell the service who is connected, as the transport vouches for it
+    ///
+    /// The peer is the session's and attaches are held to `policy` for it.
+    #[must_use]
+    pub fn with_peer(mut self, peer: Peer, policy: PeerPolicy) -> Self {
+        self.peer = Some(peer);
+        self.peer_policy = policy;
+        self
+    }
+
+    /// Serve requests until the client disconnects
+    ///
+    /// Requests are handled concurrently, each reply is sent as soon as it is ready. A Tflush
+    /// cancels the request it names so that no reply to the old tag can follow the Rflush,
+    /// unless the request may already have changed something: those are left to finish and
+    /// answered before the Rflush. A Tversion cancels everything in flight before it is
+    /// answered.
+    ///
+    /// The connection gets its own `Session`, every fid left in it is clunked when the client
+    /// goes away.
+    ///
     /// # Errors
     /// - failure sending a message to the server
-    pub async fn run(mut self) -> stowage_proto::error::Result<()> {
-        while let Some(message_result) = self.connection.next().await {
-            match message_result {
-                Ok(request) => {
-                    let response = self.handler.handle_message(&request.message).await;
-                    let tagged = response.to_tagged(request.tag);
-                    self.connection.send(tagged).await?;
+    pub async fn run(self) -> stowage_proto::error::Result<()> {
+        let Self {
+            mut connection,
+            handler,
+            authenticator,
+            peer,
+            peer_policy,
+        } = self;
+        let session = Session::new().with_peer(peer);
+
+        let result = Self::serve(
+            &mut connection,
+            &handler,
+            authenticator.as_deref(),
+            peer_policy,
+            &session,
+        )
+        .await;
+        Self::reset_session(&handler, &session).await;
+        result
+    }
+
+    async fn serve(
+        connection: &mut Framed<T, MessageCodec>,
+        handler: &H,
+        authenticator: Option<&dyn Authenticator>,
+        peer_policy: PeerPolicy,
+        session: &Session<H::Fid>,
+    ) -> stowage_proto::error::Result<()> {
+        let mut pending = FuturesUnordered::new();
+        let mut in_flight: HashMap<u16, InFlight> = HashMap::new();
+
+        loop {
+            tokio::select! {
+                Some(result) = pending.next(), if !pending.is_empty() => {
+                    // aborted requests were already dropped from the table, their tag may be
+                    // in use again
+                    if let Ok((tag, response)) = result {
+                        let flushes = in_flight.remove(&tag).map(|request| request.flushes);
+                        Self::reply(connection, tag, response).await?;
+                        for flush in flushes.into_iter().flatten() {
+                            Self::reply(connection, flush, Message::Rflush(Rflush)).await?;
+                        }
+                    }
                 }
-                Err(e) => {
-                    return Err(e);
+                request = connection.next() => {
+                    let Some(request) = request else {
+                        break;
+                    };
+                    let TaggedMessage { tag, message } = request?;
+
+                    match Self::limit_request(connection.codec(), message) {
+                        Err(ename) => {
+                            Self::reply(connection, tag, Message::error(ename)).await?;
+                        }
+                        Ok(Message::Tversion(tversion)) => {
+                            for (_, request) in in_flight.drain() {
+                                request.handle.abort();
+                            }
+                            pending.clear();
+                            Self::reset_session(handler, session).await;
+
+                            let message = Message::Tversion(tversion);
+                            let response = handler.handle_message(session, &message).await;
+                            if let Message::Rversion(rversion) = &response {
+                                // the reply itself is dialect independent, everything after
+                                // it is not
+                                let dialect = Dialect::from_version(&rversion.version)
+                                    .unwrap_or_default();
+                                session.set_version(rversion.msize, dialect);
+                                let codec = connection.codec_mut();
+                                codec.set_dialect(dialect);
+                                codec.set_msize(rversion.msize);
+                            }
+                            Self::reply(connection, tag, response).await?;
+                        }
+                        Ok(Message::Tflush(tflush)) => match in_flight.get_mut(&tflush.oldtag) {
+                            // answered along with the request once it is done
+                            Some(request) if !request.abortable => request.flushes.push(tag),
+                            Some(_) => {
+                                if let Some(request) = in_flight.remove(&tflush.oldtag) {
+                                    request.handle.abort();
+                                }
+                                Self::reply(connection, tag, Message::Rflush(Rflush)).await?;
+                            }
+                            None => {
+                                Self::reply(connection, tag, Message::Rflush(Rflush)).await?;
+                            }
+                        },
+                        Ok(_) if in_flight.contains_key(&tag) => {
+                            let response = Message::error("duplicate tag".to_string());
+                            Self::reply(connection, tag, response).await?;
+                        }
+                        Ok(message) => {
+                            let (handle, registration) = AbortHandle::new_pair();
+                            in_flight.insert(
+                                tag,
+                                InFlight {
+                                    handle,
+                                    abortable: abortable(&message),
+                                    flushes: Vec::new(),
+                                },
+                            );
+                            pending.push(Abortable::new(
+                                Self::dispatch(
+                                    handler,
+                                    authenticator,
+                                    peer_policy,
+                                    session,
+                                    tag,
+                                    message,
+                                ),
+                                registration,
+                            ));
+                        }
+                    }
                 }
             }
         }
 
         Ok(())
     }
+
+    async fn dispatch(
+        handler: &H,
+        authenticator: Option<&dyn Authenticator>,
+        peer_policy: PeerPolicy,
+        session: &Session<H::Fid>,
+        tag: u16,
+        mut message: Message,
+    ) -> (u16, Message) {
+        if let Some(peer) = session.peer() {
+            if let Some(refusal) = peer::apply(peer, peer_policy, &mut message) {
+                return (tag, refusal);
+            }
+        }
+
+        let intercepted = authenticator
+            .and_then(|authenticator| auth::intercept(authenticator, session, &message));
+        let response = match intercepted {
+            Some(response) => response,
+            None => handler.handle_message(session, &message).await,
+        };
+        if let (Message::Tattach(tattach), Message::Rattach(_)) = (&message, &response) {
+            session.set_uname(Some(tattach.uname.clone()));
+        }
+        (tag, response)
+    }
+
+    /// Clunk every fid of the session and forget who attached
+    async fn reset_session(handler: &H, session: &Session<H::Fid>) {
+        for fid in session.fids().ids() {
+            handler.clunk(session, &Tclunk { fid }).await;
+        }
+        // whatever the handler did not clunk itself is dropped regardless
+        session.fids().clear();
+        session.auths().clear();
+        session.set_uname(None);
+    }
+
+    async fn reply(
+        connection: &mut Framed<T, MessageCodec>,
+        tag: u16,
+        response: Message,
+    ) -> stowage_proto::error::Result<()> {
+        // 9P2000.L has no Rerror, pass the errno along in an Rlerror instead
+        let response = match response {
+            Message::Rerror(rerror) if connection.codec().dialect() == Dialect::Linux => {
+                Message::Rlerror(Rlerror {
+                    ecode: if rerror.errno == 0 { EIO } else { rerror.errno },
+                })
+            }
+            response => response,
+        };
+        connection.send(response.to_tagged(tag)).await
+    }
+
+    /// Keep i/o requests within the negotiated msize before they reach the handler
+    ///
+    /// Read counts are clamped so the reply fits in a message, and writes whose data could not
+    /// have fit are refused.
+    fn limit_request(codec: &MessageCodec, message: Message) -> Result<Message, String> {
+        let iounit = codec.msize().saturating_sub(P9_IOHDRSZ);
+
+        match message {
+            Message::Tversion(tversion) if tversion.msize <= P9_IOHDRSZ => {
+                Err("msize too small".to_string())
+            }
+            Message::Tread(mut tread) => {
+                tread.count = tread.count.min(iounit);
+                Ok(Message::Tread(tread))
+            }
+            Message::Treaddir(mut treaddir) => {
+                treaddir.count = treaddir.count.min(iounit);
+                Ok(Message::Treaddir(treaddir))
+            }
+            Message::Twrite(twrite) if twrite.data.len() > iounit as usize => {
+                Err(format!("write count {} exceeds msize", twrite.data.len()))
+            }
+            Message::Twalk(twalk) if twalk.wnames.len() > P9_MAXWELEM => Err(format!(
+                "walk of {} names exceeds {P9_MAXWELEM}",
+                twalk.wnames.len()
+            )),
+            message => Ok(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use stowage_proto::{Qid, QidType, Rcreate, Tflush};
+    use tokio::{io::DuplexStream, sync::Notify};
+
+    /// Answers nothing but Tversion
+    struct Nothing;
+
+    impl MessageHandler for Nothing {
+        type Fid = ();
+    }
+
+    type Service = Plan9<DuplexStream, Nothing>;
+
+    /// Never finishes a read and finishes a create only once let through the gate
+    #[derive(Default)]
+    struct Gated {
+        gate: Notify,
+    }
+
+    impl MessageHandler for Gated {
+        type Fid = ();
+
+        async fn read(&self, _session: &Session<()>, _message: &Tread) -> Message {
+            std::future::pending().await
+        }
+
+        async fn create(&self, _session: &Session<()>, _message: &Tcreate) -> Message {
+            self.gate.notified().await;
+            Message::Rcreate(Rcreate {
+                qid: Qid {
+                    qtype: QidType::File.into(),
+                    version: 0,
+                    path: 1,
+                },
+                iounit: 0,
+            })
+        }
+    }
+
+    /// A client connected to a server for `handler`
+    fn serve(handler: Arc<Gated>) -> Framed<DuplexStream, MessageCodec> {
+        let (client, server) = tokio::io::duplex(8192);
+        tokio::spawn(Plan9::new(server, handler).run());
+        Framed::new(client, MessageCodec::new())
+    }
+
+    async fn send(client: &mut Framed<DuplexStream, MessageCodec>, tag: u16, message: Message) {
+        client.send(message.to_tagged(tag)).await.unwrap();
+    }
+
+    async fn receive(client: &mut Framed<DuplexStream, MessageCodec>) -> TaggedMessage {
+        client.next().await.unwrap().unwrap()
+    }
+
+    fn tflush(oldtag: u16) -> Message {
+        Message::Tflush(Tflush { oldtag })
+    }
+
+    #[tokio::test]
+    async fn flushed_reads_are_answered_by_the_rflush_alone() {
+        let mut client = serve(Arc::default());
+
+        let tread = Tread {
+            fid: 1,
+            offset: 0,
+            count: 10,
+        };
+        send(&mut client, 1, Message::Tread(tread)).await;
+        send(&mut client, 2, tflush(1)).await;
+        let reply = receive(&mut client).await;
+        assert_eq!((reply.tag, reply.message), (2, Message::Rflush(Rflush)));
+
+        // the old tag is free again and nothing more is said about the read
+        send(&mut client, 1, Message::Tclunk(Tclunk { fid: 1 })).await;
+        let reply = receive(&mut client).await;
+        assert_eq!(reply.tag, 1);
+        assert!(matches!(reply.message, Message::Rerror(_)));
+    }
+
+    #[tokio::test]
+    async fn flushed_creates_are_answered_before_the_rflush() {
+        let handler = Arc::new(Gated::default());
+        let mut client = serve(handler.clone());
+
+        let tcreate = Tcreate {
+            fid: 1,
+            name: "file".to_string(),
+            perm: stowage_proto::FileMode::OwnerRead.into(),
+            mode: stowage_proto::OpenMode::Read.into(),
+            extension: String::new(),
+        };
+        send(&mut client, 1, Message::Tcreate(tcreate)).await;
+        send(&mut client, 2, tflush(1)).await;
+        send(&mut client, 3, tflush(1)).await;
+        let early = tokio::time::timeout(Duration::from_millis(50), client.next()).await;
+        assert!(early.is_err(), "answered a flush early: {early:?}");
+
+        handler.gate.notify_one();
+        let replies = [
+            receive(&mut client).await,
+            receive(&mut client).await,
+            receive(&mut client).await,
+        ];
+        assert_eq!(replies.each_ref().map(|reply| reply.tag), [1, 2, 3]);
+        assert!(matches!(replies[0].message, Message::Rcreate(_)));
+    }
+
+    #[tokio::test]
+    async fn flushes_of_unknown_tags_are_answered_at_once() {
+        let mut client = serve(Arc::default());
+        send(&mut client, 2, tflush(1)).await;
+        let reply = receive(&mut client).await;
+        assert_eq!((reply.tag, reply.message), (2, Message::Rflush(Rflush)));
+    }
+
+    #[test]
+    fn read_counts_are_clamped_to_the_msize() {
+        let codec = MessageCodec::with_msize(8192);
+        let iounit = 8192 - P9_IOHDRSZ;
+
+        for count in [u32::MAX, iounit + 1, iounit, 10] {
+            let tread = Message::Tread(Tread {
+                fid: 1,
+                offset: 0,
+                count,
+            });
+            let Ok(Message::Tread(tread)) = Service::limit_request(&codec, tread) else {
+                panic!("read of {count} refused");
+            };
+            assert_eq!(tread.count, count.min(iounit));
+
+            let treaddir = Message::Treaddir(Treaddir {
+                fid: 1,
+                offset: 0,
+                count,
+            });
+            let Ok(Message::Treaddir(treaddir)) = Service::limit_request(&codec, treaddir) else {
+                panic!("readdir of {count} refused");
+            };
+            assert_eq!(treaddir.count, count.min(iounit));
+        }
+    }
+
+    #[test]
+    fn walks_of_more_than_maxwelem_names_are_refused() {
+        let codec = MessageCodec::new();
+        let twalk = |names: usize| {
+            Message::Twalk(Twalk {
+                fid: 0,
+                newfid: 1,
+                wnames: vec!["a".to_string(); names],
+            })
+        };
+        assert!(Service::limit_request(&codec, twalk(P9_MAXWELEM)).is_ok());
+        assert!(Service::limit_request(&codec, twalk(P9_MAXWELEM + 1)).is_err());
+    }
 }
@@ -0,0 +1,67 @@
+use stowage_proto::{
+    consts::{errno::EACCES, P9_NONUNAME},
+    Message,
+};
+
+/// The user on the other end of a connection, as the transport vouches for it
+///
+/// That is the kernel for a unix socket and the client's certificate for TLS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    /// the uname the peer attaches as
+    pub name: String,
+    /// the uid of a local peer
+    pub uid: Option<u32>,
+    /// the gid of a local peer
+    pub gid: Option<u32>,
+}
+
+impl Peer {
+    /// Whether `uname` and `n_uname` of an attach name this peer
+    ///
+    /// Either may be left out, but whatever is given has to match. A uname may also be the
+    /// uid of a local peer.
+    #[must_use]
+    pub fn is(&self, uname: &str, n_uname: u32) -> bool {
+        let by_name = uname == self.name || self.uid.is_some_and(|uid| uname == uid.to_string());
+        match self.uid {
+            Some(uid) if n_uname != P9_NONUNAME => n_uname == uid && (uname.is_empty() || by_name),
+            _ => by_name,
+        }
+    }
+}
+
+/// What to make of the uname of an attach on a connection whose peer is known
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PeerPolicy {
+    /// attach as the peer, whatever uname the client gives
+    #[default]
+    Trust,
+    /// refuse attaches that name anyone but the peer
+    Verify,
+}
+
+/// Hold `message` to `policy` for `peer`, returning the refusal if it breaks it
+///
+/// Under `PeerPolicy::Trust` the names in a Tauth or Tattach are replaced with the peer's, so
+/// that the handler and any authenticator only ever see who is really connected.
+pub(crate) fn apply(peer: &Peer, policy: PeerPolicy, message: &mut Message) -> Option<Message> {
+    let (uname, n_uname) = match message {
+        Message::Tauth(tauth) => (&mut tauth.uname, &mut tauth.n_uname),
+        Message::Tattach(tattach) => (&mut tattach.uname, &mut tattach.n_uname),
+        _ => return None,
+    };
+
+    match policy {
+        PeerPolicy::Trust => {
+            uname.clone_from(&peer.name);
+            *n_uname = peer.uid.unwrap_or(P9_NONUNAME);
+            None
+        }
+        PeerPolicy::Verify if peer.is(uname, *n_uname) => None,
+        PeerPolicy::Verify => Some(Message::error_with_errno(
+            format!("{uname}: connected as {}", peer.name),
+            EACCES,
+        )),
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{auth::AuthFid, FidTable, Peer};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use stowage_proto::{consts::P9_DEFAULT_MSIZE, Dialect};
+
+/// State belonging to a single client connection
+///
+/// `Plan9::run` creates one session per connection and hands it to every `MessageHandler`
+/// call, so handlers never see another client's fids. `F` is whatever the handler keeps for
+/// each fid.
+pub struct Session<F> {
+    msize: Mutex<u32>,
+    dialect: Mutex<Dialect>,
+    uname: Mutex<Option<String>>,
+    peer: Option<Peer>,
+    fids: FidTable<F>,
+    /// the afids, kept apart from the handler's fids
+    auths: Mutex<HashMap<u32, Arc<AuthFid>>>,
+}
+
+impl<F> Session<F> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            msize: Mutex::new(P9_DEFAULT_MSIZE),
+            dialect: Mutex::new(Dialect::Base),
+            uname: Mutex::new(None),
+            peer: None,
+            fids: FidTable::new(),
+            auths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The msize agreed on by the last Tversion
+    ///
+    /// # Panics
+    /// - the session lock is poisoned
+    #[must_use]
+    pub fn msize(&self) -> u32 {
+        *self.msize.lock().unwrap()
+    }
+
+    /// The dialect agreed on by the last Tversion
+    ///
+    /// # Panics
+    /// - the session lock is poisoned
+    #[must_use]
+    pub fn dialect(&self) -> Dialect {
+        *self.dialect.lock().unwrap()
+    }
+
+    /// The user name given by the last successful Tattach
+    ///
+    /// # Panics
+    /// - the session lock is poisoned
+    #[must_use]
+    pub fn uname(&self) -> Option<String> {
+        self.uname.lock().unwrap().clone()
+    }
+
+    /// Who is connected, when the transport vouches for it
+    #[must_use]
+    pub fn peer(&self) -> Option<&Peer> {
+        self.peer.as_ref()
+    }
+
+    /// The fids this session has established
+    #[must_use]
+    pub fn fids(&self) -> &FidTable<F> {
+        &self.fids
+    }
+
+    pub(crate) fn with_peer(mut self, peer: Option<Peer>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub(crate) fn auths(&self) -> MutexGuard<'_, HashMap<u32, Arc<AuthFid>>> {
+        self.auths.lock().unwrap()
+    }
+
+    pub(crate) fn set_version(&self, msize: u32, dialect: Dialect) {
+        *self.msize.lock().unwrap() = msize;
+        *self.dialect.lock().unwrap() = dialect;
+    }
+
+    pub(crate) fn set_uname(&self, uname: Option<String>) {
+        *self.uname.lock().unwrap() = uname;
+    }
+}
+
+impl<F> Default for Session<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}